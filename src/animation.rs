@@ -0,0 +1,581 @@
+use crate::grid::GridResult;
+use crate::png_text;
+use anyhow::anyhow;
+use image::{AnimationDecoder, ImageDecoder, ImageFormat, Pixel, Rgba, RgbaImage};
+use std::fs;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+
+// Browsers show frames with no delay, or an unreasonably short one, for this
+// long instead. Many animations rely on it.
+const DEFAULT_DELAY: f64 = 0.1;
+const MIN_DELAY: f64 = 0.02;
+
+// However small the frames are
+const MAX_FRAMES: usize = 2000;
+
+/// One frame of an animation, composited onto the whole canvas.
+pub struct Frame {
+    pub image: RgbaImage,
+    /// Seconds the frame is shown for.
+    pub delay: f64,
+}
+
+impl Frame {
+    fn new(image: RgbaImage, delay: f64) -> Frame {
+        let delay = if delay < MIN_DELAY {
+            DEFAULT_DELAY
+        } else {
+            delay
+        };
+        Frame { image, delay }
+    }
+}
+
+/// Decodes the frames of an animated GIF, PNG or WebP file. Returns None for
+/// other files and for animations with a single frame. Long animations are
+/// cut short: decoding stops once the frames take up `max_bytes`, or there
+/// are a few thousand of them, but the first frame is always decoded.
+pub fn decode_frames(contents: &[u8], max_bytes: usize) -> GridResult<Option<Vec<Frame>>> {
+    let frames = frames(contents, max_bytes)?;
+    if frames.len() > 1 {
+        Ok(Some(frames))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Decodes only the first frame of an animated GIF, PNG or WebP file, which
+/// is what the image crate cannot do for animated WebP files. Returns None
+/// for other files.
+pub fn decode_first_frame(contents: &[u8]) -> GridResult<Option<RgbaImage>> {
+    // A budget of nothing still decodes the first frame
+    Ok(frames(contents, 0)?
+        .into_iter()
+        .next()
+        .map(|frame| frame.image))
+}
+
+fn frames(contents: &[u8], max_bytes: usize) -> GridResult<Vec<Frame>> {
+    match image::guess_format(contents) {
+        Ok(ImageFormat::GIF) => gif_frames(contents, max_bytes),
+        Ok(ImageFormat::PNG) => apng_frames(contents, max_bytes),
+        Ok(ImageFormat::WEBP) => webp_frames(contents, max_bytes),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Whether the file's header says it may be animated, without reading the
+/// rest of it. GIF files may always be.
+pub fn may_be_animated(file: &Path) -> bool {
+    let mut file = match fs::File::open(file) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    let mut header = Vec::new();
+    if (&mut file).take(21).read_to_end(&mut header).is_err() {
+        return false;
+    }
+    if header.starts_with(b"GIF8") {
+        true
+    } else if header.starts_with(&png_text::SIGNATURE) {
+        png_has_animation(&mut file).unwrap_or(false)
+    } else {
+        // The extended header of a WebP file flags animations
+        header.len() == 21
+            && &header[0..4] == b"RIFF"
+            && &header[8..16] == b"WEBPVP8X"
+            && header[20] & 0x02 != 0
+    }
+}
+
+// APNG files have an animation control chunk before the image data
+fn png_has_animation(file: &mut fs::File) -> std::io::Result<bool> {
+    file.seek(SeekFrom::Start(png_text::SIGNATURE.len() as u64))?;
+    loop {
+        let mut chunk = [0; 8];
+        file.read_exact(&mut chunk)?;
+        match &chunk[4..] {
+            b"acTL" => return Ok(true),
+            b"IDAT" | b"IEND" => return Ok(false),
+            _ => {}
+        }
+        // The data and the checksum
+        let length = be32(&chunk, 0) as i64;
+        file.seek(SeekFrom::Current(length + 4))?;
+    }
+}
+
+// How many frames of the size fit in `max_bytes`
+fn max_frames(width: u32, height: u32, max_bytes: usize) -> usize {
+    let frame_bytes = (width as usize * height as usize * 4).max(1);
+    (max_bytes / frame_bytes).clamp(1, MAX_FRAMES)
+}
+
+fn gif_frames(contents: &[u8], max_bytes: usize) -> GridResult<Vec<Frame>> {
+    let decoder = image::gif::Decoder::new(Cursor::new(contents))?;
+    let (width, height) = decoder.dimensions();
+    let frames = decoder
+        .into_frames()
+        .take(max_frames(width as u32, height as u32, max_bytes))
+        .collect::<image::ImageResult<Vec<_>>>()?;
+    Ok(frames
+        .into_iter()
+        .map(|frame| {
+            let delay = frame.delay();
+            let delay = *delay.numer() as f64 / *delay.denom() as f64 / 1000.0;
+            Frame::new(frame.into_buffer(), delay)
+        })
+        .collect())
+}
+
+// Where a frame goes on the canvas and what happens to it afterwards. The
+// disposal and blending operations are numbered as in the APNG specification,
+// which animated WebP shares a subset of.
+struct Region {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    delay: f64,
+    // 0 leaves the frame, 1 clears it and 2 restores what was there before
+    dispose: u8,
+    // 0 replaces what is below the frame, 1 blends over it
+    blend: u8,
+}
+
+// Composites frames onto a canvas the way the animation formats describe.
+struct Canvas {
+    image: RgbaImage,
+    frames: Vec<Frame>,
+    max_frames: usize,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32, max_bytes: usize) -> Canvas {
+        Canvas {
+            image: RgbaImage::new(width, height),
+            frames: Vec::new(),
+            max_frames: max_frames(width, height, max_bytes),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.frames.len() >= self.max_frames
+    }
+
+    fn add(&mut self, region: &Region, image: &RgbaImage) {
+        let saved = if region.dispose == 2 {
+            Some(self.image.clone())
+        } else {
+            None
+        };
+        let (width, height) = self.image.dimensions();
+        for (x, y, pixel) in image.enumerate_pixels() {
+            // Parts of the frame outside the canvas are left out
+            let (x, y) = match (region.x.checked_add(x), region.y.checked_add(y)) {
+                (Some(x), Some(y)) if x < width && y < height => (x, y),
+                _ => continue,
+            };
+            if region.blend == 0 {
+                self.image.put_pixel(x, y, *pixel);
+            } else {
+                self.image.get_pixel_mut(x, y).blend(pixel);
+            }
+        }
+        self.frames
+            .push(Frame::new(self.image.clone(), region.delay));
+        match saved {
+            Some(saved) => self.image = saved,
+            None if region.dispose == 1 => {
+                let end = |start: u32, size: u32, canvas: u32| {
+                    start
+                        .checked_add(size)
+                        .map_or(canvas, |end| end.min(canvas))
+                };
+                for y in region.y..end(region.y, region.height, height) {
+                    for x in region.x..end(region.x, region.width, width) {
+                        self.image.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn le24(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], 0])
+}
+
+// The image crate only decodes the default image of an APNG. Each frame is
+// turned into a PNG of its own, with the header and palette of the file, and
+// decoded by itself.
+fn apng_frames(contents: &[u8], max_bytes: usize) -> GridResult<Vec<Frame>> {
+    let chunks = png_text::chunks(contents);
+    if !chunks.iter().any(|chunk| &chunk.kind == b"acTL") {
+        return Ok(Vec::new());
+    }
+    let header = match chunks.first() {
+        Some(chunk) if &chunk.kind == b"IHDR" && chunk.data.len() == 13 => chunk.data,
+        _ => return Err(anyhow!("PNG header missing")),
+    };
+    let shared: Vec<&png_text::Chunk> = chunks
+        .iter()
+        .filter(|chunk| &chunk.kind == b"PLTE" || &chunk.kind == b"tRNS")
+        .collect();
+    let mut canvas = Canvas::new(be32(header, 0), be32(header, 4), max_bytes);
+
+    // The default image is only part of the animation when a frame control
+    // chunk comes before it.
+    let mut region: Option<Region> = None;
+    let mut data = Vec::new();
+    for chunk in chunks.iter() {
+        match &chunk.kind {
+            b"fcTL" | b"IEND" => {
+                if let Some(region) = region.take() {
+                    let image = decode_apng_frame(header, &shared, &region, &data)?;
+                    canvas.add(&region, &image);
+                }
+                if canvas.is_full() {
+                    break;
+                }
+                data.clear();
+                if &chunk.kind == b"fcTL" {
+                    region = Some(apng_region(chunk.data, canvas.frames.is_empty())?);
+                }
+            }
+            b"IDAT" => data.extend_from_slice(chunk.data),
+            // Frame data starts with a sequence number
+            b"fdAT" if chunk.data.len() >= 4 => data.extend_from_slice(&chunk.data[4..]),
+            _ => {}
+        }
+    }
+    Ok(canvas.frames)
+}
+
+fn apng_region(data: &[u8], first: bool) -> GridResult<Region> {
+    if data.len() < 26 {
+        return Err(anyhow!("Truncated APNG frame control"));
+    }
+    let numerator = u16::from_be_bytes([data[20], data[21]]) as f64;
+    let denominator = match u16::from_be_bytes([data[22], data[23]]) {
+        0 => 100.0,
+        denominator => denominator as f64,
+    };
+    Ok(Region {
+        width: be32(data, 4),
+        height: be32(data, 8),
+        x: be32(data, 12),
+        y: be32(data, 16),
+        delay: numerator / denominator,
+        // There is nothing to restore before the first frame
+        dispose: if first && data[24] == 2 { 1 } else { data[24] },
+        blend: data[25],
+    })
+}
+
+fn decode_apng_frame(
+    header: &[u8],
+    shared: &[&png_text::Chunk],
+    region: &Region,
+    data: &[u8],
+) -> GridResult<RgbaImage> {
+    let mut png = png_text::SIGNATURE.to_vec();
+    let mut frame_header = header.to_vec();
+    frame_header[0..4].copy_from_slice(&region.width.to_be_bytes());
+    frame_header[4..8].copy_from_slice(&region.height.to_be_bytes());
+    png_text::write_chunk(&mut png, b"IHDR", &frame_header);
+    for chunk in shared {
+        png_text::write_chunk(&mut png, &chunk.kind, chunk.data);
+    }
+    png_text::write_chunk(&mut png, b"IDAT", data);
+    png_text::write_chunk(&mut png, b"IEND", &[]);
+    Ok(image::load_from_memory_with_format(&png, ImageFormat::PNG)?.to_rgba())
+}
+
+// Animated WebP files are a RIFF container with a frame chunk per frame. The
+// frames are decoded with the image crate's VP8 decoder, which like its WebP
+// support as a whole only produces the brightness of each pixel, so they play
+// in grayscale. There is no decoder for lossless frames.
+fn webp_frames(contents: &[u8], max_bytes: usize) -> GridResult<Vec<Frame>> {
+    if contents.len() < 12 || &contents[0..4] != b"RIFF" || &contents[8..12] != b"WEBP" {
+        return Err(anyhow!("Invalid WebP file"));
+    }
+    let mut canvas: Option<Canvas> = None;
+    for (kind, data) in riff_chunks(&contents[12..]) {
+        match &kind {
+            b"VP8X" if data.len() >= 10 => {
+                // Only animations have frame chunks
+                if data[0] & 0x02 == 0 {
+                    return Ok(Vec::new());
+                }
+                canvas = Some(Canvas::new(1 + le24(data, 4), 1 + le24(data, 7), max_bytes));
+            }
+            b"ANMF" if data.len() >= 16 => {
+                let canvas = canvas
+                    .as_mut()
+                    .ok_or_else(|| anyhow!("WebP frame before the canvas size"))?;
+                let region = Region {
+                    x: 2 * le24(data, 0),
+                    y: 2 * le24(data, 3),
+                    width: 1 + le24(data, 6),
+                    height: 1 + le24(data, 9),
+                    delay: le24(data, 12) as f64 / 1000.0,
+                    dispose: data[15] & 0x01,
+                    blend: if data[15] & 0x02 == 0 { 1 } else { 0 },
+                };
+                let image = decode_webp_frame(&data[16..])?;
+                canvas.add(&region, &image);
+                if canvas.is_full() {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(canvas.map_or_else(Vec::new, |canvas| canvas.frames))
+}
+
+fn riff_chunks(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    while data.len() >= 8 {
+        let kind = [data[0], data[1], data[2], data[3]];
+        let length = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        let end = match 8usize.checked_add(length) {
+            Some(end) if end <= data.len() => end,
+            _ => break,
+        };
+        chunks.push((kind, &data[8..end]));
+        // Chunks are padded to an even length
+        data = &data[(end + length % 2).min(data.len())..];
+    }
+    chunks
+}
+
+fn decode_webp_frame(data: &[u8]) -> GridResult<RgbaImage> {
+    for (kind, data) in riff_chunks(data) {
+        match &kind {
+            b"VP8 " => {
+                let mut decoder = image::webp::vp8::VP8Decoder::new(Cursor::new(data));
+                let frame = decoder.decode_frame()?;
+                let (width, height) = (frame.width as u32, frame.height as u32);
+                return Ok(RgbaImage::from_fn(width, height, |x, y| {
+                    let luma = frame.ybuf[(y * width + x) as usize];
+                    Rgba([luma, luma, luma, 255])
+                }));
+            }
+            b"VP8L" => return Err(anyhow!("Lossless WebP frames are not supported")),
+            _ => {}
+        }
+    }
+    Err(anyhow!("WebP frame without image data"))
+}
+
+/// Which animated tiles are played back. The fullscreen tile always is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Animate {
+    Fullscreen,
+    /// The selected tile in the grid as well.
+    Selected,
+    /// The tile under the mouse in the grid as well.
+    Hovered,
+}
+
+impl FromStr for Animate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> GridResult<Animate> {
+        match s {
+            "fullscreen" => Ok(Animate::Fullscreen),
+            "selected" => Ok(Animate::Selected),
+            "hovered" => Ok(Animate::Hovered),
+            _ => Err(anyhow!("Unknown animation mode '{}'", s)),
+        }
+    }
+}
+
+/// Keeps time for an animated tile that is being played.
+#[derive(Clone, Debug)]
+pub struct Playback {
+    pub tile: usize,
+    pub frame: usize,
+    elapsed: f64,
+}
+
+impl Playback {
+    pub fn new(tile: usize) -> Playback {
+        Playback {
+            tile,
+            frame: 0,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Advances the clock by `dt` seconds, given the number of frames and the
+    /// delay of each. Returns true when it moved on to another frame.
+    pub fn update<F>(&mut self, dt: f64, count: usize, delay: F) -> bool
+    where
+        F: Fn(usize) -> f64,
+    {
+        if count <= 1 {
+            return false;
+        }
+        let mut changed = false;
+        self.elapsed += dt;
+        loop {
+            let delay = delay(self.frame).max(MIN_DELAY);
+            if self.elapsed < delay {
+                break;
+            }
+            self.elapsed -= delay;
+            self.frame = (self.frame + 1) % count;
+            changed = true;
+        }
+        changed
+    }
+
+    /// Moves by `steps` frames, wrapping around, and starts the frame over.
+    pub fn step(&mut self, steps: isize, count: usize) {
+        if count > 0 {
+            self.frame = (self.frame as isize + steps).rem_euclid(count as isize) as usize;
+        }
+        self.elapsed = 0.0;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // A 1x1 lossy frame
+    const VP8: &[u8] = &[
+        0x30, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x01, 0x00, 0x01, 0x00, 0x0e, 0xc0, 0xfe, 0x25, 0xa4,
+        0x00, 0x03, 0x70, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    fn riff_chunk(riff: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        riff.extend_from_slice(kind);
+        riff.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        riff.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            riff.push(0);
+        }
+    }
+
+    /// An animated WebP file with two 1x1 frames of 100 ms.
+    pub(crate) fn animated_webp() -> Vec<u8> {
+        let mut chunks = Vec::new();
+        riff_chunk(&mut chunks, b"VP8X", &[0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        riff_chunk(&mut chunks, b"ANIM", &[0, 0, 0, 0, 0, 0]);
+        for _ in 0..2 {
+            let mut frame = vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 100, 0, 0, 0];
+            riff_chunk(&mut frame, b"VP8 ", VP8);
+            riff_chunk(&mut chunks, b"ANMF", &frame);
+        }
+        let mut webp = b"RIFF".to_vec();
+        webp.extend_from_slice(&(4 + chunks.len() as u32).to_le_bytes());
+        webp.extend_from_slice(b"WEBP");
+        webp.extend_from_slice(&chunks);
+        webp
+    }
+
+    fn animated(name: &str, contents: &[u8]) -> bool {
+        let path =
+            std::env::temp_dir().join(format!("image_grid_test_{}_{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        let animated = may_be_animated(&path);
+        fs::remove_file(&path).unwrap();
+        animated
+    }
+
+    fn png(chunks: &[&[u8; 4]]) -> Vec<u8> {
+        let mut png = png_text::SIGNATURE.to_vec();
+        png_text::write_chunk(&mut png, b"IHDR", &[0; 13]);
+        for kind in chunks {
+            png_text::write_chunk(&mut png, kind, &[0; 8]);
+        }
+        png
+    }
+
+    fn webp(flags: u8) -> Vec<u8> {
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0".to_vec();
+        webp.extend_from_slice(&[flags, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        webp
+    }
+
+    #[test]
+    fn header_tells_animations_apart() {
+        assert!(animated("gif", b"GIF89a\x01\0\x01\0\0\0\0"));
+        assert!(animated("apng", &png(&[b"tEXt", b"acTL", b"IDAT"])));
+        assert!(!animated("png", &png(&[b"tEXt", b"IDAT", b"acTL"])));
+        assert!(!animated("truncated_png", &png(&[b"tEXt"])));
+        assert!(animated("animated_webp", &webp(0x02)));
+        assert!(!animated("webp", &webp(0x10)));
+        assert!(!animated("jpeg", b"\xff\xd8\xff\xe0\0\x10JFIF\0"));
+    }
+
+    #[test]
+    fn frames_are_bounded() {
+        assert_eq!(max_frames(10, 10, 4000), 10);
+        assert_eq!(max_frames(10, 10, 3999), 9);
+        // The first frame is always decoded
+        assert_eq!(max_frames(10, 10, 0), 1);
+        assert_eq!(max_frames(1, 1, usize::MAX), MAX_FRAMES);
+    }
+
+    #[test]
+    fn animated_webp_frames() {
+        let webp = animated_webp();
+        let frames = decode_frames(&webp, usize::MAX).unwrap().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].image.dimensions(), (1, 1));
+        assert_eq!(frames[0].delay, 0.1);
+        let first = decode_first_frame(&webp).unwrap().unwrap();
+        assert_eq!(first.dimensions(), (1, 1));
+        assert_eq!(first.get_pixel(0, 0)[3], 255);
+        assert!(decode_first_frame(b"\xff\xd8\xff\xe0\0\x10JFIF\0")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn regions_outside_the_canvas_are_left_out() {
+        let mut canvas = Canvas::new(4, 4, usize::MAX);
+        let image = RgbaImage::from_pixel(4, 4, Rgba([255, 0, 0, 255]));
+        let mut region = Region {
+            x: u32::MAX - 1,
+            y: 2,
+            width: 4,
+            height: 4,
+            delay: 0.1,
+            dispose: 1,
+            blend: 0,
+        };
+        canvas.add(&region, &image);
+        region.x = 2;
+        region.y = u32::MAX;
+        canvas.add(&region, &image);
+        assert_eq!(canvas.frames.len(), 2);
+        assert!(canvas
+            .frames
+            .iter()
+            .all(|frame| frame.image.pixels().all(|p| p[3] == 0)));
+        // A frame that runs off the canvas is cut off, and cleared afterwards
+        region.y = 3;
+        region.dispose = 0;
+        canvas.add(&region, &image);
+        let covered = |x, y| canvas.frames[2].image.get_pixel(x, y)[3] == 255;
+        assert!(covered(3, 3) && covered(2, 3) && !covered(1, 3) && !covered(3, 2));
+    }
+}
@@ -1,38 +1,719 @@
 extern crate image_grid;
 
-use clap::{App, Arg};
+use anyhow::anyhow;
+use clap::{App, Arg, ArgMatches};
 use glutin_window::GlutinWindow as Window;
+use graphics::Graphics;
 use image_grid::{
+    animation::{self, Animate},
+    dir_walker::DirWalker,
     grid::{Grid, GridResult, TileHandler},
-    image_loader::ImageLoader,
+    image_loader::{ImageLoader, ImageStream, LoadError, LoadReport},
+    keymap::Keymap,
+    metadata::Metadata,
+    orientation::{self, Orientation, SaveTarget},
+    placeholder::Placeholder,
+    render::ImageTexture,
+    search::Search,
+    slideshow::SlideshowSettings,
+    software::{SoftwareGraphics, SoftwareTexture},
+    sort::{self, SortBy, SortInfo, SortKey},
+    texture_cache::{self, TextureCache},
+    thumbnail_cache::ThumbnailCache,
 };
-use opengl_graphics::Texture;
-use opengl_graphics::{GlGraphics, OpenGL};
+use opengl_graphics::{GlGraphics, OpenGL, Texture};
 use piston::window::WindowSettings;
-use std::io::{self, BufRead};
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
 
-struct ImageTileHandler {
-    filenames: Vec<String>,
-    tiles: Vec<Texture>,
+struct ImageTileHandler<T> {
+    files: Vec<PathBuf>,
+    tiles: Vec<Option<T>>,
+    // Every tile in display order, and the ones the filter leaves, which are
+    // the ones shown
     indexes: Vec<usize>,
+    shown: Vec<usize>,
+    filter: Option<Search>,
+    stream: Option<ImageStream>,
+    // Files that fail to load are kept as placeholder tiles, along with why,
+    // instead of being left out
+    keep_failed: bool,
+    errors: Vec<Option<LoadError>>,
+    // Everything that went wrong while loading, printed once the grid closes
+    report: LoadReport,
+    // full resolution images for fullscreen display
+    full: TextureCache<T>,
+    full_load: Option<(usize, ImageStream)>,
+    // The tile shown fullscreen, and the last one whose full resolution
+    // image was considered for loading ahead of time
+    showing: Option<usize>,
+    prefetched: Option<usize>,
+    // The frames of the tile being played back, once they are loaded
+    animating: Option<usize>,
+    animation: Option<Animation<T>>,
+    // Tiles that turned out not to be animated, so they are not loaded again
+    stills: HashSet<usize>,
+    // Tiles whose full resolution image failed to load, so it is not retried
+    full_failed: HashSet<usize>,
+    // None while the files are in the order they were listed in
+    sort: Option<SortBy>,
+    // Read the first time the files are sorted, so sorting again is quick.
+    // Empty until then.
+    sort_info: Vec<SortInfo>,
+    // The order being sorted into, while the files are read on another
+    // thread
+    resorting: Option<(SortBy, Receiver<Vec<SortInfo>>)>,
+    // In picker mode confirming a selection stores it and closes the grid
+    // instead of printing it.
+    picker: bool,
+    picked: Option<Vec<usize>>,
+    // Read the first time a file is shown in the status bar
+    info: Vec<OnceCell<FileInfo>>,
+    // Read the first time a file is shown in the info panel, on another
+    // thread as the whole file is read, and the file being read
+    metadata: Vec<OnceCell<FileMetadata>>,
+    reading_metadata: RefCell<Option<(usize, Receiver<FileMetadata>)>>,
+    // Rotations and flips on top of the EXIF orientation, which are saved
+    // as they are made when there is somewhere to save them
+    orientations: Vec<Orientation>,
+    save_rotation: Option<SaveTarget>,
 }
 
-impl TileHandler for ImageTileHandler {
+// The details of a file for the info panel, or why it could not be read
+type FileMetadata = Result<Vec<(String, String)>, String>;
+
+struct Animation<T> {
+    tile: usize,
+    frames: Vec<T>,
+    delays: Vec<f64>,
+    current: usize,
+}
+
+// What the status bar shows about a file besides its name. Anything that
+// cannot be read is left out.
+struct FileInfo {
+    size: Option<u64>,
+    format: Option<image::ImageFormat>,
+    dimensions: Option<(u32, u32)>,
+}
+
+impl FileInfo {
+    fn read(file: &Path) -> FileInfo {
+        let size = fs::metadata(file).map(|metadata| metadata.len()).ok();
+        let reader = image::io::Reader::open(file).and_then(|reader| reader.with_guessed_format());
+        let (format, dimensions) = match reader {
+            Ok(reader) => (reader.format(), reader.into_dimensions().ok()),
+            Err(_) => (None, None),
+        };
+        FileInfo {
+            size,
+            format,
+            dimensions,
+        }
+    }
+}
+
+// How picked files are written to stdout
+#[derive(Clone, Copy)]
+enum PickFormat {
+    Lines,
+    Nul,
+    Json,
+}
+
+impl<T: ImageTexture> ImageTileHandler<T> {
+    fn new(files: Vec<PathBuf>, tiles: Vec<T>) -> ImageTileHandler<T> {
+        let indexes: Vec<usize> = (0..tiles.len()).collect();
+        ImageTileHandler {
+            info: files.iter().map(|_| OnceCell::new()).collect(),
+            metadata: files.iter().map(|_| OnceCell::new()).collect(),
+            reading_metadata: RefCell::new(None),
+            orientations: files
+                .iter()
+                .map(|f| orientation::saved_orientation(f))
+                .collect(),
+            save_rotation: None,
+            keep_failed: false,
+            errors: files.iter().map(|_| None).collect(),
+            report: LoadReport::default(),
+            files,
+            tiles: tiles.into_iter().map(Some).collect(),
+            shown: indexes.clone(),
+            indexes,
+            filter: None,
+            stream: None,
+            full: TextureCache::new(0),
+            full_load: None,
+            showing: None,
+            prefetched: None,
+            animating: None,
+            animation: None,
+            stills: HashSet::new(),
+            full_failed: HashSet::new(),
+            sort: None,
+            sort_info: Vec::new(),
+            resorting: None,
+            picker: false,
+            picked: None,
+        }
+    }
+
+    // Every file gets a tile up front which shows a placeholder until the
+    // image has been decoded.
+    fn from_stream(stream: ImageStream, memory_budget: usize) -> ImageTileHandler<T> {
+        let files = stream.files().to_vec();
+        let indexes: Vec<usize> = (0..files.len()).collect();
+        ImageTileHandler {
+            tiles: files.iter().map(|_| None).collect(),
+            info: files.iter().map(|_| OnceCell::new()).collect(),
+            metadata: files.iter().map(|_| OnceCell::new()).collect(),
+            reading_metadata: RefCell::new(None),
+            orientations: files.iter().map(|_| Orientation::default()).collect(),
+            save_rotation: None,
+            keep_failed: false,
+            errors: files.iter().map(|_| None).collect(),
+            report: LoadReport::default(),
+            files,
+            shown: indexes.clone(),
+            indexes,
+            filter: None,
+            stream: Some(stream),
+            full: TextureCache::new(memory_budget),
+            full_load: None,
+            showing: None,
+            prefetched: None,
+            animating: None,
+            animation: None,
+            stills: HashSet::new(),
+            full_failed: HashSet::new(),
+            sort: None,
+            sort_info: Vec::new(),
+            resorting: None,
+            picker: false,
+            picked: None,
+        }
+    }
+
+    // Reading the dimensions of every file takes a while, so it is done on
+    // other threads and the tiles are sorted once it is done
+    fn resort(&mut self, sort: SortBy) {
+        let files = self.files.clone();
+        let known = self.sort_info.clone();
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            let _ = sender.send(sort::read_infos(&files, sort.key, known));
+        });
+        self.resorting = Some((sort, receiver));
+    }
+
+    // Starts reading the details of file `i` unless they are already being
+    // read. Moving on to another file before they are in forgets them.
+    fn read_metadata(&self, i: usize) {
+        let mut reading = self.reading_metadata.borrow_mut();
+        if reading.as_ref().is_some_and(|(index, _)| *index == i) {
+            return;
+        }
+        let file = self.files[i].clone();
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            let metadata = fs::read(&file)
+                .map(|contents| {
+                    let mut entries =
+                        vec![("Size".to_string(), format_size(contents.len() as u64))];
+                    entries.extend(Metadata::read(&contents).entries());
+                    entries
+                })
+                .map_err(|err| err.to_string());
+            let _ = sender.send(metadata);
+        });
+        *reading = Some((i, receiver));
+    }
+
+    fn update_metadata(&mut self) {
+        let (i, metadata) = match self.reading_metadata.get_mut() {
+            Some((i, receiver)) => match receiver.try_recv() {
+                Ok(metadata) => (*i, metadata),
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => (*i, Err("unable to read".to_string())),
+            },
+            None => return,
+        };
+        *self.reading_metadata.get_mut() = None;
+        let _ = self.metadata[i].set(metadata);
+    }
+
+    fn update_sort(&mut self) {
+        if let Some(sorted) = self.stream.as_ref().and_then(ImageStream::sorted) {
+            // Sorted again while the stream was still sorting
+            if self.sort_info.is_empty() && self.resorting.is_none() {
+                self.sort_info = sorted.infos.clone();
+                self.apply_sort(sorted.sort);
+            }
+        }
+        let (sort, receiver) = match &self.resorting {
+            Some(resorting) => resorting,
+            None => return,
+        };
+        match receiver.try_recv() {
+            Ok(infos) => {
+                let sort = *sort;
+                self.resorting = None;
+                self.sort_info = infos;
+                self.apply_sort(sort);
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => self.resorting = None,
+        }
+    }
+
+    fn apply_sort(&mut self, sort: SortBy) {
+        self.sort = Some(sort);
+        let infos = &self.sort_info;
+        self.indexes
+            .sort_by(|a, b| sort.compare(&infos[*a], &infos[*b]));
+        self.refilter();
+    }
+
+    fn refilter(&mut self) {
+        let files = &self.files;
+        self.shown = match &self.filter {
+            Some(search) => self
+                .indexes
+                .iter()
+                .copied()
+                .filter(|i| search.matches(&files[*i].to_string_lossy()))
+                .collect(),
+            None => self.indexes.clone(),
+        };
+    }
+
+    fn print_picked(&self, format: PickFormat) -> io::Result<()> {
+        let picked: Vec<&Path> = self
+            .picked
+            .iter()
+            .flatten()
+            .map(|i| self.files[*i].as_path())
+            .collect();
+        write_paths(&mut io::stdout().lock(), &picked, format)
+    }
+
+    // Waits for every tile to finish loading, for when there is no window to
+    // draw them in as they arrive.
+    fn finish_loading<G: Graphics<Texture = T>>(&mut self) {
+        while self.stream.is_some() {
+            TileHandler::<G>::update(self);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // Everything that went wrong, including the files that already failed if
+    // the window was closed before loading finished
+    fn final_report(&self) -> LoadReport {
+        let mut report = self.report.clone();
+        if let Some(stream) = &self.stream {
+            report
+                .failures
+                .extend(stream.report().failures.iter().cloned());
+        }
+        report
+    }
+
+    fn update_full(&mut self) {
+        let (i, stream) = match self.full_load.as_mut() {
+            Some((i, stream)) if !stream.is_done() => (*i, stream),
+            _ => return,
+        };
+        for loaded in stream.poll() {
+            match loaded.image {
+                Ok((image, frames)) => {
+                    self.full.insert(i, T::from_rgba(&image));
+                    if frames.is_empty() {
+                        self.stills.insert(i);
+                    } else if self.animating == Some(i) {
+                        self.set_animation(Some(Animation {
+                            tile: i,
+                            frames: frames.iter().map(|f| T::from_rgba(&f.image)).collect(),
+                            delays: frames.iter().map(|f| f.delay).collect(),
+                            current: 0,
+                        }));
+                    }
+                }
+                Err(err) => {
+                    self.report.failures.push(err);
+                    self.stills.insert(i);
+                    self.full_failed.insert(i);
+                }
+            }
+        }
+    }
+
+    fn load_full(&mut self, i: usize) {
+        let file = self.files[i].clone();
+        // Frames may take up whatever the thumbnails leave
+        let max_frame_bytes = self.full.budget().saturating_sub(self.full.reserved());
+        self.full_load = Some((
+            i,
+            ImageLoader::stream_full_size(vec![file], max_frame_bytes),
+        ));
+    }
+
+    // The frames count against the memory budget while they are kept
+    fn set_animation(&mut self, animation: Option<Animation<T>>) {
+        let size = |animation: &Animation<T>| -> usize {
+            animation
+                .frames
+                .iter()
+                .map(texture_cache::texture_size)
+                .sum()
+        };
+        if let Some(old) = self.animation.take() {
+            self.full.release(size(&old));
+        }
+        if let Some(new) = &animation {
+            self.full.reserve(size(new));
+        }
+        self.animation = animation;
+    }
+
+    // Animated files are decoded again for their frames, once nothing else
+    // is loading. Files whose header rules out an animation are not.
+    fn load_animation(&mut self) {
+        let i = match self.animating {
+            Some(i) => i,
+            None => return,
+        };
+        if self.animation(i).is_some() || self.stills.contains(&i) || self.errors[i].is_some() {
+            return;
+        }
+        if let Some((_, stream)) = &self.full_load {
+            if !stream.is_done() {
+                return;
+            }
+        }
+        if !animation::may_be_animated(&self.files[i]) {
+            self.stills.insert(i);
+            return;
+        }
+        self.load_full(i);
+    }
+
+    fn animation(&self, i: usize) -> Option<&Animation<T>> {
+        self.animation
+            .as_ref()
+            .filter(|animation| animation.tile == i)
+    }
+
+    fn frame(&self, i: usize) -> Option<&T> {
+        self.animation(i)
+            .map(|animation| &animation.frames[animation.current])
+    }
+}
+
+impl<G, T> TileHandler<G> for ImageTileHandler<T>
+where
+    G: Graphics<Texture = T>,
+    T: ImageTexture,
+{
     fn window_title(&self) -> String {
-        "Image Grid".to_string()
+        match self.sort {
+            Some(sort) => format!("Image Grid (sorted by {})", sort),
+            None => "Image Grid".to_string(),
+        }
     }
 
     fn tiles(&self) -> &Vec<usize> {
-        return &self.indexes;
+        &self.shown
+    }
+
+    fn tile(&self, i: usize) -> Option<&T> {
+        self.frame(i).or(self.tiles[i].as_ref())
     }
 
-    fn tile(&self, i: usize) -> &Texture {
-        &self.tiles[i]
+    fn full_tile(&self, i: usize) -> Option<&T> {
+        self.frame(i)
+            .or_else(|| self.full.get(i))
+            .or_else(|| self.tiles[i].as_ref())
+    }
+
+    fn fullscreen(&mut self, i: usize) {
+        self.showing = Some(i);
+        // The frames stand in for the full resolution image
+        if self.errors[i].is_some() || self.animation(i).is_some() {
+            return;
+        }
+        if self.full.contains(i) {
+            self.full.touch(i);
+            return;
+        }
+        // Only one full resolution image is loaded at a time. An image that
+        // loaded but has since been dropped to make room is loaded again.
+        let loading = self
+            .full_load
+            .as_ref()
+            .is_some_and(|(loading, stream)| *loading == i && !stream.is_done());
+        if loading || self.full_failed.contains(&i) {
+            return;
+        }
+        self.load_full(i);
+    }
+
+    // Waits for the image being shown, which may still be loading, so that
+    // it comes first. Each tile is only considered once, so a file that
+    // fails to load is not loaded over and over.
+    fn prefetch(&mut self, i: usize) {
+        let loading = self
+            .full_load
+            .as_ref()
+            .is_some_and(|(_, stream)| !stream.is_done());
+        if loading
+            || self.prefetched == Some(i)
+            || self.full.contains(i)
+            || self.errors[i].is_some()
+            || self.full_failed.contains(&i)
+        {
+            return;
+        }
+        self.prefetched = Some(i);
+        // Making room for the next image must not push out the one being
+        // shown, which would then be loaded again and push out the next one
+        let shown = self
+            .showing
+            .and_then(|shown| self.full.get(shown))
+            .map_or(0, texture_cache::texture_size);
+        let fits = image::image_dimensions(&self.files[i]).is_ok_and(|(width, height)| {
+            shown + self.full.reserved() + width as usize * height as usize * 4
+                <= self.full.budget()
+        });
+        if fits {
+            self.load_full(i);
+        }
+    }
+
+    fn start_animation(&mut self, i: usize) {
+        self.animating = Some(i);
+        self.load_animation();
+    }
+
+    fn stop_animation(&mut self, i: usize) {
+        if self.animating == Some(i) {
+            self.animating = None;
+            self.set_animation(None);
+        }
+    }
+
+    fn frame_count(&self, i: usize) -> usize {
+        self.animation(i)
+            .map_or(1, |animation| animation.frames.len())
+    }
+
+    fn frame_delay(&self, i: usize, frame: usize) -> f64 {
+        self.animation(i)
+            .and_then(|animation| animation.delays.get(frame).copied())
+            .unwrap_or(0.1)
+    }
+
+    fn show_frame(&mut self, i: usize, frame: usize) {
+        if let Some(animation) = self.animation.as_mut().filter(|a| a.tile == i) {
+            animation.current = frame.min(animation.frames.len() - 1);
+        }
+    }
+
+    fn update(&mut self) {
+        self.update_metadata();
+        self.update_full();
+        self.load_animation();
+        self.update_sort();
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return,
+        };
+        // Only files that fail to load change which tiles there are
+        let mut removed = false;
+        for loaded in stream.poll() {
+            let index = loaded.index;
+            match loaded.image {
+                Ok((image, _frames)) => {
+                    // Thumbnails are kept for as long as the grid is open, and
+                    // leave less room for full resolution images
+                    let tile = T::from_rgba(&image);
+                    self.full.reserve(texture_cache::texture_size(&tile));
+                    self.tiles[index] = Some(tile);
+                    self.orientations[index] = loaded.orientation;
+                }
+                Err(err) if self.keep_failed => {
+                    self.errors[index] = Some(err);
+                }
+                Err(_) => {
+                    self.indexes.retain(|i| *i != index);
+                    removed = true;
+                }
+            }
+        }
+        if stream.is_done() {
+            // Drop the tiles that were never loaded because max was reached
+            let (tiles, errors) = (&self.tiles, &self.errors);
+            self.indexes
+                .retain(|i| tiles[*i].is_some() || errors[*i].is_some());
+            self.report
+                .failures
+                .extend(stream.report().failures.iter().cloned());
+            // The order may have arrived along with the last of the images
+            self.update_sort();
+            self.stream = None;
+            removed = true;
+        }
+        if removed {
+            self.refilter();
+        }
+    }
+
+    fn busy(&self) -> bool {
+        let full_loading = self
+            .full_load
+            .as_ref()
+            .is_some_and(|(_, stream)| !stream.is_done());
+        self.stream.is_some()
+            || full_loading
+            || self.resorting.is_some()
+            || self.reading_metadata.borrow().is_some()
+    }
+
+    fn placeholder(&self, i: usize) -> Placeholder {
+        match &self.errors[i] {
+            Some(err) if err.is_not_found() => Placeholder::Missing,
+            Some(_) => Placeholder::Broken,
+            None => Placeholder::Loading,
+        }
+    }
+
+    fn matches(&self, i: usize, search: &Search) -> bool {
+        search.matches(&self.files[i].to_string_lossy())
+    }
+
+    fn filter(&mut self, search: Option<&Search>) {
+        self.filter = search.cloned();
+        self.refilter();
     }
 
     fn act(&mut self, i: usize) {
-        println!("{}", self.filenames[i]);
+        if self.picker {
+            self.picked = Some(vec![i]);
+        } else {
+            // Errors writing to stdout are ignored, as println would panic
+            let _ = write_paths(
+                &mut io::stdout().lock(),
+                &[&self.files[i]],
+                PickFormat::Lines,
+            );
+        }
+    }
+
+    fn act_on_selection(&mut self, tiles: &[usize]) {
+        if self.picker {
+            self.picked = Some(tiles.to_vec());
+        } else {
+            let files: Vec<&Path> = tiles.iter().map(|i| self.files[*i].as_path()).collect();
+            let _ = write_paths(&mut io::stdout().lock(), &files, PickFormat::Lines);
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.picked.is_some()
+    }
+
+    fn actions(&self) -> Vec<(&'static str, &'static [&'static str])> {
+        vec![("next-sort", &["s"]), ("reverse-sort", &["S"])]
+    }
+
+    fn action(&mut self, _i: usize, name: &str, count: usize) {
+        // Pressed again before the last sort was done
+        let current = self.resorting.as_ref().map(|(sort, _)| *sort).or(self.sort);
+        let mut sort = current.unwrap_or_else(|| SortBy::new(SortKey::Name));
+        match name {
+            "next-sort" => {
+                // The first press sorts by name
+                let steps = if current.is_some() { count } else { count - 1 };
+                for _ in 0..steps {
+                    sort.key = sort.key.next();
+                }
+            }
+            "reverse-sort" => sort.descending = !sort.descending,
+            _ => return,
+        }
+        self.resort(sort);
+    }
+
+    fn orientation(&self, i: usize) -> Orientation {
+        self.orientations[i]
+    }
+
+    fn set_orientation(&mut self, i: usize, orientation: Orientation) {
+        let previous = self.orientations[i];
+        self.orientations[i] = orientation;
+        if let Some(target) = self.save_rotation {
+            let file = &self.files[i];
+            if let Err(err) = orientation::save_orientation(file, previous, orientation, target) {
+                eprintln!("{}: unable to save rotation: {}", file.display(), err);
+            }
+        }
+    }
+
+    fn caption(&self, i: usize) -> Option<String> {
+        self.files[i]
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+    }
+
+    fn status_text(&self, i: usize) -> Option<String> {
+        let info = self.info[i].get_or_init(|| FileInfo::read(&self.files[i]));
+        let mut details = Vec::new();
+        if let Some(err) = &self.errors[i] {
+            details.push(err.message());
+        }
+        if let Some((width, height)) = info.dimensions {
+            details.push(format!("{}×{}", width, height));
+        }
+        if let Some(size) = info.size {
+            details.push(format_size(size));
+        }
+        if let Some(format) = &info.format {
+            details.push(format!("{:?}", format));
+        }
+        if let Some(position) = self.shown.iter().position(|index| *index == i) {
+            details.push(format!("{} / {}", position + 1, self.shown.len()));
+        }
+        Some(format!(
+            "{}\t{}",
+            self.files[i].display(),
+            details.join("    ")
+        ))
+    }
+
+    // The rest of the details are added once they have been read
+    fn metadata(&self, i: usize) -> Vec<(String, String)> {
+        let mut entries = vec![("File".to_string(), self.files[i].display().to_string())];
+        let error = self.errors[i].as_ref().map(LoadError::message);
+        if let Some(error) = &error {
+            entries.push(("Error".to_string(), error.clone()));
+        }
+        match self.metadata[i].get() {
+            Some(Ok(metadata)) => entries.extend(metadata.iter().cloned()),
+            Some(Err(err)) if error.is_none() => entries.push(("Error".to_string(), err.clone())),
+            Some(Err(_)) => {}
+            None => self.read_metadata(i),
+        }
+        entries
+    }
+
+    fn group(&self, i: usize) -> Option<String> {
+        self.files[i].parent().map(|dir| dir.display().to_string())
     }
 }
 
@@ -51,6 +732,38 @@ fn main() -> GridResult<()> {
                 .long("stdin")
                 .help("Read files to display from stdin"),
         )
+        .arg(
+            Arg::with_name("recursive")
+                .long("recursive")
+                .short("r")
+                .help("Also display images in subdirectories of the directory."),
+        )
+        .arg(
+            Arg::with_name("max-depth")
+                .long("max-depth")
+                .takes_value(true)
+                .help("How many levels of subdirectories to descend into. Implies --recursive."),
+        )
+        .arg(
+            Arg::with_name("follow-symlinks")
+                .long("follow-symlinks")
+                .help("Descend into symlinked directories when recursing."),
+        )
+        .arg(
+            Arg::with_name("skip-hidden")
+                .long("skip-hidden")
+                .help("Ignore files and directories whose names start with a dot."),
+        )
+        .arg(
+            Arg::with_name("sort")
+                .long("sort")
+                .takes_value(true)
+                .value_name("KEY[:ORDER]")
+                .help(
+                    "Sort by name, natural, mtime, size, dimensions or aspect, \
+                     optionally followed by :asc or :desc.",
+                ),
+        )
         .arg(
             Arg::with_name("max")
                 .long("max")
@@ -76,6 +789,10 @@ fn main() -> GridResult<()> {
                 .takes_value(true)
                 .help("Only display files that match this regex."),
         )
+        .arg(Arg::with_name("keep-failed").long("keep-failed").help(
+            "Show files that are missing or cannot be decoded as placeholder tiles \
+                     instead of leaving them out.",
+        ))
         .arg(
             Arg::with_name("tile-width")
                 .long("tile-width")
@@ -85,10 +802,10 @@ fn main() -> GridResult<()> {
         )
         .arg(
             Arg::with_name("tile-height")
-                .long("tile-width")
+                .long("tile-height")
                 .takes_value(true)
                 .default_value("200")
-                .help("Set the max tile-width."),
+                .help("Set the max tile-height."),
         )
         .arg(
             Arg::with_name("draw-tile")
@@ -97,23 +814,231 @@ fn main() -> GridResult<()> {
                 .default_value("true")
                 .help("Whether to draw tile fullscreen when activating a tile."),
         )
+        .arg(
+            Arg::with_name("slideshow")
+                .long("slideshow")
+                .help("Start with a fullscreen slideshow of the images. F5 starts and stops it."),
+        )
+        .arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .default_value("5")
+                .help("How long each image is shown for in slideshows."),
+        )
+        .arg(
+            Arg::with_name("shuffle")
+                .long("shuffle")
+                .help("Show the images of slideshows in random order."),
+        )
+        .arg(
+            Arg::with_name("loop")
+                .long("loop")
+                .help("Start slideshows over after the last image."),
+        )
+        .arg(
+            Arg::with_name("fade")
+                .long("fade")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .default_value("0")
+                .help("Cross-fade between the images of slideshows for this long."),
+        )
+        .arg(
+            Arg::with_name("animate")
+                .long("animate")
+                .takes_value(true)
+                .possible_values(&["fullscreen", "selected", "hovered"])
+                .default_value("fullscreen")
+                .help(
+                    "Which animated images are played: only in fullscreen, or also the selected \
+                       or hovered image in the grid. Animated WebP files play in grayscale, \
+                       and only when their frames are lossy.",
+                ),
+        )
+        .arg(
+            Arg::with_name("save-rotation")
+                .long("save-rotation")
+                .takes_value(true)
+                .possible_values(&["file", "sidecar"])
+                .help(
+                    "Save rotations and flips as they are made: in the EXIF orientation of \
+                     the image file, or in an XMP sidecar next to it.",
+                ),
+        )
+        .arg(
+            Arg::with_name("memory-budget")
+                .long("memory-budget")
+                .takes_value(true)
+                .value_name("MB")
+                .default_value("512")
+                .help(
+                    "Memory available for the full resolution images shown fullscreen, which \
+                     are dropped when it runs out. Thumbnails count against it, but are never \
+                     dropped.",
+                ),
+        )
+        .arg(
+            Arg::with_name("no-cache")
+                .long("no-cache")
+                .help("Do not read or write cached thumbnails."),
+        )
+        .arg(
+            Arg::with_name("clear-cache")
+                .long("clear-cache")
+                .help("Delete the thumbnails cached by image_grid before loading images."),
+        )
+        .arg(
+            Arg::with_name("cache-size")
+                .long("cache-size")
+                .takes_value(true)
+                .value_name("MB")
+                .default_value("512")
+                .help("Maximum disk space used by cached thumbnails."),
+        )
+        .arg(
+            Arg::with_name("export")
+                .long("export")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write a contact sheet of the images to FILE instead of opening a window."),
+        )
+        .arg(
+            Arg::with_name("export-width")
+                .long("export-width")
+                .takes_value(true)
+                .default_value("1200")
+                .help("The width in pixels of the exported contact sheet."),
+        )
+        .arg(
+            Arg::with_name("keymap")
+                .long("keymap")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Read key bindings from FILE instead of the default keymap file."),
+        )
+        .arg(Arg::with_name("pick").long("pick").help(
+            "Pick images: confirming a selection closes the window and prints the \
+             selected files. Exits with status 130 if nothing was picked.",
+        ))
+        .arg(
+            Arg::with_name("print0")
+                .long("print0")
+                .requires("pick")
+                .conflicts_with("json")
+                .help("Separate the picked files with NUL characters instead of newlines."),
+        )
+        .arg(Arg::with_name("json").long("json").requires("pick").help(
+            "Print the picked files as a JSON array. Bytes in file names that are not \
+                     valid UTF-8 are replaced.",
+        ))
+        .arg(
+            Arg::with_name("no-captions")
+                .long("no-captions")
+                .help("Do not show file names below the images."),
+        )
+        .arg(
+            Arg::with_name("no-status-bar")
+                .long("no-status-bar")
+                .help("Do not show details of the current image along the bottom of the window."),
+        )
         .get_matches();
 
     let mut loader = ImageLoader::new();
     if let Some(filters) = matches.values_of("filter") {
         for filter in filters {
-            loader.filter(filter);
+            loader.filter(filter)?;
         }
     }
     if let Some(onlys) = matches.values_of("only") {
         for only in onlys {
-            loader.only(only);
+            loader.only(only)?;
         }
     }
+    let mut walker = DirWalker::new();
+    if let Some(max_depth) = matches.value_of("max-depth") {
+        walker.recursive(Some(max_depth.parse()?));
+    } else if matches.is_present("recursive") {
+        walker.recursive(None);
+    }
+    walker.follow_symlinks(matches.is_present("follow-symlinks"));
+    walker.include_hidden(!matches.is_present("skip-hidden"));
+    loader.walker(walker);
+    let sort = match matches.value_of("sort") {
+        Some(sort) => Some(sort.parse::<SortBy>()?),
+        None => None,
+    };
+    if let Some(sort) = sort {
+        loader.sort(sort);
+    }
     if let Some(max) = matches.value_of("max") {
         let max = max.parse().expect("Unable to parse max");
         loader.max(max);
     }
+    let tile_width = matches
+        .value_of("tile-width")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap();
+    let tile_height = matches
+        .value_of("tile-height")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap();
+
+    loader.thumbnail_size(tile_width as u32, tile_height as u32);
+    if let Some(root) = ThumbnailCache::default_root() {
+        let mut cache = ThumbnailCache::new(root);
+        cache.max_size(matches.value_of("cache-size").unwrap().parse::<u64>()? * 1024 * 1024);
+        if matches.is_present("clear-cache") {
+            cache.clear()?;
+            if !matches.is_present("dir") && !matches.is_present("stdin") {
+                return Ok(());
+            }
+        }
+        if !matches.is_present("no-cache") {
+            // Reading every thumbnail takes a while, and should not keep the
+            // window from opening
+            let pruning = cache.clone();
+            thread::spawn(move || {
+                if let Err(err) = pruning.prune() {
+                    eprintln!("Unable to prune the thumbnail cache: {}", err);
+                }
+            });
+            loader.cache(cache);
+        }
+    }
+    let memory_budget = matches
+        .value_of("memory-budget")
+        .unwrap()
+        .parse::<usize>()?
+        * 1024
+        * 1024;
+    let interval = seconds("interval", matches.value_of("interval").unwrap(), false)?;
+    let fade = seconds("fade", matches.value_of("fade").unwrap(), true)?;
+
+    if let Some(export) = matches.value_of("export") {
+        let width = matches.value_of("export-width").unwrap().parse::<u32>()?;
+        let mut handler: ImageTileHandler<SoftwareTexture> = if matches.is_present("keep-failed") {
+            let stream = loader.stream_files(input_files(&matches, &loader)?);
+            let mut handler = ImageTileHandler::from_stream(stream, 0);
+            handler.keep_failed = true;
+            handler.finish_loading::<SoftwareGraphics>();
+            eprint!("{}", handler.final_report());
+            handler
+        } else {
+            let (files, tiles, report) = loader.load_files(input_files(&matches, &loader)?)?;
+            eprint!("{}", report);
+            ImageTileHandler::new(files.into_iter().map(PathBuf::from).collect(), tiles)
+        };
+        let mut grid: Grid<SoftwareGraphics> =
+            Grid::new(Box::new(&mut handler), tile_width, tile_height);
+        grid.show_captions = !matches.is_present("no-captions");
+        grid.contact_sheet(width)?.save(export)?;
+        return Ok(());
+    }
+
     // Change this to OpenGL::V2_1 if not working.
     let opengl = OpenGL::V3_2;
 
@@ -122,55 +1047,303 @@ fn main() -> GridResult<()> {
         .resizable(true)
         .vsync(true)
         .graphics_api(opengl)
-        .exit_on_esc(true)
+        .exit_on_esc(false)
         .build()
         .unwrap();
 
     let mut gl = GlGraphics::new(opengl);
     //let mut app = ImageViewerApp { gl, rotation: 0.0 };
 
-    let (files, tiles) = if matches.is_present("dir") {
-        loader.load_all(PathBuf::from(
+    let mut handler: ImageTileHandler<Texture> = ImageTileHandler::from_stream(
+        loader.stream_files(input_files(&matches, &loader)?),
+        memory_budget,
+    );
+    handler.sort = sort;
+    handler.keep_failed = matches.is_present("keep-failed");
+    handler.picker = matches.is_present("pick");
+    if let Some(target) = matches.value_of("save-rotation") {
+        handler.save_rotation = Some(target.parse()?);
+    }
+    let mut grid = Grid::new(Box::new(&mut handler), tile_width, tile_height);
+    grid.show_captions = !matches.is_present("no-captions");
+    grid.show_status_bar = !matches.is_present("no-status-bar");
+    let draw_tile = matches.value_of("draw-tile").unwrap().parse::<bool>()?;
+    if !draw_tile {
+        grid.allow_draw_tile = false;
+    }
+    if let Some(keymap) = keymap(&matches, &grid.handler_actions())? {
+        grid.set_keymap(keymap);
+    }
+    grid.slideshow_settings = SlideshowSettings {
+        interval,
+        shuffle: matches.is_present("shuffle"),
+        repeat: matches.is_present("loop"),
+        fade,
+    };
+    grid.animate = matches.value_of("animate").unwrap().parse::<Animate>()?;
+    if matches.is_present("slideshow") {
+        grid.start_slideshow();
+    }
+    grid.run(&mut window, &mut gl)?;
+    drop(grid);
+    eprint!("{}", handler.final_report());
+
+    if handler.picker {
+        if handler.picked.is_none() {
+            // Same status as fzf uses when it is cancelled
+            std::process::exit(130);
+        }
+        let format = if matches.is_present("print0") {
+            PickFormat::Nul
+        } else if matches.is_present("json") {
+            PickFormat::Json
+        } else {
+            PickFormat::Lines
+        };
+        handler.print_picked(format)?;
+    }
+    Ok(())
+}
+
+// Sizes in bytes with binary prefixes, such as "2.4 MiB"
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+// Paths are written as the bytes the file system uses, so that the names
+// can be passed on even when they are not valid UTF-8. JSON can only hold
+// text, so invalid bytes are replaced there.
+fn write_paths(out: &mut dyn Write, files: &[&Path], format: PickFormat) -> io::Result<()> {
+    match format {
+        PickFormat::Lines => {
+            for file in files {
+                out.write_all(&path_bytes(file))?;
+                out.write_all(b"\n")?;
+            }
+        }
+        PickFormat::Nul => {
+            for file in files {
+                out.write_all(&path_bytes(file))?;
+                out.write_all(b"\0")?;
+            }
+        }
+        PickFormat::Json => {
+            let files: Vec<String> = files
+                .iter()
+                .map(|file| json_string(&file.to_string_lossy()))
+                .collect();
+            writeln!(out, "[{}]", files.join(","))?;
+        }
+    }
+    out.flush()
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().into()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    std::ffi::OsString::from_vec(bytes).into()
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    String::from_utf8_lossy(&bytes).into_owned().into()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(path) => path.as_bytes().into(),
+        Cow::Owned(path) => path.into_bytes().into(),
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// The value of option `name` as a number of seconds, which may be zero if
+// `allow_zero` is set.
+fn seconds(name: &str, value: &str, allow_zero: bool) -> GridResult<f64> {
+    let seconds = value
+        .parse::<f64>()
+        .map_err(|err| anyhow!("--{}: {}", name, err))?;
+    if !seconds.is_finite() || seconds < 0.0 || (seconds == 0.0 && !allow_zero) {
+        let least = if allow_zero {
+            "zero or more"
+        } else {
+            "more than zero"
+        };
+        return Err(anyhow!(
+            "--{} must be {} seconds, not {}",
+            name,
+            least,
+            value
+        ));
+    }
+    Ok(seconds)
+}
+
+// An explicitly given keymap must exist, the default one is optional.
+fn keymap(matches: &ArgMatches, handler_actions: &[&str]) -> GridResult<Option<Keymap>> {
+    if let Some(path) = matches.value_of("keymap") {
+        return Ok(Some(Keymap::load(Path::new(path), handler_actions)?));
+    }
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    match config_home.map(|dir| dir.join("image_grid").join("keymap")) {
+        Some(path) if path.exists() => Ok(Some(Keymap::load(&path, handler_actions)?)),
+        _ => Ok(None),
+    }
+}
+
+fn input_files(matches: &ArgMatches, loader: &ImageLoader) -> GridResult<Vec<PathBuf>> {
+    if matches.is_present("dir") {
+        let (files, report) = loader.list(Path::new(
             matches.value_of("dir").expect("Must specify a directory!"),
-        ))?
+        ))?;
+        eprint!("{}", report);
+        Ok(files)
     } else if matches.is_present("stdin") {
         let mut files: Vec<PathBuf> = Vec::new();
         let stdin = io::stdin();
-        for line in stdin.lock().lines() {
-            let file = PathBuf::from(line?);
-            if !file.exists() {
+        for line in stdin.lock().split(b'\n') {
+            // Read as bytes, so that names which are not valid UTF-8 work
+            let mut line = line?;
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            let file = path_from_bytes(line);
+            // Kept files that do not exist are shown as missing
+            if !file.exists() && !matches.is_present("keep-failed") {
                 eprintln!("Skipping: {}", &file.display());
                 continue;
             }
             files.push(file);
         }
-        loader.load_files(files)?
+        Ok(files)
     } else {
         panic!("Must specify either --dir or --stdin. See --help for details.");
-    };
-    let indexes = (0..tiles.len()).collect();
-    let mut handler = ImageTileHandler {
-        filenames: files,
-        tiles,
-        indexes,
-    };
-    let mut grid = Grid::new(
-        Box::new(&mut handler),
-        matches
-            .value_of("tile-width")
-            .unwrap()
-            .parse::<usize>()
-            .unwrap(),
-        matches
-            .value_of("tile-height")
-            .unwrap()
-            .parse::<usize>()
-            .unwrap(),
-    );
-    let draw_tile = matches.value_of("draw-tile").unwrap().parse::<bool>()?;
-    if !draw_tile {
-        grid.allow_draw_tile = false;
     }
-    grid.run(&mut window, &mut gl)?;
-    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(files: &[&Path], format: PickFormat) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_paths(&mut out, files, format).unwrap();
+        out
+    }
+
+    #[test]
+    fn pick_formats() {
+        let files = [Path::new("a b.png"), Path::new("dir/\"c\".png")];
+        assert_eq!(
+            written(&files, PickFormat::Lines),
+            b"a b.png\ndir/\"c\".png\n"
+        );
+        assert_eq!(
+            written(&files, PickFormat::Nul),
+            b"a b.png\0dir/\"c\".png\0"
+        );
+        assert_eq!(
+            written(&files, PickFormat::Json),
+            b"[\"a b.png\",\"dir/\\\"c\\\".png\"]\n"
+        );
+        assert_eq!(written(&[], PickFormat::Json), b"[]\n");
+        assert!(written(&[], PickFormat::Nul).is_empty());
+    }
+
+    #[test]
+    fn report_of_a_stream_that_is_still_open() {
+        let missing = std::env::temp_dir().join("image_grid_missing_report");
+        let stream = ImageLoader::new().stream_files(vec![missing]);
+        let mut handler: ImageTileHandler<SoftwareTexture> =
+            ImageTileHandler::from_stream(stream, 0);
+        // The failure is handed out, but the grid closes before it sees that
+        // the stream is done
+        let stream = handler.stream.as_mut().unwrap();
+        while !stream.is_done() {
+            stream.poll();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(handler.report.failures.is_empty());
+        assert_eq!(handler.final_report().failures.len(), 1);
+    }
+
+    #[test]
+    fn seconds_options() {
+        assert_eq!(seconds("interval", "2.5", false).unwrap(), 2.5);
+        assert_eq!(seconds("fade", "0", true).unwrap(), 0.0);
+        assert_eq!(
+            seconds("interval", "0", false).unwrap_err().to_string(),
+            "--interval must be more than zero seconds, not 0"
+        );
+        assert_eq!(
+            seconds("fade", "-1", true).unwrap_err().to_string(),
+            "--fade must be zero or more seconds, not -1"
+        );
+        assert!(seconds("interval", "-3", false).is_err());
+        assert!(seconds("interval", "inf", false).is_err());
+        assert!(seconds("fade", "NaN", true).is_err());
+        assert!(seconds("fade", "soon", true).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_are_written_as_they_are() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let file = Path::new(OsStr::from_bytes(b"caf\xe9.png"));
+        assert_eq!(written(&[file], PickFormat::Nul), b"caf\xe9.png\0");
+        assert_eq!(written(&[file], PickFormat::Lines), b"caf\xe9.png\n");
+        assert_eq!(
+            written(&[file], PickFormat::Json),
+            "[\"caf\u{fffd}.png\"]\n".as_bytes()
+        );
+    }
+
+    #[test]
+    fn json_strings() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(
+            json_string("line\nbreak\ttab\r"),
+            "\"line\\nbreak\\ttab\\r\""
+        );
+        assert_eq!(json_string("\u{1}\u{1f}"), "\"\\u0001\\u001f\"");
+        assert_eq!(json_string("été ✓"), "\"été ✓\"");
+    }
 }
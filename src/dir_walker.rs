@@ -0,0 +1,266 @@
+use crate::grid::GridResult;
+use crate::image_loader::LoadError;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Lists the files under a directory. By default only the immediate children
+// are listed, like `read_dir` but without the directories. Files are returned directory by
+// directory: the files of a directory come first, followed by the contents of
+// each of its subdirectories, so files in the same directory stay together.
+pub struct DirWalker {
+    max_depth: Option<usize>,
+    follow_symlinks: bool,
+    include_hidden: bool,
+}
+
+impl Default for DirWalker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DirWalker {
+    pub fn new() -> DirWalker {
+        DirWalker {
+            max_depth: Some(0),
+            follow_symlinks: false,
+            include_hidden: true,
+        }
+    }
+
+    /// Descends into subdirectories, at most `max_depth` levels below the
+    /// starting directory. None means there is no limit.
+    pub fn recursive(&mut self, max_depth: Option<usize>) {
+        self.max_depth = max_depth;
+    }
+
+    /// Whether to descend into symlinked directories. Each directory is only
+    /// visited once, so symlinks that form a cycle are not followed forever.
+    pub fn follow_symlinks(&mut self, follow: bool) {
+        self.follow_symlinks = follow;
+    }
+
+    /// Whether to include files and directories whose names start with a dot.
+    pub fn include_hidden(&mut self, include: bool) {
+        self.include_hidden = include;
+    }
+
+    /// Lists the files under `root`. Only an unreadable `root` is an error;
+    /// the entries and subdirectories below it that cannot be read are
+    /// returned alongside the files, so they do not hide everything else.
+    pub fn walk(&self, root: &Path) -> GridResult<(Vec<PathBuf>, Vec<LoadError>)> {
+        let mut files = Vec::new();
+        let mut errors = Vec::new();
+        let mut visited = HashSet::new();
+        visited.insert(fs::canonicalize(root).map_err(|err| LoadError::io(root, err))?);
+        self.walk_dir(root, 0, &mut visited, &mut files, &mut errors)?;
+        Ok((files, errors))
+    }
+
+    fn walk_dir(
+        &self,
+        dir: &Path,
+        depth: usize,
+        visited: &mut HashSet<PathBuf>,
+        files: &mut Vec<PathBuf>,
+        errors: &mut Vec<LoadError>,
+    ) -> Result<(), LoadError> {
+        let mut subdirs = Vec::new();
+        let entries = dir.read_dir().map_err(|err| LoadError::io(dir, err))?;
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if !self.include_hidden && is_hidden(&path) {
+                continue;
+            }
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(err) => {
+                    errors.push(LoadError::io(&path, err));
+                    continue;
+                }
+            };
+            if file_type.is_dir() {
+                subdirs.push(path);
+            } else if file_type.is_symlink() && path.is_dir() {
+                if self.follow_symlinks {
+                    subdirs.push(path);
+                }
+            } else {
+                files.push(path);
+            }
+        }
+
+        if let Some(max_depth) = self.max_depth {
+            if depth >= max_depth {
+                return Ok(());
+            }
+        }
+        for subdir in subdirs {
+            let canonical = match fs::canonicalize(&subdir) {
+                Ok(canonical) => canonical,
+                Err(err) => {
+                    errors.push(LoadError::io(&subdir, err));
+                    continue;
+                }
+            };
+            if !visited.insert(canonical) {
+                continue;
+            }
+            if let Err(err) = self.walk_dir(&subdir, depth + 1, visited, files, errors) {
+                errors.push(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory tree under the temp directory, removed when dropped
+    struct Tree(PathBuf);
+
+    impl Tree {
+        fn new(name: &str, files: &[&str]) -> Tree {
+            let root = std::env::temp_dir().join(format!(
+                "image_grid_test_{}_{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&root);
+            for file in files {
+                let path = root.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, b"").unwrap();
+            }
+            Tree(root)
+        }
+
+        // The files found, relative to the root and sorted
+        fn walk(&self, walker: &DirWalker) -> Vec<String> {
+            let (files, errors) = walker.walk(&self.0).unwrap();
+            assert!(errors.is_empty(), "{:?}", errors);
+            let mut files: Vec<String> = files
+                .iter()
+                .map(|file| file.strip_prefix(&self.0).unwrap().display().to_string())
+                .collect();
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const FILES: &[&str] = &[
+        "a.png",
+        ".b.png",
+        "sub/c.png",
+        "sub/deeper/d.png",
+        ".hidden/e.png",
+    ];
+
+    #[test]
+    fn depth() {
+        let tree = Tree::new("depth", FILES);
+        let mut walker = DirWalker::new();
+        assert_eq!(tree.walk(&walker), [".b.png", "a.png"]);
+        walker.recursive(Some(1));
+        assert_eq!(
+            tree.walk(&walker),
+            [".b.png", ".hidden/e.png", "a.png", "sub/c.png"]
+        );
+        walker.recursive(None);
+        assert_eq!(
+            tree.walk(&walker),
+            [
+                ".b.png",
+                ".hidden/e.png",
+                "a.png",
+                "sub/c.png",
+                "sub/deeper/d.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn files_of_a_directory_come_first() {
+        let tree = Tree::new(
+            "order",
+            &["sub/b.png", "a.png", "sub/deeper/c.png", "z.png"],
+        );
+        let mut walker = DirWalker::new();
+        walker.recursive(None);
+        let (files, _) = walker.walk(&tree.0).unwrap();
+        let depth = |file: &PathBuf| file.strip_prefix(&tree.0).unwrap().components().count();
+        let depths: Vec<usize> = files.iter().map(depth).collect();
+        assert_eq!(depths, [1, 1, 2, 3]);
+    }
+
+    #[test]
+    fn hidden() {
+        let tree = Tree::new("hidden", FILES);
+        let mut walker = DirWalker::new();
+        walker.recursive(None);
+        walker.include_hidden(false);
+        assert_eq!(
+            tree.walk(&walker),
+            ["a.png", "sub/c.png", "sub/deeper/d.png"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        use std::os::unix::fs::symlink;
+        let tree = Tree::new("symlinks", &["a.png", "sub/b.png"]);
+        let outside = Tree::new("symlinks_outside", &["c.png"]);
+        symlink(&outside.0, tree.0.join("link")).unwrap();
+        // Both point back up the tree, so following them would never end
+        symlink(&tree.0, tree.0.join("sub/loop")).unwrap();
+        symlink("..", tree.0.join("sub/parent")).unwrap();
+        let mut walker = DirWalker::new();
+        walker.recursive(None);
+        assert_eq!(tree.walk(&walker), ["a.png", "sub/b.png"]);
+        walker.follow_symlinks(true);
+        assert_eq!(tree.walk(&walker), ["a.png", "link/c.png", "sub/b.png"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_subdirectories_are_returned_as_errors() {
+        use std::os::unix::fs::PermissionsExt;
+        let tree = Tree::new("errors", &["a.png", "locked/b.png", "open/c.png"]);
+        let locked = tree.0.join("locked");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        let readable = locked.read_dir().is_ok();
+        let mut walker = DirWalker::new();
+        walker.recursive(None);
+        let (files, errors) = walker.walk(&tree.0).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        // Permissions do not apply to root
+        if readable {
+            return;
+        }
+        assert_eq!(files.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path(), Some(locked.as_path()));
+    }
+
+    #[test]
+    fn missing_root() {
+        let tree = Tree::new("missing", &[]);
+        assert!(DirWalker::new().walk(&tree.0.join("nothing")).is_err());
+    }
+}
@@ -0,0 +1,636 @@
+// Minimal access to the EXIF data the image crate does not read: the
+// orientation tag, and the fields the info panel shows. The EXIF data is found
+// in the APP1 segment of JPEG files, the eXIf chunk of PNG files and the EXIF
+// chunk of WebP files, and is a small TIFF file of its own. TIFF files are
+// read directly.
+
+use crate::png_text;
+use std::ops::Range;
+
+const ORIENTATION: u16 = 0x0112;
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+
+// TIFF value types
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+
+/// Where the orientation tag is stored in a file, so that it can be changed
+/// without touching anything else.
+#[derive(Clone, Copy, Debug)]
+pub struct OrientationTag {
+    /// The EXIF orientation, from 1 to 8.
+    pub value: u16,
+    // Offset of the value in the file
+    offset: usize,
+    big_endian: bool,
+}
+
+/// Finds the orientation tag in a JPEG, PNG, WebP or TIFF file.
+pub fn orientation(contents: &[u8]) -> Option<OrientationTag> {
+    let tiff = Tiff::find(contents)?;
+    // Only the first image file directory describes the image itself
+    let entry = tiff
+        .entries(tiff.first_ifd()?)
+        .into_iter()
+        .find(|entry| entry.tag == ORIENTATION && entry.kind == SHORT)?;
+    Some(OrientationTag {
+        value: tiff.read16(entry.value)?,
+        offset: entry.value,
+        big_endian: tiff.big_endian,
+    })
+}
+
+/// Changes the orientation in place. The file is otherwise left as it was,
+/// apart from the checksum of the chunk holding the tag in PNG files.
+pub fn set_orientation(contents: &mut [u8], tag: &OrientationTag, value: u16) {
+    let bytes = if tag.big_endian {
+        value.to_be_bytes()
+    } else {
+        value.to_le_bytes()
+    };
+    contents[tag.offset..tag.offset + 2].copy_from_slice(&bytes);
+    let chunk = png_text::chunks(contents)
+        .into_iter()
+        .find(|chunk| &chunk.kind == b"eXIf")
+        .map(|chunk| (chunk.offset, chunk.data.len()));
+    if let Some((offset, length)) = chunk {
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&contents[offset + 4..offset + 8 + length]);
+        let end = offset + 8 + length;
+        contents[end..end + 4].copy_from_slice(&crc.finalize().to_be_bytes());
+    }
+}
+
+/// The camera settings, date and location a photo was taken with, as labels
+/// and values for people to read. Fields the file does not have are left out.
+pub fn fields(contents: &[u8]) -> Vec<(&'static str, String)> {
+    let tiff = match Tiff::find(contents) {
+        Some(tiff) => tiff,
+        None => return Vec::new(),
+    };
+    let image = tiff
+        .first_ifd()
+        .map_or_else(Vec::new, |ifd| tiff.entries(ifd));
+    let sub_ifd = |tag: u16| {
+        image
+            .iter()
+            .find(|entry| entry.tag == tag)
+            .and_then(|entry| tiff.unsigned(entry))
+            .and_then(|offset| tiff.start.checked_add(offset as usize))
+            .map_or_else(Vec::new, |ifd| tiff.entries(ifd))
+    };
+    let photo = sub_ifd(EXIF_IFD);
+    let gps = sub_ifd(GPS_IFD);
+    let find = |entries: &[Entry], tag: u16| entries.iter().find(|entry| entry.tag == tag).cloned();
+    let text = |entries: &[Entry], tag: u16| find(entries, tag).and_then(|entry| tiff.text(&entry));
+    let number = |entries: &[Entry], tag: u16| {
+        let entry = find(entries, tag)?;
+        match entry.kind {
+            RATIONAL => tiff.rational(entry.value, 0),
+            _ => tiff.unsigned(&entry).map(|value| value as f64),
+        }
+    };
+
+    let mut fields = Vec::new();
+    let make = text(&image, 0x010F);
+    let model = text(&image, 0x0110);
+    let camera = match (make, model) {
+        // Most models already start with the make
+        (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+        (make, model) => make.or(model),
+    };
+    if let Some(camera) = camera {
+        fields.push(("Camera", camera));
+    }
+    if let Some(lens) = text(&photo, 0xA434) {
+        fields.push(("Lens", lens));
+    }
+    if let Some(date) = text(&photo, 0x9003).or_else(|| text(&image, 0x0132)) {
+        fields.push(("Taken", date));
+    }
+    if let Some(exposure) = number(&photo, 0x829A).filter(|e| *e > 0.0) {
+        let exposure = if exposure < 1.0 {
+            format!("1/{:.0} s", 1.0 / exposure)
+        } else {
+            format!("{} s", exposure)
+        };
+        fields.push(("Exposure", exposure));
+    }
+    if let Some(aperture) = number(&photo, 0x829D) {
+        fields.push(("Aperture", format!("f/{:.1}", aperture)));
+    }
+    if let Some(iso) = number(&photo, 0x8827) {
+        fields.push(("ISO", format!("{}", iso)));
+    }
+    if let Some(focal_length) = number(&photo, 0x920A) {
+        fields.push(("Focal length", format!("{:.0} mm", focal_length)));
+    }
+    if let Some(software) = text(&image, 0x0131) {
+        fields.push(("Software", software));
+    }
+    // Degrees, minutes and seconds, along with N or S and E or W
+    let coordinate = |reference: u16, tag: u16| {
+        let entry = find(&gps, tag).filter(|entry| entry.kind == RATIONAL && entry.count == 3)?;
+        let degrees = tiff.rational(entry.value, 0)?
+            + tiff.rational(entry.value, 1)? / 60.0
+            + tiff.rational(entry.value, 2)? / 3600.0;
+        Some(format!("{:.6}° {}", degrees, text(&gps, reference)?))
+    };
+    if let (Some(latitude), Some(longitude)) = (coordinate(1, 2), coordinate(3, 4)) {
+        fields.push(("Location", format!("{}, {}", latitude, longitude)));
+    }
+    if let Some(altitude) = number(&gps, 6) {
+        // A reference of 1 means below sea level
+        let below = find(&gps, 5).is_some_and(|entry| contents.get(entry.value) == Some(&1));
+        let altitude = if below { -altitude } else { altitude };
+        fields.push(("Altitude", format!("{:.0} m", altitude)));
+    }
+    fields
+}
+
+// An entry of an image file directory
+#[derive(Clone, Copy)]
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    // Offset of the value in the file. Values that do not fit in the entry
+    // itself are stored elsewhere, and this is where they are.
+    value: usize,
+}
+
+struct Tiff<'a> {
+    contents: &'a [u8],
+    // Offset of the TIFF header, which offsets within the TIFF data are
+    // relative to
+    start: usize,
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn find(contents: &'a [u8]) -> Option<Tiff<'a>> {
+        let start = tiff_offset(contents)?;
+        let big_endian = match contents.get(start..start + 2)? {
+            b"II" => false,
+            b"MM" => true,
+            _ => return None,
+        };
+        Some(Tiff {
+            contents,
+            start,
+            big_endian,
+        })
+    }
+
+    fn read16(&self, offset: usize) -> Option<u16> {
+        let bytes = [*self.contents.get(offset)?, *self.contents.get(offset + 1)?];
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn read32(&self, offset: usize) -> Option<u32> {
+        let first = self.read16(offset)? as u32;
+        let second = self.read16(offset + 2)? as u32;
+        Some(if self.big_endian {
+            first << 16 | second
+        } else {
+            second << 16 | first
+        })
+    }
+
+    fn first_ifd(&self) -> Option<usize> {
+        self.start
+            .checked_add(self.read32(self.start + 4)? as usize)
+    }
+
+    fn entries(&self, ifd: usize) -> Vec<Entry> {
+        let count = self.read16(ifd).unwrap_or(0) as usize;
+        (0..count)
+            .map_while(|n| {
+                let entry = ifd + 2 + n * 12;
+                let kind = self.read16(entry + 2)?;
+                let count = self.read32(entry + 4)?;
+                let size = match kind {
+                    SHORT => 2,
+                    LONG => 4,
+                    RATIONAL => 8,
+                    _ => 1,
+                };
+                let value = if (size * count as u64) <= 4 {
+                    entry + 8
+                } else {
+                    self.start.checked_add(self.read32(entry + 8)? as usize)?
+                };
+                Some(Entry {
+                    tag: self.read16(entry)?,
+                    kind,
+                    count,
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    fn unsigned(&self, entry: &Entry) -> Option<u32> {
+        match entry.kind {
+            SHORT => self.read16(entry.value).map(u32::from),
+            LONG => self.read32(entry.value),
+            _ => None,
+        }
+    }
+
+    fn rational(&self, offset: usize, index: usize) -> Option<f64> {
+        let offset = offset + index * 8;
+        let denominator = self.read32(offset + 4)?;
+        if denominator == 0 {
+            return None;
+        }
+        Some(self.read32(offset)? as f64 / denominator as f64)
+    }
+
+    // Strings end with a NUL, and are often padded with spaces
+    fn text(&self, entry: &Entry) -> Option<String> {
+        if entry.kind != ASCII {
+            return None;
+        }
+        let bytes = self
+            .contents
+            .get(entry.value..entry.value.checked_add(entry.count as usize)?)?;
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        let text = String::from_utf8_lossy(&bytes[..end]).trim().to_string();
+        Some(text).filter(|text| !text.is_empty())
+    }
+}
+
+// The offset of the TIFF header inside the file
+fn tiff_offset(contents: &[u8]) -> Option<usize> {
+    match contents {
+        [0xFF, 0xD8, ..] => jpeg_tiff_offset(contents),
+        [137, b'P', b'N', b'G', ..] => png_text::chunks(contents)
+            .iter()
+            .find(|chunk| &chunk.kind == b"eXIf")
+            .map(|chunk| chunk.offset + 8),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+            webp_tiff_offset(contents)
+        }
+        [b'I', b'I', 42, 0, ..] | [b'M', b'M', 0, 42, ..] => Some(0),
+        _ => None,
+    }
+}
+
+fn jpeg_tiff_offset(contents: &[u8]) -> Option<usize> {
+    jpeg_segments(contents)
+        .into_iter()
+        .find(|(marker, data)| *marker == 0xE1 && contents[data.clone()].starts_with(b"Exif\0\0"))
+        .map(|(_marker, data)| data.start + 6)
+}
+
+/// The marker of each JPEG segment before the image data, along with where
+/// its data is in the file.
+pub fn jpeg_segments(contents: &[u8]) -> Vec<(u8, Range<usize>)> {
+    let mut segments = Vec::new();
+    let mut offset = 2;
+    while offset + 4 <= contents.len() && contents[offset] == 0xFF {
+        let marker = contents[offset + 1];
+        match marker {
+            // Padding before a marker
+            0xFF => {
+                offset += 1;
+                continue;
+            }
+            // Markers without a length
+            0x01 | 0xD0..=0xD8 => {
+                offset += 2;
+                continue;
+            }
+            // The image data starts, and the metadata is over
+            0xD9 | 0xDA => break,
+            _ => {}
+        }
+        let length = u16::from_be_bytes([contents[offset + 2], contents[offset + 3]]) as usize;
+        let end = (offset + 2 + length).min(contents.len());
+        segments.push((marker, (offset + 4).min(end)..end));
+        offset += 2 + length;
+    }
+    segments
+}
+
+fn webp_tiff_offset(contents: &[u8]) -> Option<usize> {
+    let mut offset = 12;
+    while offset + 8 <= contents.len() {
+        let length = u32::from_le_bytes([
+            contents[offset + 4],
+            contents[offset + 5],
+            contents[offset + 6],
+            contents[offset + 7],
+        ]) as usize;
+        let data = offset + 8;
+        if &contents[offset..offset + 4] == b"EXIF" {
+            // Some encoders keep the JPEG style prefix
+            return match contents.get(data..data + 6) {
+                Some(b"Exif\0\0") => Some(data + 6),
+                _ => Some(data),
+            };
+        }
+        offset = data.checked_add(length)? + length % 2;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BYTE: u16 = 1;
+
+    enum Value {
+        Byte(u8),
+        Ascii(&'static str),
+        Short(u16),
+        Rationals(Vec<(u32, u32)>),
+        // The offset of another directory
+        Ifd(usize),
+    }
+
+    // A TIFF file in either byte order with the given image file directories,
+    // the first of which describes the image. Values that do not fit in their
+    // entry follow the directories.
+    fn tiff(big_endian: bool, ifds: &[Vec<(u16, Value)>]) -> Vec<u8> {
+        let u16_bytes = |value: u16| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut offsets = Vec::new();
+        let mut end = 8;
+        for ifd in ifds {
+            offsets.push(end as u32);
+            end += 2 + ifd.len() * 12 + 4;
+        }
+        let mut tiff = if big_endian {
+            b"MM\0\x2a".to_vec()
+        } else {
+            b"II\x2a\0".to_vec()
+        };
+        tiff.extend_from_slice(&u32_bytes(8));
+        let mut data = Vec::new();
+        for ifd in ifds {
+            tiff.extend_from_slice(&u16_bytes(ifd.len() as u16));
+            for (tag, value) in ifd {
+                let (kind, count, mut bytes) = match value {
+                    Value::Byte(value) => (BYTE, 1, vec![*value]),
+                    Value::Ascii(text) => {
+                        (ASCII, text.len() + 1, [text.as_bytes(), b"\0"].concat())
+                    }
+                    Value::Short(value) => (SHORT, 1, u16_bytes(*value).to_vec()),
+                    Value::Rationals(values) => (
+                        RATIONAL,
+                        values.len(),
+                        values
+                            .iter()
+                            .flat_map(|(numerator, denominator)| {
+                                [u32_bytes(*numerator), u32_bytes(*denominator)].concat()
+                            })
+                            .collect(),
+                    ),
+                    Value::Ifd(n) => (LONG, 1, u32_bytes(offsets[*n]).to_vec()),
+                };
+                tiff.extend_from_slice(&u16_bytes(*tag));
+                tiff.extend_from_slice(&u16_bytes(kind));
+                tiff.extend_from_slice(&u32_bytes(count as u32));
+                if bytes.len() <= 4 {
+                    bytes.resize(4, 0);
+                    tiff.extend_from_slice(&bytes);
+                } else {
+                    tiff.extend_from_slice(&u32_bytes((end + data.len()) as u32));
+                    data.extend_from_slice(&bytes);
+                }
+            }
+            tiff.extend_from_slice(&u32_bytes(0));
+        }
+        tiff.extend_from_slice(&data);
+        tiff
+    }
+
+    fn photo(big_endian: bool) -> Vec<u8> {
+        tiff(
+            big_endian,
+            &[
+                vec![
+                    (0x010F, Value::Ascii("Canon")),
+                    (0x0110, Value::Ascii("Canon EOS 5D")),
+                    (ORIENTATION, Value::Short(6)),
+                    (0x0131, Value::Ascii("GIMP")),
+                    (EXIF_IFD, Value::Ifd(1)),
+                    (GPS_IFD, Value::Ifd(2)),
+                ],
+                vec![
+                    (0x829A, Value::Rationals(vec![(1, 250)])),
+                    (0x829D, Value::Rationals(vec![(28, 10)])),
+                    (0x8827, Value::Short(400)),
+                    (0x9003, Value::Ascii("2020:01:02 03:04:05")),
+                    (0x920A, Value::Rationals(vec![(50, 1)])),
+                    // Padded with spaces
+                    (0xA434, Value::Ascii("EF50mm f/1.8   ")),
+                ],
+                vec![
+                    (1, Value::Ascii("N")),
+                    (2, Value::Rationals(vec![(52, 1), (30, 1), (0, 1)])),
+                    (3, Value::Ascii("W")),
+                    (4, Value::Rationals(vec![(13, 1), (24, 1), (36, 1)])),
+                    (5, Value::Byte(1)),
+                    (6, Value::Rationals(vec![(100, 1)])),
+                ],
+            ],
+        )
+    }
+
+    fn photo_fields() -> Vec<(&'static str, String)> {
+        [
+            ("Camera", "Canon EOS 5D"),
+            ("Lens", "EF50mm f/1.8"),
+            ("Taken", "2020:01:02 03:04:05"),
+            ("Exposure", "1/250 s"),
+            ("Aperture", "f/2.8"),
+            ("ISO", "400"),
+            ("Focal length", "50 mm"),
+            ("Software", "GIMP"),
+            ("Location", "52.500000° N, 13.410000° W"),
+            ("Altitude", "-100 m"),
+        ]
+        .iter()
+        .map(|(label, value)| (*label, value.to_string()))
+        .collect()
+    }
+
+    fn segment(jpeg: &mut Vec<u8>, marker: u8, data: &[u8]) {
+        jpeg.extend_from_slice(&[0xFF, marker]);
+        jpeg.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+        jpeg.extend_from_slice(data);
+    }
+
+    fn jpeg(tiff: &[u8]) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        segment(&mut jpeg, 0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        segment(&mut jpeg, 0xE1, &[b"Exif\0\0", tiff].concat());
+        // Fill bytes may come before a marker
+        jpeg.push(0xFF);
+        segment(&mut jpeg, 0xE2, b"ICC_PROFILE\0\x01\x01");
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0, 2, 0xFF, 0xE1, 0, 2]);
+        jpeg
+    }
+
+    fn webp(exif: &[u8]) -> Vec<u8> {
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0\x08\0\0\0\0\0\0\0\0\0".to_vec();
+        // An odd length, which is padded
+        webp.extend_from_slice(b"ICCP\x01\0\0\0\0\0");
+        webp.extend_from_slice(b"EXIF");
+        webp.extend_from_slice(&(exif.len() as u32).to_le_bytes());
+        webp.extend_from_slice(exif);
+        webp
+    }
+
+    #[test]
+    fn tiff_fields() {
+        for big_endian in [false, true] {
+            let tiff = photo(big_endian);
+            assert_eq!(fields(&tiff), photo_fields());
+            assert_eq!(orientation(&tiff).unwrap().value, 6);
+        }
+    }
+
+    #[test]
+    fn fields_that_are_left_out_or_combined() {
+        let tiff = tiff(
+            false,
+            &[vec![
+                (0x010F, Value::Ascii("NIKON")),
+                (0x0110, Value::Ascii("D750")),
+                (0x0132, Value::Ascii("2021:05:06 07:08:09")),
+                // Sub-IFDs that point past the end of the file
+                (EXIF_IFD, Value::Short(0xFFFF)),
+                (GPS_IFD, Value::Short(0xFFF0)),
+                // Not a SHORT, so not the orientation
+                (ORIENTATION, Value::Ascii("6")),
+            ]],
+        );
+        assert_eq!(
+            fields(&tiff),
+            vec![
+                ("Camera", "NIKON D750".to_string()),
+                ("Taken", "2021:05:06 07:08:09".to_string())
+            ]
+        );
+        assert!(orientation(&tiff).is_none());
+    }
+
+    #[test]
+    fn rationals() {
+        let exposure = |numerator, denominator| {
+            let tiff = tiff(
+                true,
+                &[
+                    vec![(EXIF_IFD, Value::Ifd(1))],
+                    vec![(0x829A, Value::Rationals(vec![(numerator, denominator)]))],
+                ],
+            );
+            fields(&tiff)
+                .into_iter()
+                .map(|(_label, value)| value)
+                .next()
+        };
+        assert_eq!(exposure(1, 60), Some("1/60 s".to_string()));
+        assert_eq!(exposure(10, 4), Some("2.5 s".to_string()));
+        assert_eq!(exposure(0, 1), None);
+        assert_eq!(exposure(1, 0), None);
+    }
+
+    #[test]
+    fn embedded_exif() {
+        let tiff = photo(true);
+        let mut png = png_text::SIGNATURE.to_vec();
+        png_text::write_chunk(&mut png, b"IHDR", &[0; 13]);
+        png_text::write_chunk(&mut png, b"eXIf", &tiff);
+        let files = [
+            jpeg(&tiff),
+            png,
+            webp(&tiff),
+            webp(&[b"Exif\0\0", &tiff[..]].concat()),
+        ];
+        for file in &files {
+            assert_eq!(fields(file), photo_fields());
+            assert_eq!(orientation(file).unwrap().value, 6);
+        }
+    }
+
+    #[test]
+    fn jpeg_segments_stop_at_the_image_data() {
+        let jpeg = jpeg(&photo(false));
+        let markers: Vec<u8> = jpeg_segments(&jpeg)
+            .into_iter()
+            .map(|(marker, _data)| marker)
+            .collect();
+        assert_eq!(markers, [0xE0, 0xE1, 0xE2]);
+        let (_marker, data) = jpeg_segments(&jpeg).pop().unwrap();
+        assert_eq!(&jpeg[data], b"ICC_PROFILE\0\x01\x01");
+        // A segment longer than the file ends with it
+        let truncated = &jpeg[..jpeg.len() - 40];
+        assert_eq!(jpeg_segments(truncated).len(), 2);
+    }
+
+    #[test]
+    fn set_orientation_changes_only_the_tag() {
+        let mut jpeg = jpeg(&photo(false));
+        let before = jpeg.clone();
+        let tag = orientation(&jpeg).unwrap();
+        set_orientation(&mut jpeg, &tag, 3);
+        assert_eq!(orientation(&jpeg).unwrap().value, 3);
+        let changed = (0..jpeg.len()).filter(|i| jpeg[*i] != before[*i]).count();
+        assert_eq!(changed, 1);
+
+        // The checksum of the PNG chunk follows
+        let mut png = png_text::SIGNATURE.to_vec();
+        png_text::write_chunk(&mut png, b"eXIf", &photo(true));
+        png_text::write_chunk(&mut png, b"IEND", &[]);
+        let tag = orientation(&png).unwrap();
+        set_orientation(&mut png, &tag, 8);
+        assert_eq!(orientation(&png).unwrap().value, 8);
+        let chunk = png_text::chunks(&png).remove(0);
+        let end = chunk.offset + 8 + chunk.data.len();
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&png[chunk.offset + 4..end]);
+        assert_eq!(png[end..end + 4], crc.finalize().to_be_bytes());
+    }
+
+    #[test]
+    fn broken_files() {
+        let tiff = photo(false);
+        for end in 0..tiff.len() {
+            // Whatever is cut off is left out
+            fields(&tiff[..end]);
+            orientation(&tiff[..end]);
+        }
+        // A directory claiming more entries than there are
+        let mut tiff = tiff;
+        tiff[8..10].copy_from_slice(&0xFFFFu16.to_le_bytes());
+        fields(&tiff);
+        assert!(fields(b"II\x2a\0\xff\xff\xff\xff").is_empty());
+        assert!(fields(b"not an image").is_empty());
+    }
+}
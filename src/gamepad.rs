@@ -0,0 +1,103 @@
+use gilrs::{Axis, Button, EventType};
+use piston::input::keyboard::Key;
+
+// How far a stick has to be pushed before it moves the selection, and how far
+// back it has to come before it can move it again.
+const STICK_PRESS: f32 = 0.6;
+const STICK_RELEASE: f32 = 0.3;
+
+/// The parts of a gamepad event the grid cares about. Events can be created
+/// directly, which is how gamepad input can be simulated without a device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GamepadEvent {
+    ButtonPressed(Button),
+    ButtonReleased(Button),
+    /// The value is between -1.0 and 1.0, with up and right being positive.
+    AxisChanged(Axis, f32),
+}
+
+impl GamepadEvent {
+    pub fn from_gilrs(event: &EventType) -> Option<GamepadEvent> {
+        match *event {
+            EventType::ButtonPressed(button, _) => Some(GamepadEvent::ButtonPressed(button)),
+            EventType::ButtonReleased(button, _) => Some(GamepadEvent::ButtonReleased(button)),
+            EventType::AxisChanged(axis, value, _) => Some(GamepadEvent::AxisChanged(axis, value)),
+            _ => None,
+        }
+    }
+}
+
+// Turns gamepad events into the keys they stand for, so that a gamepad works
+// everywhere the keyboard does:
+//
+// D-pad and left stick: arrow keys
+// A (south): Return
+// B (east): Escape
+// shoulder buttons: Page Up and Page Down
+// triggers: - and = to zoom out and in
+#[derive(Default)]
+pub struct Gamepad {
+    // The direction each stick axis is held in: -1, 0 or 1
+    x: i8,
+    y: i8,
+}
+
+impl Gamepad {
+    pub fn new() -> Gamepad {
+        Gamepad::default()
+    }
+
+    /// The key the event stands for, if any. A stick only produces a key when
+    /// it is first pushed in a direction.
+    pub fn key(&mut self, event: GamepadEvent) -> Option<Key> {
+        match event {
+            GamepadEvent::ButtonPressed(button) => button_key(button),
+            GamepadEvent::ButtonReleased(_) => None,
+            GamepadEvent::AxisChanged(Axis::LeftStickX, value)
+            | GamepadEvent::AxisChanged(Axis::DPadX, value) => stick_direction(&mut self.x, value)
+                .map(|d| if d > 0 { Key::Right } else { Key::Left }),
+            GamepadEvent::AxisChanged(Axis::LeftStickY, value)
+            | GamepadEvent::AxisChanged(Axis::DPadY, value) => {
+                stick_direction(&mut self.y, value).map(|d| if d > 0 { Key::Up } else { Key::Down })
+            }
+            GamepadEvent::AxisChanged(_, _) => None,
+        }
+    }
+}
+
+fn button_key(button: Button) -> Option<Key> {
+    match button {
+        Button::DPadUp => Some(Key::Up),
+        Button::DPadDown => Some(Key::Down),
+        Button::DPadLeft => Some(Key::Left),
+        Button::DPadRight => Some(Key::Right),
+        Button::South => Some(Key::Return),
+        Button::East => Some(Key::Escape),
+        Button::LeftTrigger => Some(Key::PageUp),
+        Button::RightTrigger => Some(Key::PageDown),
+        Button::LeftTrigger2 => Some(Key::Minus),
+        Button::RightTrigger2 => Some(Key::Equals),
+        _ => None,
+    }
+}
+
+// Returns the new direction when the stick is pushed past the press threshold
+// in a direction it was not already held in.
+fn stick_direction(held: &mut i8, value: f32) -> Option<i8> {
+    if value.abs() < STICK_RELEASE {
+        *held = 0;
+        return None;
+    }
+    let direction = if value >= STICK_PRESS {
+        1
+    } else if value <= -STICK_PRESS {
+        -1
+    } else {
+        return None;
+    };
+    if direction == *held {
+        return None;
+    }
+    *held = direction;
+    Some(direction)
+}
@@ -1,30 +1,191 @@
+use crate::animation::{Animate, Playback};
+use crate::gamepad::{Gamepad, GamepadEvent};
+use crate::keymap::{self, Action, Chord, Keymap};
+use crate::layout::GridLayout;
+use crate::orientation::Orientation;
+use crate::placeholder::Placeholder;
+use crate::render::ImageTexture;
+use crate::search::{Search, SearchMode};
+use crate::selection::Selection;
+use crate::slideshow::{Slideshow, SlideshowSettings};
+use crate::software::SoftwareGraphics;
+use crate::text::TextRenderer;
+use crate::zoom::{ZoomMode, ZoomView};
 use anyhow::Error;
+use gilrs::Gilrs;
 use glutin_window::GlutinWindow as Window;
-use graphics::math::Matrix2d;
-use graphics::{DrawState, Image, ImageSize, Transformed};
-use opengl_graphics::{GlGraphics, Texture};
+use graphics::math::{self, Matrix2d};
+use graphics::types::FontSize;
+use graphics::{Context, DrawState, Graphics, Image, ImageSize, Transformed};
+use image::RgbaImage;
+use opengl_graphics::GlGraphics;
 use piston::event_loop::*;
 use piston::input::{
     keyboard::{Key, ModifierKey},
     mouse::MouseButton,
     Button, MouseCursorEvent, MouseScrollEvent, PressEvent, ReleaseEvent, RenderArgs, RenderEvent,
+    TextEvent, UpdateEvent,
 };
-use piston::window::AdvancedWindow;
+use piston::window::{AdvancedWindow, Window as _};
 use std::cmp::{max, min};
+use std::collections::HashSet;
+use std::time::Instant;
 
 pub type GridResult<T> = Result<T, Error>;
 
+// How quickly smooth scrolling catches up: the remaining distance shrinks by
+// a factor of e this many times per second.
+const SCROLL_SPEED: f64 = 15.0;
+
+// How far one step of the mouse wheel scrolls, in rows
+const WHEEL_ROWS: f64 = 0.5;
+
+const UPDATES_PER_SECOND: u64 = 30;
+
+// How much one zoom step, by key or mouse wheel, magnifies the fullscreen tile
+const ZOOM_STEP: f64 = 1.25;
+
+const CAPTION_FONT_SIZE: FontSize = 11;
+// For the status bar, the position indicator and the help
+const OVERLAY_FONT_SIZE: FontSize = 13;
+
 pub type Color = [f32; 4];
 
-pub trait TileHandler {
+pub trait TileHandler<G: Graphics> {
     fn window_title(&self) -> String;
 
     fn tiles(&self) -> &Vec<usize>;
 
-    fn tile(&self, i: usize) -> &Texture;
+    /// Returns None while the tile is still loading, or when it has no image.
+    fn tile(&self, i: usize) -> Option<&G::Texture>;
+
+    /// What is marked on tile `i` while `tile` returns None for it.
+    fn placeholder(&self, _i: usize) -> Placeholder {
+        Placeholder::Loading
+    }
+
+    /// The full resolution version of the tile, if the handler has one. Falls
+    /// back to the tile itself.
+    fn full_tile(&self, i: usize) -> Option<&G::Texture> {
+        self.tile(i)
+    }
+
+    /// Called before every frame so the handler can pick up tiles that have
+    /// finished loading in the background.
+    fn update(&mut self) {}
+
+    /// Whether tiles are still loading in the background. Frames keep being
+    /// drawn while they are, so that `update` is called and they show up.
+    fn busy(&self) -> bool {
+        false
+    }
+
+    /// Called before every frame in which tile `i` is shown fullscreen, giving
+    /// the handler a chance to load the full resolution image.
+    fn fullscreen(&mut self, _i: usize) {}
+
+    /// Called with the tile that is shown fullscreen next, such as the next
+    /// one in a slideshow, so its full resolution image can be loaded ahead
+    /// of time.
+    fn prefetch(&mut self, _i: usize) {}
+
+    /// Called when the grid starts playing tile `i`, so the handler can load
+    /// its frames if it is animated.
+    fn start_animation(&mut self, _i: usize) {}
+
+    /// Called when the grid stops playing tile `i`. The handler may drop its
+    /// frames and should show the tile as it was before.
+    fn stop_animation(&mut self, _i: usize) {}
+
+    /// The number of frames of tile `i` that are ready to be played.
+    fn frame_count(&self, _i: usize) -> usize {
+        1
+    }
+
+    /// Seconds that frame `frame` of tile `i` is shown for.
+    fn frame_delay(&self, _i: usize, _frame: usize) -> f64 {
+        0.1
+    }
+
+    /// Makes `tile` and `full_tile` return frame `frame` of tile `i` from now
+    /// on.
+    fn show_frame(&mut self, _i: usize, _frame: usize) {}
+
+    /// How tile `i` is turned when it is drawn, on top of how its texture is.
+    fn orientation(&self, _i: usize) -> Orientation {
+        Orientation::default()
+    }
+
+    /// Called when the user rotates or flips tile `i`. Handlers that do not
+    /// store the orientation cannot be turned.
+    fn set_orientation(&mut self, _i: usize, _orientation: Orientation) {}
+
+    /// Whether tile `i` matches the search, such as by its path.
+    fn matches(&self, _i: usize, _search: &Search) -> bool {
+        false
+    }
+
+    /// Narrows `tiles` to the tiles that match the search, including any that
+    /// are added later, or shows every tile again when it is None.
+    fn filter(&mut self, _search: Option<&Search>) {}
 
     fn act(&mut self, _i: usize) {}
 
+    /// Called when the user confirms a selection of one or more tiles, in the
+    /// order they are shown. Acts on each tile by default.
+    fn act_on_selection(&mut self, tiles: &[usize]) {
+        for i in tiles {
+            self.act(*i);
+        }
+    }
+
+    /// Returning true closes the grid, for example once the handler has
+    /// everything it needs from the user. Checked after every event.
+    fn done(&self) -> bool {
+        false
+    }
+
+    /// Tiles with the same group, such as images from the same directory, are
+    /// moved between as a unit. They do not need to be next to each other in
+    /// `tiles`, for example once the tiles are sorted by date.
+    fn group(&self, _i: usize) -> Option<String> {
+        None
+    }
+
+    /// A line of text shown below the tile, such as its file name. Captions
+    /// are only shown if the first tile has one.
+    fn caption(&self, _i: usize) -> Option<String> {
+        None
+    }
+
+    fn caption_color(&self, _i: usize) -> Color {
+        [1.0, 1.0, 1.0, 0.8]
+    }
+
+    /// Information about tile `i` for the status bar, which is shown while
+    /// the tile is selected. Text after a tab is aligned to the right edge of
+    /// the bar. The bar is hidden when this returns None.
+    fn status_text(&self, _i: usize) -> Option<String> {
+        None
+    }
+
+    /// Details of tile `i` for the info panel, as labels and values. Values
+    /// are wrapped to fit the panel, and may span several lines of their own.
+    /// This is asked for on every frame while the panel is shown, so details
+    /// that take a while to read should be read elsewhere and added once
+    /// they are in.
+    fn metadata(&self, _i: usize) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    fn status_bar_color(&self) -> Color {
+        [1.0, 1.0, 1.0, 0.9]
+    }
+
+    fn status_bar_background_color(&self) -> Color {
+        [0.0, 0.0, 0.0, 0.6]
+    }
+
     fn highlight_color(&self, _i: usize) -> Color {
         [1.0, 1.0, 1.0, 1.0]
     }
@@ -33,13 +194,66 @@ pub trait TileHandler {
         [0.1, 0.2, 0.3, 1.0]
     }
 
+    fn placeholder_color(&self, _i: usize) -> Color {
+        [0.2, 0.3, 0.4, 1.0]
+    }
+
+    /// The color of the spinner, broken image or label on placeholders.
+    fn placeholder_marker_color(&self, _i: usize) -> Color {
+        [1.0, 1.0, 1.0, 0.5]
+    }
+
+    /// Shown around the fullscreen tile and through its transparent parts.
+    fn fullscreen_background_color(&self) -> Color {
+        [0.05, 0.05, 0.05, 1.0]
+    }
+
+    fn scrollbar_color(&self) -> Color {
+        [1.0, 1.0, 1.0, 0.5]
+    }
+
+    fn scrollbar_track_color(&self) -> Color {
+        [0.0, 0.0, 0.0, 0.2]
+    }
+
+    /// The color of the "123 / 4567" position indicator, and of the zoom
+    /// level shown in fullscreen.
+    fn indicator_color(&self) -> Color {
+        [1.0, 1.0, 1.0, 0.9]
+    }
+
+    fn indicator_background_color(&self) -> Color {
+        [0.0, 0.0, 0.0, 0.5]
+    }
+
+    /// The colors of the panel listing the key bindings.
+    fn overlay_color(&self) -> Color {
+        [1.0, 1.0, 1.0, 1.0]
+    }
+
+    fn overlay_background_color(&self) -> Color {
+        [0.0, 0.0, 0.0, 0.8]
+    }
+
+    /// The actions the handler provides, by name, with their default key
+    /// bindings. Keymaps can rebind them like the grid's own actions.
+    fn actions(&self) -> Vec<(&'static str, &'static [&'static str])> {
+        Vec::new()
+    }
+
+    /// Called when one of the handler's actions is triggered while tile `i` is
+    /// under the cursor. `count` is the number typed before the keys, or 1.
+    fn action(&mut self, _i: usize, _name: &str, _count: usize) {}
+
+    /// Called before the keymap sees the key. Returning None stops the grid
+    /// from handling it.
     fn key_down(
         &mut self,
         _i: usize,
         keycode: Key,
         keymod: ModifierKey,
     ) -> Option<(Key, ModifierKey)> {
-        return Some((keycode, keymod));
+        Some((keycode, keymod))
     }
 
     fn key_up(
@@ -48,15 +262,28 @@ pub trait TileHandler {
         keycode: Key,
         keymod: ModifierKey,
     ) -> Option<(Key, ModifierKey)> {
-        return Some((keycode, keymod));
+        Some((keycode, keymod))
     }
 
     fn compute_size_by_index(&self, i: usize, w: usize, h: usize) -> (f64, usize, usize) {
-        self.compute_size(self.tile(i), w, h)
+        match self.tile(i) {
+            Some(image) => self.compute_size(image, self.orientation(i), w, h),
+            // placeholders fill the whole tile
+            None => (1.0, w, h),
+        }
     }
 
-    fn compute_size(&self, image: &Texture, w: usize, h: usize) -> (f64, usize, usize) {
+    /// The scale and size that fit the image into `w` by `h` once it is
+    /// turned by `orientation`.
+    fn compute_size(
+        &self,
+        image: &G::Texture,
+        orientation: Orientation,
+        w: usize,
+        h: usize,
+    ) -> (f64, usize, usize) {
         let (width, height) = image.get_size();
+        let (width, height) = orientation.size(width, height);
         let scale = f64::min(w as f64 / width as f64, h as f64 / height as f64);
         let width = width as f64 * scale;
         let height = height as f64 * scale;
@@ -67,23 +294,100 @@ pub trait TileHandler {
         &self,
         i: usize,
         transform: Matrix2d,
-        gl: &mut GlGraphics,
+        g: &mut G,
         target_width: usize,
         target_height: usize,
     ) {
-        let image = self.tile(i);
+        let image = match self.tile(i) {
+            Some(image) => image,
+            None => {
+                self.draw_placeholder(i, transform, g, target_width, target_height);
+                return;
+            }
+        };
         let (scale, width, height) = self.compute_size_by_index(i, target_width, target_height);
         let x_image_margin = (target_width - width) / 2;
         let y_image_margin = (target_height - height) / 2;
 
+        let (image_width, image_height) = image.get_size();
+        let orientation = self
+            .orientation(i)
+            .matrix(image_width as f64, image_height as f64);
         let state = DrawState::default();
         Image::new().draw(
             image,
             &state,
-            transform
-                .trans(x_image_margin as f64, y_image_margin as f64)
-                .zoom(scale.into()),
-            gl,
+            math::multiply(
+                transform
+                    .trans(x_image_margin as f64, y_image_margin as f64)
+                    .zoom(scale),
+                orientation,
+            ),
+            g,
+        );
+    }
+
+    /// Draws the full resolution version of the tile with its top left corner
+    /// at the origin of `transform`, `scale` screen pixels per image pixel,
+    /// turned by its orientation. The opacity is below 1 while cross-fading
+    /// to the tile.
+    fn draw_full_tile(&self, i: usize, transform: Matrix2d, g: &mut G, scale: f64, opacity: f32) {
+        if let Some(image) = self.full_tile(i) {
+            let (width, height) = image.get_size();
+            let orientation = self.orientation(i).matrix(width as f64, height as f64);
+            Image::new_color([1.0, 1.0, 1.0, opacity]).draw(
+                image,
+                &DrawState::default(),
+                math::multiply(transform.zoom(scale), orientation),
+                g,
+            );
+        }
+    }
+
+    fn draw_placeholder(
+        &self,
+        i: usize,
+        transform: Matrix2d,
+        g: &mut G,
+        target_width: usize,
+        target_height: usize,
+    ) {
+        let rect = graphics::rectangle::Rectangle::new(self.placeholder_color(i));
+        rect.draw(
+            [0.0, 0.0, target_width as f64, target_height as f64],
+            &Default::default(),
+            transform,
+            g,
+        );
+    }
+
+    /// Marks a tile that is part of the selection by tinting it with the
+    /// highlight color.
+    fn draw_selected(
+        &self,
+        i: usize,
+        transform: Matrix2d,
+        g: &mut G,
+        target_width: usize,
+        target_height: usize,
+    ) {
+        let (_scale, width, height) = self.compute_size_by_index(i, target_width, target_height);
+        let x_image_margin = (target_width - width) / 2;
+        let y_image_margin = (target_height - height) / 2;
+        let color = self.highlight_color(i);
+        let fill = [color[0], color[1], color[2], color[3] * 0.35];
+        let rect = graphics::rectangle::Rectangle::new(fill)
+            .border(graphics::rectangle::Border { color, radius: 2.0 });
+        rect.draw(
+            [
+                x_image_margin as f64,
+                y_image_margin as f64,
+                width as f64,
+                height as f64,
+            ],
+            &Default::default(),
+            transform,
+            g,
         );
     }
 
@@ -91,7 +395,7 @@ pub trait TileHandler {
         &self,
         i: usize,
         transform: Matrix2d,
-        gl: &mut GlGraphics,
+        g: &mut G,
         target_width: usize,
         target_height: usize,
     ) {
@@ -108,366 +412,1859 @@ pub trait TileHandler {
             ],
             &Default::default(),
             transform,
-            gl,
+            g,
         );
     }
 }
 
-// margin: the total space between items the grid
-pub struct Grid<'a> {
-    pub tile_handler: Box<&'a mut dyn TileHandler>,
-    margin: usize,
-    tile_width: usize,
-    tile_height: usize,
-    border_margin: usize,
+pub struct Grid<'a, G: Graphics> {
+    pub tile_handler: Box<&'a mut dyn TileHandler<G>>,
+    layout: GridLayout,
     selected_tile: usize,
-    tiles_per_row: usize,
-    margin_to_center: usize,
-    coords_to_select: Option<(f64, f64)>,
+    selection: Selection,
     draw_tile: bool,
     pub allow_draw_tile: bool,
-    width: f64,
+    draw_outline: bool,
+    // set when the user asks to close the grid
+    closed: bool,
+    gamepad: Gamepad,
+    keymap: Keymap,
+    // What is drawn, and where the view is headed
     scroll_pos: f64,
+    scroll_target: f64,
+    /// Animate scrolling instead of jumping straight to the new position.
+    pub smooth_scroll: bool,
+    last_frame: Option<Instant>,
+    // The tile the view was last scrolled to show. The view is only scrolled
+    // for the selection when it changes, so it can be scrolled away freely.
+    shown_tile: Option<usize>,
     mouse_pos: [f64; 2],
+    pub show_scrollbar: bool,
+    /// Show which tile is at the top of the view out of how many.
+    pub show_position: bool,
+    // Set while the mouse button that was pressed on the scrollbar is down,
+    // along with where on the thumb it was grabbed when it is being dragged.
+    scrollbar_pressed: bool,
+    scrollbar_drag: Option<f64>,
+    zoom: ZoomView,
+    /// Show the zoom level of the fullscreen tile.
+    pub show_zoom: bool,
+    // Where the mouse was while it is dragging the fullscreen tile around
+    pan_drag: Option<[f64; 2]>,
+    /// Used by slideshows started from now on.
+    pub slideshow_settings: SlideshowSettings,
+    slideshow: Option<Slideshow>,
+    /// Which tiles are played back when they are animated.
+    pub animate: Animate,
+    playback: Option<Playback>,
+    animation_paused: bool,
+    // Seconds since the grid was created, for spinners
+    time: f64,
+    text: TextRenderer<G::Texture>,
+    pub show_captions: bool,
+    /// Show the handler's status text along the bottom of the window.
+    pub show_status_bar: bool,
+    show_help: bool,
+    show_info: bool,
+    // The metadata of the tile in the info panel, wrapped to fit the panel
+    info: Option<InfoPanel>,
+    prompt: Option<Prompt>,
+    // The last search, whose matches next-match and previous-match move
+    // between, and whether the tiles are narrowed to them
+    search: Option<Search>,
+    filtered: bool,
+}
+
+// The metadata is wrapped again when it changes, when another tile is shown
+// or when the width of the panel changes.
+struct InfoPanel {
+    tile: usize,
+    entries: Vec<(String, String)>,
+    width: f64,
+    label_width: f64,
+    // A row per line, with the label on the first line of each value
+    rows: Vec<(Option<String>, String)>,
 }
 
-impl<'a> Grid<'a> {
+// A search that is being typed. The tiles are narrowed as it changes, and go
+// back to the previous search if it is cancelled.
+struct Prompt {
+    query: String,
+    mode: SearchMode,
+    // Shown instead of the number of matches when the query cannot be
+    // searched for
+    error: Option<String>,
+    previous: Option<Search>,
+    // The key that opened the prompt is typed as well, and is left out
+    skip_text: bool,
+}
+
+impl<'a, G> Grid<'a, G>
+where
+    G: Graphics,
+    G::Texture: ImageTexture,
+{
     pub fn new(
-        tile_handler: Box<&'a mut dyn TileHandler>,
+        tile_handler: Box<&'a mut dyn TileHandler<G>>,
         tile_width: usize,
         tile_height: usize,
-    ) -> Grid {
+    ) -> Grid<'a, G> {
         // Vec<(scale, width, height)>
         let sizes: Vec<(f64, usize, usize)> = tile_handler
             .tiles()
             .iter()
             .map(|i| tile_handler.compute_size_by_index(*i, tile_width, tile_height))
             .collect();
-        let max_width = (&sizes).iter().map(|size| size.1).fold(0, max) as usize;
-        let max_height = (&sizes).iter().map(|size| size.2).fold(0, max) as usize;
+        let max_width = sizes.iter().map(|size| size.1).fold(0, max);
+        let max_height = sizes.iter().map(|size| size.2).fold(0, max);
         let tile_width = min(max_width, tile_width);
         let tile_height = min(max_height, tile_height);
-        Grid {
+        let layout = GridLayout::new(tile_width, tile_height, tile_handler.tiles().len());
+        let mut grid = Grid {
             tile_handler,
-            margin: 5,
-            tile_width,
-            tile_height,
-            border_margin: 20,
+            layout,
             selected_tile: 0,
-            tiles_per_row: 10,
-            margin_to_center: 0,
-            coords_to_select: None,
+            selection: Selection::new(),
             draw_tile: false,
             allow_draw_tile: true,
-            width: 0.0,
+            draw_outline: true,
+            closed: false,
+            gamepad: Gamepad::new(),
+            keymap: Keymap::new(),
             scroll_pos: 0.0,
+            scroll_target: 0.0,
+            smooth_scroll: true,
+            last_frame: None,
+            shown_tile: None,
             mouse_pos: [0.0, 0.0],
-        }
+            show_scrollbar: true,
+            show_position: true,
+            scrollbar_pressed: false,
+            scrollbar_drag: None,
+            zoom: ZoomView::new(),
+            show_zoom: true,
+            pan_drag: None,
+            slideshow_settings: SlideshowSettings::default(),
+            slideshow: None,
+            animate: Animate::Fullscreen,
+            playback: None,
+            animation_paused: false,
+            time: 0.0,
+            text: TextRenderer::new(),
+            show_captions: true,
+            show_status_bar: true,
+            show_help: false,
+            show_info: false,
+            info: None,
+            prompt: None,
+            search: None,
+            filtered: false,
+        };
+        grid.register_actions();
+        grid
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    /// Replaces the key bindings. The handler's actions keep their default
+    /// bindings unless the keymap binds them.
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+        self.register_actions();
     }
 
-    fn resize(&mut self, new_width: usize) {
-        let remaining_width = new_width - self.border_margin * 2 + self.margin;
-        let tile_margin_width = self.tile_width as usize + self.margin;
-        // TODO: tiles per row and margin to center do not handle case where
-        // tiles per row is greater than the number of tiles to display which
-        // leads to a not fully centered grid since all tiles can fit on a single
-        // row.
-        self.tiles_per_row = remaining_width / tile_margin_width;
-        self.margin_to_center = remaining_width % tile_margin_width / 2;
-        // minimum tiles per row is 1 regardless of window size
-        if self.tiles_per_row == 0 {
-            self.tiles_per_row = 1;
+    /// The names of the actions the handler registers, which a keymap may
+    /// bind as well as the built in ones.
+    pub fn handler_actions(&self) -> Vec<&'static str> {
+        self.tile_handler
+            .actions()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    fn register_actions(&mut self) {
+        for (name, bindings) in self.tile_handler.actions() {
+            if let Err(err) = self
+                .keymap
+                .register(Action::Handler(name.to_string()), bindings)
+            {
+                eprintln!("{}: {}", name, err);
+            }
         }
     }
 
-    fn up(&mut self) {
-        self.selected_tile = max(
-            0 as isize,
-            self.selected_tile as isize - self.tiles_per_row as isize,
-        ) as usize;
+    pub fn layout(&self) -> &GridLayout {
+        &self.layout
     }
 
-    fn down(&mut self) {
-        self.selected_tile = min(
-            self.tile_handler.tiles().len() - 1,
-            self.selected_tile + self.tiles_per_row,
-        );
+    pub fn selected(&self) -> usize {
+        self.selected_tile
     }
 
-    fn left(&mut self) {
-        self.selected_tile = max(0 as isize, self.selected_tile as isize - 1) as usize;
+    pub fn select(&mut self, i: usize) {
+        self.selected_tile = min(i, self.layout.last());
     }
 
-    fn right(&mut self) {
-        self.selected_tile = min(self.tile_handler.tiles().len() - 1, self.selected_tile + 1);
+    /// The selected tiles, as returned by `TileHandler::tiles`, in the order
+    /// they are shown. This is separate from the tile under the cursor. Tiles
+    /// hidden by a search stay selected, but are left out until they are
+    /// shown again.
+    pub fn selection(&self) -> Vec<usize> {
+        self.selection.in_order(self.tile_handler.tiles())
     }
 
-    fn select_tile_under(&mut self, x: f64, y: f64) {
-        self.coords_to_select = Some((x, y));
+    // The selected tiles that are shown, or the tile under the cursor if
+    // there are none
+    fn selection_or_current(&self) -> Vec<usize> {
+        let tiles = self.tile_handler.tiles();
+        let selection = self.selection.in_order(tiles);
+        if selection.is_empty() {
+            tiles.get(self.selected_tile).copied().into_iter().collect()
+        } else {
+            selection
+        }
     }
 
-    pub fn run(&mut self, window: &mut Window, gl: &mut GlGraphics) -> Result<(), Error> {
-        let mut settings = EventSettings::new();
-        settings.set_lazy(false);
-        settings.swap_buffers(true);
-        settings.max_fps(1);
-        settings.ups(1);
-        let mut events = Events::new(settings);
-        let mut modkeys = ModifierKey::NO_MODIFIER;
-        loop {
-            if let Some(e) = events.next(window) {
-                if let Some(r) = e.render_args() {
-                    self.draw(gl, &r)?;
-                }
+    /// Shows or hides the selected tile fullscreen. Has no effect when
+    /// `allow_draw_tile` is false. The tile is fitted to the window at first,
+    /// and leaving fullscreen stops the slideshow.
+    pub fn set_draw_tile(&mut self, draw_tile: bool) {
+        let draw_tile = draw_tile && self.allow_draw_tile;
+        if draw_tile && !self.draw_tile {
+            self.zoom = ZoomView::new();
+        }
+        if !draw_tile {
+            self.slideshow = None;
+        }
+        self.draw_tile = draw_tile;
+        self.pan_drag = None;
+    }
 
-                if let Some(pos) = e.mouse_cursor_args() {
-                    self.mouse_pos = pos;
-                }
+    /// Shows the tiles fullscreen one after the other, starting with the
+    /// selected one, using `slideshow_settings`.
+    pub fn start_slideshow(&mut self) {
+        self.set_draw_tile(true);
+        if self.draw_tile {
+            self.slideshow = Some(Slideshow::new(
+                self.slideshow_settings.clone(),
+                self.selected_tile,
+                self.layout.tile_count(),
+            ));
+        }
+    }
 
-                if let Some(scroll) = e.mouse_scroll_args() {
-                    self.mouse_wheel_event(scroll[0] as f32, scroll[1] as f32);
-                }
+    /// Stops the slideshow, staying on the tile it got to.
+    pub fn stop_slideshow(&mut self) {
+        self.slideshow = None;
+    }
 
-                if let Some(p) = e.release_args() {
-                    match p {
-                        Button::Keyboard(key) => {
-                            self.key_up_event(key, modkeys);
-                        }
-                        Button::Mouse(button) => {
-                            self.mouse_button_up_event(
-                                button,
-                                self.mouse_pos[0],
-                                self.mouse_pos[1],
-                            );
-                            window.set_title(self.tile_handler.window_title());
-                        }
-                        _ => {}
-                    }
-                }
+    pub fn slideshow(&self) -> Option<&Slideshow> {
+        self.slideshow.as_ref()
+    }
+
+    /// Advances everything that depends on time, such as slideshows and
+    /// animated tiles, by `dt` seconds.
+    pub fn update(&mut self, dt: f64) {
+        self.time += dt;
+        let tile_count = self.tile_handler.tiles().len();
+        if let Some(slideshow) = self.slideshow.as_mut() {
+            if let Some(position) = slideshow.update(dt, tile_count) {
+                self.move_to(position, false);
+            }
+        }
+        self.update_playback();
+        if self.animation_paused {
+            return;
+        }
+        if let Some(playback) = self.playback.as_mut() {
+            let handler = &self.tile_handler;
+            let tile = playback.tile;
+            let count = handler.frame_count(tile);
+            if playback.update(dt, count, |frame| handler.frame_delay(tile, frame)) {
+                self.tile_handler.show_frame(tile, playback.frame);
+            }
+        }
+    }
+
+    pub fn is_animation_paused(&self) -> bool {
+        self.animation_paused
+    }
+
+    pub fn set_animation_paused(&mut self, paused: bool) {
+        self.animation_paused = paused;
+    }
+
+    // Whether anything shown changes without input, which needs frames to
+    // be drawn continuously: scrolling, a slideshow, an animated tile that is
+    // playing, a loading spinner or tiles loading in the background.
+    fn animating(&self) -> bool {
+        let scrolling = self.scroll_pos != self.scroll_target;
+        let slideshow = self.slideshow.as_ref().is_some_and(|s| !s.is_paused());
+        let playing = !self.animation_paused
+            && self
+                .playback
+                .as_ref()
+                .is_some_and(|playback| self.tile_handler.frame_count(playback.tile) > 1);
+        scrolling || slideshow || playing || self.spinning() || self.tile_handler.busy()
+    }
 
-                modkeys.event(&e);
+    fn spinning(&self) -> bool {
+        let tiles = self.tile_handler.tiles();
+        let loading = |i: usize| self.tile_handler.placeholder(i) == Placeholder::Loading;
+        if self.draw_tile {
+            return tiles
+                .get(self.selected_tile)
+                .is_some_and(|&i| self.tile_handler.full_tile(i).is_none() && loading(i));
+        }
+        self.layout.visible_range(self.scroll_pos).any(|position| {
+            self.tile_handler.tile(tiles[position]).is_none() && loading(tiles[position])
+        })
+    }
 
-                if let Some(p) = e.press_args() {
-                    match p {
-                        Button::Keyboard(key) => {
-                            self.key_down_event(key, modkeys, false);
-                            window.set_title(self.tile_handler.window_title());
-                        }
-                        _ => {}
+    // The tile that is played back, if any
+    fn animated_tile(&self) -> Option<usize> {
+        let position = if self.draw_tile {
+            self.selected_tile
+        } else {
+            match self.animate {
+                Animate::Fullscreen => return None,
+                Animate::Selected => self.selected_tile,
+                Animate::Hovered => {
+                    let [x, y] = self.mouse_pos;
+                    if y >= self.layout.height() {
+                        return None;
                     }
+                    self.layout.tile_at(x, y + self.scroll_pos)?
                 }
-            } else {
-                break;
             }
+        };
+        self.tile_handler.tiles().get(position).copied()
+    }
+
+    // Switches playback to the tile that should be animated now
+    fn update_playback(&mut self) {
+        let tile = self.animated_tile();
+        if self.playback.as_ref().map(|playback| playback.tile) == tile {
+            return;
+        }
+        if let Some(playback) = self.playback.take() {
+            self.tile_handler.stop_animation(playback.tile);
+        }
+        if let Some(tile) = tile {
+            self.tile_handler.start_animation(tile);
+            self.playback = Some(Playback::new(tile));
         }
-        Ok(())
     }
 
-    fn draw(&mut self, gl: &mut GlGraphics, args: &RenderArgs) -> GridResult<()> {
-        // handle window resize
-        let [win_width, win_height] = args.window_size;
-        if win_width != self.width {
-            self.resize(win_width as usize);
-            self.width = win_width;
+    // Shows another frame of the animated tile, which pauses it
+    fn step_frame(&mut self, steps: isize) {
+        if let Some(playback) = self.playback.as_mut() {
+            let count = self.tile_handler.frame_count(playback.tile);
+            playback.step(steps, count);
+            self.tile_handler.show_frame(playback.tile, playback.frame);
+            self.animation_paused = true;
         }
-        let viewport = args.viewport();
+    }
 
-        // clear the screen
-        gl.draw(viewport, |_c, gl| {
-            use graphics::clear;
-            clear(self.tile_handler.background_color(), gl);
-        });
-        let mut x: f64;
-        let mut y: f64 = self.border_margin as f64;
-        let mut start_at =
-            (viewport.rect[1] as usize) / (self.tile_height + self.margin) * self.tiles_per_row;
-        let row_of_selection: usize = self.selected_tile / self.tiles_per_row;
-        if start_at > row_of_selection {
-            start_at = row_of_selection;
-        }
-        let mut move_win_by = 0.0;
-        let tiles = self.tile_handler.tiles();
-        if self.selected_tile >= tiles.len() {
-            self.selected_tile = tiles.len() - 1;
+    // Moves through the slideshow by hand
+    fn step_slideshow(&mut self, steps: isize) {
+        if let Some(position) = self.slideshow.as_mut().and_then(|s| s.step(steps)) {
+            self.move_to(position, false);
         }
-        let mut launch = false;
+    }
 
-        for (i, ii) in tiles.iter().enumerate() {
-            x = (self.margin_to_center
-                + self.border_margin
-                + i % self.tiles_per_row * self.tile_width
-                + i % self.tiles_per_row * self.margin) as f64;
+    /// How the fullscreen tile is zoomed.
+    pub fn zoom_mode(&self) -> ZoomMode {
+        self.zoom.mode()
+    }
 
-            // Increment y when we start a new row
-            if i != 0 && i % self.tiles_per_row == 0 {
-                y += (self.margin + self.tile_height) as f64;
+    pub fn set_zoom_mode(&mut self, mode: ZoomMode) {
+        self.zoom.set_mode(mode);
+    }
 
-                // Optimization to only draw a single page of images
-                if i > self.selected_tile && y > (self.scroll_pos + win_height) as f64 {
-                    break;
-                }
-            }
+    // The size of the fullscreen tile and of the area it is shown in
+    fn zoom_sizes(&self) -> Option<([f64; 2], [f64; 2])> {
+        let tile = self.tile_handler.tiles().get(self.selected_tile)?;
+        self.full_tile_sizes(*tile)
+    }
 
-            // Handle mouse selection of tiles
-            if let Some((x_coord, y_coord)) = self.coords_to_select {
-                if x_coord >= x
-                    && x_coord <= x + self.tile_width as f64
-                    && y_coord >= y
-                    && y_coord <= y + self.tile_height as f64
-                {
-                    if self.selected_tile == i {
-                        launch = true;
-                    }
-                    self.selected_tile = i;
-                    self.coords_to_select = None;
-                }
-            }
+    fn full_tile_sizes(&self, tile: usize) -> Option<([f64; 2], [f64; 2])> {
+        let (width, height) = self.tile_handler.full_tile(tile)?.get_size();
+        let (width, height) = self.tile_handler.orientation(tile).size(width, height);
+        let view = [self.layout.width(), self.layout.height()];
+        Some(([width as f64, height as f64], view))
+    }
 
-            // Skip to next tile if current tile is offscreen
-            if i < start_at {
-                continue;
-            }
+    // Zooms the fullscreen tile around a point in the window
+    fn zoom_at(&mut self, factor: f64, point: [f64; 2]) {
+        if let Some((image, view)) = self.zoom_sizes() {
+            self.zoom.zoom_at(factor, point, image, view);
+        }
+    }
 
-            // Draw current tile
-            gl.draw(viewport, |c, gl| {
-                let transform = c.transform.trans(x, y).trans(0.0, -self.scroll_pos);
-                self.tile_handler
-                    .draw_tile(*ii, transform, gl, self.tile_width, self.tile_height);
-            });
+    fn zoom_by(&mut self, factor: f64) {
+        let center = [self.layout.width() / 2.0, self.layout.height() / 2.0];
+        self.zoom_at(factor, center);
+    }
 
-            // Draw outline around selected tile
-            if i == self.selected_tile {
-                gl.draw(viewport, |c, gl| {
-                    let transform = c.transform.trans(x, y).trans(0.0, -self.scroll_pos);
-                    self.tile_handler.draw_outline(
-                        *ii,
-                        transform,
-                        gl,
-                        self.tile_width,
-                        self.tile_height,
-                    );
-                });
-
-                // See if the window needs to be scrolled
-                if y + self.tile_height as f64 > self.scroll_pos + win_height as f64 {
-                    move_win_by = self.tile_height as f64;
-                }
-                if (y as f64) < self.scroll_pos {
-                    move_win_by = -(self.tile_height as f64);
-                }
-            }
+    fn resize(&mut self, new_width: f64, new_height: f64) {
+        self.layout.resize(new_width, new_height);
+        self.scroll_pos = self.layout.clamp_scroll(self.scroll_pos);
+        self.scroll_target = self.layout.clamp_scroll(self.scroll_target);
+    }
+
+    /// How far the view is scrolled down, in pixels.
+    pub fn scroll_pos(&self) -> f64 {
+        self.scroll_pos
+    }
+
+    /// Scrolls the view by `pixels`, down if positive, without moving the
+    /// selection.
+    pub fn scroll_by(&mut self, pixels: f64) {
+        self.scroll_target = self.layout.clamp_scroll(self.scroll_target + pixels);
+    }
+
+    // Scrolls as little as needed to show a tile next to the view, and centers
+    // tiles further away so that what surrounds them is visible too.
+    fn show_selected(&mut self) {
+        let minimal = self
+            .layout
+            .scroll_to_show(self.selected_tile, self.scroll_target);
+        self.scroll_target = if (minimal - self.scroll_target).abs() <= self.layout.row_height() {
+            minimal
+        } else {
+            self.layout.scroll_to_center(self.selected_tile)
+        };
+        self.shown_tile = Some(self.selected_tile);
+    }
+
+    // Moves the drawn scroll position towards the target, covering the same
+    // fraction of the remaining distance per unit of time.
+    fn animate_scroll(&mut self) {
+        let now = Instant::now();
+        let elapsed = self
+            .last_frame
+            .map_or(0.0, |last| now.duration_since(last).as_secs_f64());
+        self.last_frame = Some(now);
+        let distance = self.scroll_target - self.scroll_pos;
+        if !self.smooth_scroll || distance.abs() < 0.5 {
+            self.scroll_pos = self.scroll_target;
+            // No frames are drawn until the next scroll, which should not
+            // count the time in between
+            self.last_frame = None;
+        } else {
+            self.scroll_pos += distance * (1.0 - (-elapsed * SCROLL_SPEED).exp());
+        }
+    }
+
+    fn up(&mut self, extend: bool) {
+        self.move_to(self.layout.up(self.selected_tile), extend);
+    }
+
+    fn down(&mut self, extend: bool) {
+        self.move_to(self.layout.down(self.selected_tile), extend);
+    }
+
+    // Paging scrolls the view by a screenful of rows and keeps the selection
+    // at the same place on screen where possible.
+    fn page_up(&mut self, extend: bool) {
+        let page = self.layout.rows_per_page() as f64 * self.layout.row_height();
+        self.scroll_by(-page);
+        self.move_to(self.layout.page_up(self.selected_tile), extend);
+        self.shown_tile = None;
+    }
+
+    fn page_down(&mut self, extend: bool) {
+        let page = self.layout.rows_per_page() as f64 * self.layout.row_height();
+        self.scroll_by(page);
+        self.move_to(self.layout.page_down(self.selected_tile), extend);
+        self.shown_tile = None;
+    }
+
+    fn left(&mut self, extend: bool) {
+        self.move_to(self.layout.left(self.selected_tile), extend);
+    }
+
+    fn right(&mut self, extend: bool) {
+        self.move_to(self.layout.right(self.selected_tile), extend);
+    }
+
+    // Moves the cursor to tile i. When extending, the tiles between the
+    // anchor and i are selected as well.
+    fn move_to(&mut self, i: usize, extend: bool) {
+        if extend {
+            self.selection
+                .extend(self.tile_handler.tiles(), self.selected_tile, i);
+        } else {
+            self.selection.reset_anchor();
         }
+        self.selected_tile = i;
+    }
 
-        // Trigger action if tile was clicked
-        if launch {
-            self.key_down_event(Key::Return, ModifierKey::NO_MODIFIER, false);
+    fn toggle_selected(&mut self) {
+        if let Some(tile) = self.tile_handler.tiles().get(self.selected_tile) {
+            self.selection.toggle(*tile);
         }
+    }
 
-        // Draw current image full screen
-        if self.draw_tile {
-            // draw overlay and image
-            gl.draw(viewport, |c, gl| {
-                let rect = graphics::rectangle::Rectangle::new([1.0, 1.0, 1.0, 1.0]);
-                let transform = c.transform.trans(0.0, 0.0);
-                rect.draw(
-                    [0.0, 0.0, win_width, win_height],
-                    &Default::default(),
-                    transform,
-                    gl,
-                );
+    // Confirms the selected tiles that are shown, or just the tile under the
+    // cursor if none are.
+    fn confirm_selection(&mut self) {
+        let selection = self.selection_or_current();
+        if !selection.is_empty() {
+            self.tile_handler.act_on_selection(&selection);
+        }
+    }
 
-                self.tile_handler.draw_tile(
-                    self.tile_handler.tiles()[self.selected_tile],
-                    c.transform,
-                    gl,
-                    win_width as usize,
-                    win_height as usize,
-                );
-            });
+    // Turns the selected tiles that are shown, or the current one when none
+    // are or it is shown fullscreen.
+    fn turn(&mut self, turn: fn(Orientation) -> Orientation) {
+        let turned = if self.draw_tile {
+            let tiles = self.tile_handler.tiles();
+            tiles.get(self.selected_tile).copied().into_iter().collect()
+        } else {
+            self.selection_or_current()
+        };
+        for tile in turned {
+            let orientation = turn(self.tile_handler.orientation(tile));
+            self.tile_handler.set_orientation(tile, orientation);
         }
-        if move_win_by != 0.0 {
-            self.scroll_pos += move_win_by;
+    }
+
+    /// Narrows the tiles to the matches of the search, or shows every tile
+    /// again. The current tile stays selected if it is still shown.
+    pub fn set_filter(&mut self, search: Option<Search>) {
+        self.narrow(search.as_ref());
+        self.filtered = search.is_some();
+        if search.is_some() {
+            self.search = search;
         }
+    }
 
-        Ok(())
+    pub fn filter(&self) -> Option<&Search> {
+        self.search.as_ref().filter(|_| self.filtered)
     }
 
-    fn mouse_button_up_event(&mut self, _button: MouseButton, x: f64, y: f64) {
-        self.select_tile_under(x, y + self.scroll_pos);
+    fn narrow(&mut self, search: Option<&Search>) {
+        let selected = self.tile_handler.tiles().get(self.selected_tile).copied();
+        self.tile_handler.filter(search);
+        let tiles = self.tile_handler.tiles();
+        // Tiles the search hides stay selected for when they are shown again
+        self.layout.set_tile_count(tiles.len());
+        self.selected_tile = selected
+            .and_then(|tile| tiles.iter().position(|t| *t == tile))
+            .unwrap_or(0);
+        self.shown_tile = None;
     }
 
-    fn mouse_wheel_event(&mut self, _x: f32, y: f32) {
-        if y > 0.0 {
-            if self.draw_tile {
-                self.left();
+    fn open_prompt(&mut self) {
+        self.prompt = Some(Prompt {
+            query: String::new(),
+            mode: self
+                .search
+                .as_ref()
+                .map_or(SearchMode::Substring, |search| search.mode()),
+            error: None,
+            previous: self.filter().cloned(),
+            skip_text: false,
+        });
+    }
+
+    // Narrows the tiles to what has been typed so far
+    fn update_prompt(&mut self) {
+        let (query, mode) = match &self.prompt {
+            Some(prompt) => (prompt.query.clone(), prompt.mode),
+            None => return,
+        };
+        let search = match Search::new(&query, mode) {
+            Ok(search) => search,
+            Err(_) => {
+                if let Some(prompt) = self.prompt.as_mut() {
+                    prompt.error = Some(format!("Invalid {}", mode));
+                }
                 return;
             }
-            self.up();
+        };
+        if let Some(prompt) = self.prompt.as_mut() {
+            prompt.error = None;
         }
-        if y < 0.0 {
-            if self.draw_tile {
-                self.right();
-                return;
+        self.narrow(Some(&search).filter(|_| !query.is_empty()));
+    }
+
+    // Keeps the search when it is confirmed, or goes back to the previous one
+    fn close_prompt(&mut self, confirm: bool) {
+        let prompt = match self.prompt.take() {
+            Some(prompt) => prompt,
+            None => return,
+        };
+        if !confirm {
+            self.narrow(prompt.previous.as_ref());
+        } else if prompt.query.is_empty() {
+            self.set_filter(None);
+        } else {
+            match Search::new(&prompt.query, prompt.mode) {
+                Ok(search) => self.set_filter(Some(search)),
+                // There is nothing to confirm yet
+                Err(_) => self.prompt = Some(prompt),
             }
-            self.down();
         }
     }
 
-    fn key_up_event(&mut self, keycode: Key, keymod: ModifierKey) {
-        let result = self
-            .tile_handler
-            .key_up(self.selected_tile, keycode, keymod);
-        if let None = result {
-            return;
+    fn prompt_key(&mut self, keycode: Key, keymod: ModifierKey) {
+        let prompt = match self.prompt.as_mut() {
+            Some(prompt) => prompt,
+            None => return,
+        };
+        match keycode {
+            Key::Return => return self.close_prompt(true),
+            Key::Escape => return self.close_prompt(false),
+            Key::Backspace if keymod.contains(ModifierKey::CTRL) => prompt.query.clear(),
+            Key::Backspace => {
+                prompt.query.pop();
+            }
+            Key::Tab => prompt.mode = prompt.mode.next(),
+            _ => return,
         }
+        self.update_prompt();
     }
 
-    fn key_down_event(&mut self, keycode: Key, keymod: ModifierKey, _repeat: bool) {
-        let result = self
-            .tile_handler
-            .key_down(self.selected_tile, keycode, keymod);
-        if let None = result {
+    fn text_event(&mut self, text: &str) {
+        let prompt = match self.prompt.as_mut() {
+            Some(prompt) => prompt,
+            None => return,
+        };
+        if prompt.skip_text {
+            prompt.skip_text = false;
             return;
         }
-        let (keycode, keymod) = result.unwrap();
-        match keycode {
-            Key::E => if keymod.contains(ModifierKey::CTRL) {},
-            Key::Up => {
-                self.up();
-            }
-            Key::Down => {
-                self.down();
-            }
-            Key::Left => {
-                self.left();
-            }
-            Key::Right => {
-                self.right();
-            }
-            Key::Return => {
-                if self.allow_draw_tile && !self.draw_tile {
-                    self.draw_tile = true;
-                } else {
-                    self.tile_handler
-                        .act(self.tile_handler.tiles()[self.selected_tile]);
-                }
+        let text: String = text.chars().filter(|c| !c.is_control()).collect();
+        if text.is_empty() {
+            return;
+        }
+        prompt.query.push_str(&text);
+        self.update_prompt();
+    }
+
+    // Moves to the next tile after the current one that matches the last
+    // search, wrapping around, or the previous one for negative steps.
+    fn next_match(&mut self, steps: isize) {
+        let search = match &self.search {
+            Some(search) => search,
+            None => return,
+        };
+        let tiles = self.tile_handler.tiles();
+        let count = tiles.len() as isize;
+        let found = (1..=count)
+            .map(|n| (self.selected_tile as isize + n * steps.signum()).rem_euclid(count) as usize)
+            .find(|i| self.tile_handler.matches(tiles[*i], search));
+        if let Some(i) = found {
+            self.move_to(i, false);
+        }
+    }
+
+    // The first tile of each group, in the order the groups first appear,
+    // along with which of them the selected tile belongs to
+    fn group_starts(&self) -> Option<(Vec<usize>, usize)> {
+        let tiles = self.tile_handler.tiles();
+        let selected = self.tile_handler.group(*tiles.get(self.selected_tile)?);
+        let mut seen = HashSet::new();
+        let mut starts = Vec::new();
+        let mut current = 0;
+        for (i, tile) in tiles.iter().enumerate() {
+            let group = self.tile_handler.group(*tile);
+            if seen.insert(group.clone()) {
+                if group == selected {
+                    current = starts.len();
+                }
+                starts.push(i);
+            }
+        }
+        Some((starts, current))
+    }
+
+    // Moves to the first tile of the next group. Groups are visited in the
+    // order they first appear, even when their tiles are interleaved.
+    fn next_group(&mut self) {
+        if let Some((starts, current)) = self.group_starts() {
+            if let Some(start) = starts.get(current + 1) {
+                self.selected_tile = *start;
+            }
+        }
+    }
+
+    // Moves to the first tile of the current group, or to the first tile of
+    // the previous group when already there.
+    fn previous_group(&mut self) {
+        if let Some((starts, current)) = self.group_starts() {
+            self.selected_tile = if starts[current] == self.selected_tile && current > 0 {
+                starts[current - 1]
+            } else {
+                starts[current]
+            };
+        }
+    }
+
+    fn select_tile_under(&mut self, x: f64, y: f64, keymod: ModifierKey) {
+        if let Some(i) = self.layout.tile_at(x, y) {
+            if keymod.contains(ModifierKey::SHIFT) {
+                self.move_to(i, true);
+                return;
+            }
+            if keymod.contains(ModifierKey::CTRL) {
+                self.selected_tile = i;
+                self.toggle_selected();
+                return;
+            }
+            if self.selected_tile == i {
+                // Trigger action if the selected tile was clicked again
+                self.perform(&Action::Activate, 1);
+            }
+            self.selected_tile = i;
+        }
+    }
+
+    /// Draws the grid, and the fullscreen tile if one is shown, with any
+    /// graphics backend. `window_size` is the size of the area being drawn to.
+    pub fn draw(&mut self, c: Context, g: &mut G, window_size: [f64; 2]) -> GridResult<()> {
+        self.tile_handler.update();
+
+        // the handler may add or remove tiles between frames. While a search
+        // narrows them, the tiles it hides are not gone.
+        let tile_count = self.tile_handler.tiles().len();
+        if tile_count != self.layout.tile_count() {
+            self.layout.set_tile_count(tile_count);
+            if self.prompt.is_none() && !self.filtered {
+                self.selection.retain(self.tile_handler.tiles());
             }
-            Key::Home => {
-                self.selected_tile = 0;
+        }
+        if self.selected_tile > self.layout.last() {
+            self.selected_tile = self.layout.last();
+        }
+        if self.draw_tile && tile_count > 0 {
+            let i = self.tile_handler.tiles()[self.selected_tile];
+            self.tile_handler.fullscreen(i);
+            let next = self
+                .slideshow
+                .as_ref()
+                .and_then(|slideshow| slideshow.next_position())
+                .and_then(|position| self.tile_handler.tiles().get(position).copied());
+            if let Some(next) = next {
+                self.tile_handler.prefetch(next);
             }
-            Key::End => {
-                self.selected_tile = self.tile_handler.tiles().len() - 1;
+        }
+        self.update_caption_height();
+
+        // handle window resize. The status bar, the search prompt and the info
+        // panel are not part of the grid.
+        let [win_width, win_height] = window_size;
+        let status = self.status_text();
+        let prompt = self.prompt_text();
+        let bar_height = self.text.line_height(OVERLAY_FONT_SIZE);
+        let status_height = bar_height * (status.iter().count() + prompt.iter().count()) as f64;
+        self.update_info();
+        let info_width = match &self.info {
+            Some(info) if !info.entries.is_empty() => {
+                (win_width * 0.4).clamp(240.0, 420.0).min(win_width)
             }
-            Key::Escape => {
-                if self.draw_tile {
-                    self.draw_tile = false;
-                } else {
-                    //ggez::event::quit(ctx);
+            _ => 0.0,
+        };
+        let grid_width = win_width - info_width;
+        let grid_height = (win_height - status_height).max(0.0);
+        if grid_width != self.layout.width() || grid_height != self.layout.height() {
+            self.resize(grid_width, grid_height);
+        }
+
+        // See if the window needs to be scrolled
+        self.scroll_target = self.layout.clamp_scroll(self.scroll_target);
+        if self.shown_tile != Some(self.selected_tile) {
+            self.show_selected();
+        }
+        self.animate_scroll();
+
+        // clear the screen
+        graphics::clear(self.tile_handler.background_color(), g);
+
+        let tiles = self.tile_handler.tiles();
+        let (tile_width, tile_height) = (self.layout.tile_width(), self.layout.tile_height());
+        for i in self.layout.visible_range(self.scroll_pos) {
+            let [x, y, _w, _h] = self.layout.tile_rect(i);
+            let transform = c.transform.trans(x, y - self.scroll_pos);
+
+            // Draw current tile
+            self.tile_handler
+                .draw_tile(tiles[i], transform, g, tile_width, tile_height);
+            if self.tile_handler.tile(tiles[i]).is_none() {
+                self.tile_handler.placeholder(tiles[i]).draw(
+                    &mut self.text,
+                    self.tile_handler.placeholder_marker_color(tiles[i]),
+                    self.time,
+                    [tile_width as f64, tile_height as f64],
+                    transform,
+                    g,
+                )?;
+            }
+
+            if self.layout.caption_height() > 0 {
+                if let Some(caption) = self.tile_handler.caption(tiles[i]) {
+                    let caption = self
+                        .text
+                        .elide(&caption, CAPTION_FONT_SIZE, tile_width as f64);
+                    let width = self.text.width(&caption, CAPTION_FONT_SIZE);
+                    self.text.draw(
+                        &caption,
+                        self.tile_handler.caption_color(tiles[i]),
+                        CAPTION_FONT_SIZE,
+                        transform.trans(
+                            ((tile_width as f64 - width) / 2.0).round(),
+                            tile_height as f64,
+                        ),
+                        g,
+                    )?;
                 }
             }
+
+            if self.draw_outline && self.selection.contains(tiles[i]) {
+                self.tile_handler
+                    .draw_selected(tiles[i], transform, g, tile_width, tile_height);
+            }
+
+            // Draw outline around selected tile
+            if self.draw_outline && i == self.selected_tile {
+                self.tile_handler
+                    .draw_outline(tiles[i], transform, g, tile_width, tile_height);
+            }
+        }
+
+        // Draw current image full screen
+        if self.draw_tile && !tiles.is_empty() {
+            self.draw_fullscreen(c, g)?;
+        }
+
+        if let Some(prompt) = prompt {
+            self.draw_status_bar(&prompt, c.transform.trans(0.0, grid_height), g)?;
+        }
+        if let Some(status) = status {
+            let y = win_height - bar_height;
+            self.draw_status_bar(&status, c.transform.trans(0.0, y), g)?;
+        }
+
+        if !self.draw_tile && self.layout.scrollable() {
+            if self.show_scrollbar {
+                self.draw_scrollbar(c, g);
+            }
+            if self.show_position {
+                self.draw_position(c, g)?;
+            }
+        }
+
+        if info_width > 0.0 {
+            let rect = [grid_width, 0.0, info_width, win_height];
+            self.draw_info(rect, c, g)?;
+        }
+
+        if self.show_help {
+            self.draw_help(c, g)?;
+        }
+
+        Ok(())
+    }
+
+    // Draws the selected tile over the grid, cross-fading from the previous
+    // one while the slideshow moves on.
+    fn draw_fullscreen(&mut self, c: Context, g: &mut G) -> GridResult<()> {
+        let [width, height] = [self.layout.width(), self.layout.height()];
+        graphics::rectangle(
+            self.tile_handler.fullscreen_background_color(),
+            [0.0, 0.0, width, height],
+            c.transform,
+            g,
+        );
+        let tiles = self.tile_handler.tiles();
+        let tile = tiles[self.selected_tile];
+        let previous = self
+            .slideshow
+            .as_ref()
+            .and_then(|slideshow| slideshow.fading())
+            .and_then(|(position, opacity)| Some((*tiles.get(position)?, opacity)));
+        let opacity = match previous {
+            Some((previous, opacity)) => {
+                self.draw_full_tile(previous, 1.0, c, g)?;
+                opacity
+            }
+            None => 1.0,
+        };
+        let scale = self.draw_full_tile(tile, opacity, c, g)?;
+
+        let mut indicator = Vec::new();
+        if self.slideshow.as_ref().is_some_and(|s| s.is_paused()) {
+            indicator.push("Paused".to_string());
+        }
+        if let Some(playback) = self.playback.as_ref().filter(|p| p.tile == tile) {
+            let count = self.tile_handler.frame_count(tile);
+            if self.animation_paused && count > 1 {
+                indicator.push(format!("Frame {} / {}", playback.frame + 1, count));
+            }
+        }
+        if let (Some(scale), true) = (scale, self.show_zoom) {
+            indicator.push(format!("{:.0}%", scale * 100.0));
+        }
+        if !indicator.is_empty() {
+            self.draw_indicator(&indicator.join("   "), width, c, g)?;
+        }
+        Ok(())
+    }
+
+    // Draws the tile as zoomed, and returns the scale it was drawn at. Tiles
+    // that are still loading, or have no image, are fitted to the window
+    // instead.
+    fn draw_full_tile(
+        &mut self,
+        tile: usize,
+        opacity: f32,
+        c: Context,
+        g: &mut G,
+    ) -> GridResult<Option<f64>> {
+        match self.full_tile_sizes(tile) {
+            Some((image, view)) => {
+                let [x, y, _w, _h] = self.zoom.image_rect(image, view);
+                let scale = self.zoom.scale(image, view);
+                self.tile_handler
+                    .draw_full_tile(tile, c.transform.trans(x, y), g, scale, opacity);
+                Ok(Some(scale))
+            }
+            None => {
+                let (width, height) = (self.layout.width(), self.layout.height());
+                self.tile_handler
+                    .draw_tile(tile, c.transform, g, width as usize, height as usize);
+                if self.tile_handler.full_tile(tile).is_none() {
+                    self.tile_handler.placeholder(tile).draw(
+                        &mut self.text,
+                        self.tile_handler.placeholder_marker_color(tile),
+                        self.time,
+                        [width, height],
+                        c.transform,
+                        g,
+                    )?;
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    fn status_text(&self) -> Option<String> {
+        if !self.show_status_bar {
+            return None;
+        }
+        let tile = self.tile_handler.tiles().get(self.selected_tile)?;
+        self.tile_handler.status_text(*tile)
+    }
+
+    // The search being typed, or the search the tiles are narrowed to, with
+    // the number of matches and the mode on the right
+    fn prompt_text(&self) -> Option<String> {
+        let matches = match self.tile_handler.tiles().len() {
+            1 => "1 match".to_string(),
+            count => format!("{} matches", count),
+        };
+        match (&self.prompt, self.filter()) {
+            (Some(prompt), _) => Some(format!(
+                "/{}_\t{}    {}",
+                prompt.query,
+                prompt.error.as_ref().unwrap_or(&matches),
+                prompt.mode
+            )),
+            (None, Some(search)) => Some(format!(
+                "/{}\t{}    {}",
+                search.query(),
+                matches,
+                search.mode()
+            )),
+            (None, None) => None,
+        }
+    }
+
+    // The text before a tab is shortened to leave room for the text after it.
+    fn draw_status_bar(&mut self, status: &str, transform: Matrix2d, g: &mut G) -> GridResult<()> {
+        let padding = 8.0;
+        let width = self.layout.width();
+        let height = self.text.line_height(OVERLAY_FONT_SIZE);
+        graphics::rectangle(
+            self.tile_handler.status_bar_background_color(),
+            [0.0, 0.0, width, height],
+            transform,
+            g,
+        );
+        let color = self.tile_handler.status_bar_color();
+        let (left, right) = match status.find('\t') {
+            Some(tab) => (&status[..tab], &status[tab + 1..]),
+            None => (status, ""),
+        };
+        let right_width = self.text.width(right, OVERLAY_FONT_SIZE);
+        let x = (width - padding - right_width).round();
+        self.text
+            .draw(right, color, OVERLAY_FONT_SIZE, transform.trans(x, 0.0), g)?;
+        let max_width = x - padding * 3.0;
+        let left = self.text.elide(left, OVERLAY_FONT_SIZE, max_width);
+        self.text.draw(
+            &left,
+            color,
+            OVERLAY_FONT_SIZE,
+            transform.trans(padding, 0.0),
+            g,
+        )
+    }
+
+    // Captions take up space in the layout only when there are any to show.
+    fn update_caption_height(&mut self) {
+        let tiles = self.tile_handler.tiles();
+        let has_captions = self.show_captions
+            && tiles
+                .first()
+                .is_some_and(|tile| self.tile_handler.caption(*tile).is_some());
+        let caption_height = if has_captions {
+            self.text.line_height(CAPTION_FONT_SIZE) as usize
+        } else {
+            0
+        };
+        self.layout.set_caption_height(caption_height);
+    }
+
+    fn draw_scrollbar(&self, c: Context, g: &mut G) {
+        let state = DrawState::default();
+        graphics::rectangle(
+            self.tile_handler.scrollbar_track_color(),
+            self.layout.scrollbar_track(),
+            c.transform,
+            g,
+        );
+        graphics::rectangle::Rectangle::new_round(self.tile_handler.scrollbar_color(), 3.0).draw(
+            self.layout.scrollbar_thumb(self.scroll_pos),
+            &state,
+            c.transform,
+            g,
+        );
+    }
+
+    // Drawn in the bottom right corner, next to the scrollbar
+    fn draw_position(&mut self, c: Context, g: &mut G) -> GridResult<()> {
+        let tile_count = self.layout.tile_count();
+        if tile_count == 0 {
+            return Ok(());
+        }
+        let first = self.layout.visible_range(self.scroll_pos).start + 1;
+        let text = format!("{} / {}", first, tile_count);
+        let [track_x, _y, _w, _h] = self.layout.scrollbar_track();
+        self.draw_indicator(&text, track_x, c, g)
+    }
+
+    // A short piece of text in the bottom right corner of the grid, with its
+    // right edge at `right`.
+    fn draw_indicator(&mut self, text: &str, right: f64, c: Context, g: &mut G) -> GridResult<()> {
+        let padding = 6.0;
+        let width = self.text.width(text, OVERLAY_FONT_SIZE) + padding * 2.0;
+        let height = self.text.line_height(OVERLAY_FONT_SIZE) + padding;
+        let x = right - width - 4.0;
+        let y = self.layout.height() - height - 4.0;
+        graphics::rectangle(
+            self.tile_handler.indicator_background_color(),
+            [x, y, width, height],
+            c.transform,
+            g,
+        );
+        self.text.draw(
+            text,
+            self.tile_handler.indicator_color(),
+            OVERLAY_FONT_SIZE,
+            c.transform.trans(x + padding, y + padding / 2.0),
+            g,
+        )
+    }
+
+    // Lists every action with its key bindings in a panel in the middle of the
+    // window, in as many columns as it takes to fit.
+    fn draw_help(&mut self, c: Context, g: &mut G) -> GridResult<()> {
+        let mut actions = Action::BUILTIN.to_vec();
+        for (name, _bindings) in self.tile_handler.actions() {
+            actions.push(Action::Handler(name.to_string()));
+        }
+        let entries: Vec<(String, String)> = actions
+            .iter()
+            .map(|action| {
+                let keys: Vec<String> = self
+                    .keymap
+                    .bindings_for(action)
+                    .map(|binding| binding.to_string())
+                    .collect();
+                (action.name().to_string(), keys.join("  "))
+            })
+            .filter(|(_name, keys)| !keys.is_empty())
+            .collect();
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let (padding, gap) = (20.0, 16.0);
+        let line_height = self.text.line_height(OVERLAY_FONT_SIZE);
+        let name_width = entries
+            .iter()
+            .map(|(name, _keys)| self.text.width(name, OVERLAY_FONT_SIZE))
+            .fold(0.0, f64::max);
+        let keys_width = entries
+            .iter()
+            .map(|(_name, keys)| self.text.width(keys, OVERLAY_FONT_SIZE))
+            .fold(0.0, f64::max);
+        let column_width = (name_width + gap + keys_width).ceil();
+        let fit = ((self.layout.height() - padding * 2.0) / line_height).floor();
+        let rows = min(entries.len(), max(1, fit as usize));
+        let columns = entries.len().div_ceil(rows);
+        let width = columns as f64 * column_width + (columns - 1) as f64 * gap * 2.0;
+        let height = rows as f64 * line_height;
+        let x = ((self.layout.width() - width) / 2.0).round();
+        let y = ((self.layout.height() - height) / 2.0).round().max(padding);
+
+        graphics::rectangle(
+            self.tile_handler.overlay_background_color(),
+            [
+                x - padding,
+                y - padding,
+                width + padding * 2.0,
+                height + padding * 2.0,
+            ],
+            c.transform,
+            g,
+        );
+        let color = self.tile_handler.overlay_color();
+        for (n, (name, keys)) in entries.iter().enumerate() {
+            let left = x + (n / rows) as f64 * (column_width + gap * 2.0);
+            let top = y + (n % rows) as f64 * line_height;
+            let transform = c.transform.trans(left, top);
+            self.text
+                .draw(name, color, OVERLAY_FONT_SIZE, transform, g)?;
+            self.text.draw(
+                keys,
+                color,
+                OVERLAY_FONT_SIZE,
+                transform.trans(name_width + gap, 0.0),
+                g,
+            )?;
+        }
+        Ok(())
+    }
+
+    // Reads the metadata of the current tile while the info panel is shown
+    fn update_info(&mut self) {
+        let tile = match self.tile_handler.tiles().get(self.selected_tile) {
+            Some(tile) if self.show_info => *tile,
             _ => {
-                return ();
+                self.info = None;
+                return;
+            }
+        };
+        let entries = self.tile_handler.metadata(tile);
+        if let Some(info) = &self.info {
+            if info.tile == tile && info.entries == entries {
+                return;
             }
         }
+        self.info = Some(InfoPanel {
+            tile,
+            entries,
+            width: 0.0,
+            label_width: 0.0,
+            rows: Vec::new(),
+        });
+    }
+
+    // Lists the metadata in a panel next to the grid. Whatever does not fit is
+    // cut off at the bottom.
+    fn draw_info(
+        &mut self,
+        [x, y, width, height]: [f64; 4],
+        c: Context,
+        g: &mut G,
+    ) -> GridResult<()> {
+        let mut info = match self.info.take() {
+            Some(info) => info,
+            None => return Ok(()),
+        };
+        let (padding, gap) = (12.0, 12.0);
+        let line_height = self.text.line_height(OVERLAY_FONT_SIZE);
+        if info.width != width {
+            self.wrap_info(&mut info, width, padding, gap);
+        }
+        let label_width = info.label_width;
+        let fit = ((height - padding * 2.0) / line_height).floor().max(0.0) as usize;
+        let mut rows = &info.rows[..];
+        let mut cut = None;
+        if rows.len() > fit {
+            rows = &rows[..fit];
+            // The last row that fits says there is more
+            if let Some((label, _line)) = rows.last() {
+                cut = Some((label.clone(), "…".to_string()));
+                rows = &rows[..fit - 1];
+            }
+        }
+
+        graphics::rectangle(
+            self.tile_handler.overlay_background_color(),
+            [x, y, width, height],
+            c.transform,
+            g,
+        );
+        let color = self.tile_handler.overlay_color();
+        let mut label_color = color;
+        label_color[3] *= 0.6;
+        for (n, (label, line)) in rows.iter().chain(cut.as_ref()).enumerate() {
+            let transform = c
+                .transform
+                .trans(x + padding, y + padding + n as f64 * line_height);
+            if let Some(label) = label {
+                self.text
+                    .draw(label, label_color, OVERLAY_FONT_SIZE, transform, g)?;
+            }
+            self.text.draw(
+                line,
+                color,
+                OVERLAY_FONT_SIZE,
+                transform.trans(label_width + gap, 0.0),
+                g,
+            )?;
+        }
+        self.info = Some(info);
+        Ok(())
+    }
+
+    fn wrap_info(&mut self, info: &mut InfoPanel, width: f64, padding: f64, gap: f64) {
+        let inner_width = width - padding * 2.0;
+        let label_width = info
+            .entries
+            .iter()
+            .map(|(label, _value)| self.text.width(label, OVERLAY_FONT_SIZE))
+            .fold(0.0, f64::max)
+            .min(inner_width * 0.4)
+            .ceil();
+        let value_width = inner_width - label_width - gap;
+        info.rows.clear();
+        for (label, value) in &info.entries {
+            let label = self.text.elide(label, OVERLAY_FONT_SIZE, label_width);
+            let mut lines = self.text.wrap(value, OVERLAY_FONT_SIZE, value_width);
+            if lines.is_empty() {
+                lines.push(String::new());
+            }
+            for (n, line) in lines.into_iter().enumerate() {
+                info.rows
+                    .push((if n == 0 { Some(label.clone()) } else { None }, line));
+            }
+        }
+        info.width = width;
+        info.label_width = label_width;
+    }
+
+    fn on_scrollbar(&self, x: f64) -> bool {
+        let [track_x, _y, _w, _h] = self.layout.scrollbar_track();
+        // The info panel is to the right of the scrollbar
+        self.show_scrollbar
+            && !self.draw_tile
+            && self.layout.scrollable()
+            && x >= track_x
+            && x < self.layout.width()
+    }
+
+    // Pressing the thumb starts dragging it, pressing elsewhere on the
+    // scrollbar scrolls a page towards that point.
+    fn mouse_button_down_event(&mut self, _button: MouseButton, x: f64, y: f64) {
+        if self.draw_tile {
+            self.pan_drag = Some([x, y]);
+            return;
+        }
+        if !self.on_scrollbar(x) {
+            return;
+        }
+        self.scrollbar_pressed = true;
+        let [_x, thumb_y, _w, thumb_height] = self.layout.scrollbar_thumb(self.scroll_pos);
+        if y >= thumb_y && y <= thumb_y + thumb_height {
+            self.scrollbar_drag = Some(y - thumb_y);
+        } else {
+            let page = self.layout.rows_per_page() as f64 * self.layout.row_height();
+            self.scroll_by(if y < thumb_y { -page } else { page });
+        }
+    }
+
+    fn mouse_move_event(&mut self, x: f64, y: f64) {
+        if let Some([last_x, last_y]) = self.pan_drag {
+            if let Some((image, view)) = self.zoom_sizes() {
+                self.zoom.pan(x - last_x, y - last_y, image, view);
+            }
+            self.pan_drag = Some([x, y]);
+        }
+        if let Some(grab) = self.scrollbar_drag {
+            // The view follows the mouse directly, without animating
+            self.scroll_target = self.layout.scroll_for_thumb(y - grab);
+            self.scroll_pos = self.scroll_target;
+        }
+    }
+
+    fn mouse_button_up_event(&mut self, _button: MouseButton, x: f64, y: f64, keymod: ModifierKey) {
+        if self.scrollbar_pressed {
+            self.scrollbar_pressed = false;
+            self.scrollbar_drag = None;
+            return;
+        }
+        // Clicks in fullscreen only pan
+        if self.draw_tile {
+            self.pan_drag = None;
+            return;
+        }
+        // Tiles scrolled below the grid are hidden by the status bar
+        if y >= self.layout.height() {
+            return;
+        }
+        self.select_tile_under(x, y + self.scroll_pos, keymod);
+    }
+
+    // The wheel scrolls the grid, or zooms the fullscreen tile around the
+    // mouse
+    fn mouse_wheel_event(&mut self, _x: f32, y: f32) {
+        if self.draw_tile {
+            self.zoom_at(ZOOM_STEP.powf(y as f64), self.mouse_pos);
+            return;
+        }
+        self.scroll_by(-y as f64 * self.layout.row_height() * WHEEL_ROWS);
+    }
+
+    /// Handles gamepad input the same way as the keys it is mapped to. Events
+    /// can be injected here to simulate a gamepad.
+    pub fn gamepad_event(&mut self, event: GamepadEvent) {
+        if let Some(key) = self.gamepad.key(event) {
+            self.key_down_event(key, ModifierKey::NO_MODIFIER, false);
+        }
+    }
+
+    fn key_up_event(&mut self, keycode: Key, keymod: ModifierKey) {
+        self.tile_handler
+            .key_up(self.selected_tile, keycode, keymod);
+    }
+
+    // Handlers may reorder or remove tiles. Keep the same tile selected if it
+    // is still there.
+    fn reselect(&mut self, tile: Option<usize>) {
+        let tiles = self.tile_handler.tiles();
+        if let Some(i) = tile.and_then(|tile| tiles.iter().position(|t| *t == tile)) {
+            self.selected_tile = i;
+        }
+    }
+
+    fn key_down_event(&mut self, keycode: Key, keymod: ModifierKey, _repeat: bool) {
+        if self.prompt.is_some() {
+            return self.prompt_key(keycode, keymod);
+        }
+        let selected = self.tile_handler.tiles().get(self.selected_tile).copied();
+        let result = self
+            .tile_handler
+            .key_down(self.selected_tile, keycode, keymod);
+        self.reselect(selected);
+        let (keycode, keymod) = match result {
+            Some(result) => result,
+            None => return,
+        };
+        // Holding Shift for "G" must not abandon the count typed before it
+        if keymap::is_modifier(keycode) {
+            return;
+        }
+        let (action, count) = match self.keymap.key(Chord::new(keycode, keymod)) {
+            Some(action) => action,
+            None => return,
+        };
+        self.perform(&action, count);
+        // Keys that type a character also send it as text
+        let typed = (0x20..0x7f).contains(&(keycode as u32))
+            && !keymod.intersects(ModifierKey::CTRL | ModifierKey::ALT | ModifierKey::GUI);
+        if let (Action::Search, Some(prompt)) = (&action, self.prompt.as_mut()) {
+            prompt.skip_text = typed;
+        }
+    }
+
+    /// Performs the action as if its keys had been pressed. Moves are repeated
+    /// `count` times, handler actions are given the count.
+    pub fn perform(&mut self, action: &Action, count: usize) {
+        let tile = self.tile_handler.tiles().get(self.selected_tile).copied();
+        // Moving during a slideshow follows its order
+        if self.slideshow.is_some() {
+            let steps = count as isize;
+            match action {
+                Action::MoveRight | Action::MoveDown => return self.step_slideshow(steps),
+                Action::MoveLeft | Action::MoveUp => return self.step_slideshow(-steps),
+                _ => {}
+            }
+        }
+        for _ in 0..count {
+            match action {
+                Action::MoveUp => self.up(false),
+                Action::MoveDown => self.down(false),
+                Action::MoveLeft => self.left(false),
+                Action::MoveRight => self.right(false),
+                Action::PageUp => self.page_up(false),
+                Action::PageDown => self.page_down(false),
+                Action::NextGroup => self.next_group(),
+                Action::PreviousGroup => self.previous_group(),
+                Action::ExtendUp => self.up(true),
+                Action::ExtendDown => self.down(true),
+                Action::ExtendLeft => self.left(true),
+                Action::ExtendRight => self.right(true),
+                Action::ExtendPageUp => self.page_up(true),
+                Action::ExtendPageDown => self.page_down(true),
+                Action::ZoomIn if self.draw_tile => self.zoom_by(ZOOM_STEP),
+                Action::ZoomOut if self.draw_tile => self.zoom_by(1.0 / ZOOM_STEP),
+                Action::RotateClockwise => self.turn(Orientation::rotate_clockwise),
+                Action::RotateCounterclockwise => self.turn(Orientation::rotate_counterclockwise),
+                Action::Rotate180 => self.turn(Orientation::rotate_180),
+                Action::FlipHorizontal => self.turn(Orientation::flip_horizontal),
+                Action::FlipVertical => self.turn(Orientation::flip_vertical),
+                Action::NextMatch => self.next_match(1),
+                Action::PreviousMatch => self.next_match(-1),
+                _ => break,
+            }
+        }
+        match action {
+            Action::First => self.move_to(0, false),
+            Action::Last => self.move_to(self.layout.last(), false),
+            Action::ExtendFirst => self.move_to(0, true),
+            Action::ExtendLast => self.move_to(self.layout.last(), true),
+            Action::Activate => {
+                if self.allow_draw_tile && !self.draw_tile {
+                    self.set_draw_tile(true);
+                } else if let Some(tile) = tile {
+                    self.tile_handler.act(tile);
+                }
+            }
+            Action::ToggleFullscreen => self.set_draw_tile(!self.draw_tile),
+            Action::Help => self.show_help = !self.show_help,
+            Action::Info => self.show_info = !self.show_info,
+            Action::Search => self.open_prompt(),
+            Action::ClearFilter => self.set_filter(None),
+            Action::Slideshow if self.slideshow.is_some() => self.stop_slideshow(),
+            Action::Slideshow => self.start_slideshow(),
+            Action::PauseSlideshow => {
+                if let Some(slideshow) = self.slideshow.as_mut() {
+                    slideshow.set_paused(!slideshow.is_paused());
+                }
+            }
+            Action::PauseAnimation => self.animation_paused = !self.animation_paused,
+            Action::NextFrame => self.step_frame(count as isize),
+            Action::PreviousFrame => self.step_frame(-(count as isize)),
+            Action::ZoomFit => self.zoom.set_mode(ZoomMode::Fit),
+            Action::ZoomFitWidth => self.zoom.set_mode(ZoomMode::FitWidth),
+            Action::ZoomActual => self.zoom.set_mode(ZoomMode::Scale(1.0)),
+            Action::Back if self.prompt.is_some() => self.close_prompt(false),
+            Action::Back if self.show_help => self.show_help = false,
+            Action::Back if self.draw_tile => self.set_draw_tile(false),
+            Action::Back if self.filtered => self.set_filter(None),
+            Action::Back | Action::Quit => self.closed = true,
+            Action::ToggleSelect if !self.draw_tile => self.toggle_selected(),
+            Action::SelectAll => self.selection.select_all(self.tile_handler.tiles()),
+            Action::SelectNone => self.selection.clear(),
+            Action::InvertSelection => self.selection.invert(self.tile_handler.tiles()),
+            Action::ConfirmSelection => self.confirm_selection(),
+            Action::Handler(name) => {
+                if let Some(tile) = tile {
+                    self.tile_handler.action(tile, name, count);
+                    self.reselect(Some(tile));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a> Grid<'a, GlGraphics> {
+    pub fn run(&mut self, window: &mut Window, gl: &mut GlGraphics) -> Result<(), Error> {
+        let mut settings = EventSettings::new();
+        settings.set_lazy(false);
+        settings.swap_buffers(true);
+        // Frequent enough for smooth scrolling, and for slideshows to keep
+        // time
+        settings.max_fps(60);
+        settings.ups(UPDATES_PER_SECOND);
+        let mut events = Events::new(settings);
+        let mut lazy = false;
+        let mut modkeys = ModifierKey::NO_MODIFIER;
+        self.closed = false;
+        // The grid still works with the keyboard if gamepads are unavailable
+        let mut gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(err) => {
+                eprintln!("Gamepad support disabled: {}", err);
+                None
+            }
+        };
+        while let Some(e) = events.next(window) {
+            if let Some(r) = e.render_args() {
+                self.render(gl, &r)?;
+                // Only wait for input once the frame shows the final state.
+                // Gamepads cannot wake up the window, so they are polled.
+                let gamepad = gilrs
+                    .as_ref()
+                    .is_some_and(|gilrs| gilrs.gamepads().next().is_some());
+                if !lazy && !self.animating() && !gamepad {
+                    lazy = true;
+                    events.set_lazy(true);
+                }
+            }
+
+            if let Some(u) = e.update_args() {
+                self.update(u.dt);
+            }
+
+            if let Some(pos) = e.mouse_cursor_args() {
+                self.mouse_pos = pos;
+                self.mouse_move_event(pos[0], pos[1]);
+            }
+
+            if let Some(scroll) = e.mouse_scroll_args() {
+                self.mouse_wheel_event(scroll[0] as f32, scroll[1] as f32);
+            }
+
+            if let Some(p) = e.release_args() {
+                match p {
+                    Button::Keyboard(key) => {
+                        self.key_up_event(key, modkeys);
+                    }
+                    Button::Mouse(button) => {
+                        self.mouse_button_up_event(
+                            button,
+                            self.mouse_pos[0],
+                            self.mouse_pos[1],
+                            modkeys,
+                        );
+                        window.set_title(self.tile_handler.window_title());
+                    }
+                    _ => {}
+                }
+            }
+
+            modkeys.event(&e);
+
+            if let Some(text) = e.text_args() {
+                self.text_event(&text);
+            }
+
+            match e.press_args() {
+                Some(Button::Keyboard(key)) => {
+                    self.key_down_event(key, modkeys, false);
+                    window.set_title(self.tile_handler.window_title());
+                }
+                Some(Button::Mouse(button)) => {
+                    self.mouse_button_down_event(button, self.mouse_pos[0], self.mouse_pos[1]);
+                }
+                _ => {}
+            }
+
+            if let Some(gilrs) = gilrs.as_mut() {
+                while let Some(event) = gilrs.next_event() {
+                    if let Some(event) = GamepadEvent::from_gilrs(&event.event) {
+                        self.gamepad_event(event);
+                        window.set_title(self.tile_handler.window_title());
+                    }
+                }
+            }
+
+            if self.closed || self.tile_handler.done() {
+                window.set_should_close(true);
+                break;
+            }
+
+            // Input may have started something that moves on its own
+            if lazy && self.animating() {
+                lazy = false;
+                events.set_lazy(false);
+            }
+        }
+        Ok(())
+    }
+
+    fn render(&mut self, gl: &mut GlGraphics, args: &RenderArgs) -> GridResult<()> {
+        gl.draw(args.viewport(), |c, gl| self.draw(c, gl, args.window_size))
+    }
+}
+
+impl<'a> Grid<'a, SoftwareGraphics> {
+    /// Renders a single frame of the grid into an image without a window.
+    pub fn snapshot(&mut self, width: u32, height: u32) -> GridResult<RgbaImage> {
+        // There is only one frame, so there is nothing to animate
+        let smooth_scroll = self.smooth_scroll;
+        self.smooth_scroll = false;
+        let mut g = SoftwareGraphics::new(width, height);
+        let result = g.draw(|c, g| self.draw(c, g, [width as f64, height as f64]));
+        self.smooth_scroll = smooth_scroll;
+        result?;
+        Ok(g.into_image())
+    }
+
+    /// Renders every tile into one image that is `width` wide and tall enough
+    /// to hold all of the rows. The selection outline and the status bar are
+    /// not drawn.
+    pub fn contact_sheet(&mut self, width: u32) -> GridResult<RgbaImage> {
+        self.update_caption_height();
+        let mut layout = self.layout.clone();
+        layout.set_tile_count(self.tile_handler.tiles().len());
+        layout.resize(width as f64, 0.0);
+        let height = layout.content_height().ceil() as u32;
+
+        let (draw_tile, draw_outline) = (self.draw_tile, self.draw_outline);
+        let show_status_bar = self.show_status_bar;
+        self.draw_tile = false;
+        self.draw_outline = false;
+        self.show_status_bar = false;
+        self.scroll_pos = 0.0;
+        self.scroll_target = 0.0;
+        let image = self.snapshot(width, height);
+        self.draw_tile = draw_tile;
+        self.draw_outline = draw_outline;
+        self.show_status_bar = show_status_bar;
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::software::SoftwareTexture;
+    use image::Rgba;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Solid colored tiles, or placeholders for the ones without a color
+    struct Tiles {
+        indexes: Vec<usize>,
+        textures: Vec<Option<SoftwareTexture>>,
+        acted: Vec<usize>,
+        // Whether the details after the first line of metadata are in
+        metadata_read: Rc<Cell<bool>>,
+        groups: Vec<&'static str>,
+    }
+
+    impl Tiles {
+        fn new(colors: &[Option<[u8; 3]>]) -> Tiles {
+            let textures = colors
+                .iter()
+                .map(|color| {
+                    color.map(|[r, g, b]| {
+                        SoftwareTexture::new(RgbaImage::from_pixel(20, 20, Rgba([r, g, b, 255])))
+                    })
+                })
+                .collect();
+            Tiles {
+                indexes: (0..colors.len()).collect(),
+                textures,
+                acted: Vec::new(),
+                metadata_read: Default::default(),
+                groups: Vec::new(),
+            }
+        }
+    }
+
+    impl TileHandler<SoftwareGraphics> for Tiles {
+        fn window_title(&self) -> String {
+            String::new()
+        }
+
+        fn tiles(&self) -> &Vec<usize> {
+            &self.indexes
+        }
+
+        fn tile(&self, i: usize) -> Option<&SoftwareTexture> {
+            self.textures[i].as_ref()
+        }
+
+        fn act(&mut self, i: usize) {
+            self.acted.push(i);
+        }
+
+        // Tiles are searched by their number
+        fn matches(&self, i: usize, search: &Search) -> bool {
+            search.matches(&i.to_string())
+        }
+
+        fn filter(&mut self, search: Option<&Search>) {
+            self.indexes = (0..self.textures.len())
+                .filter(|i| search.is_none_or(|search| self.matches(*i, search)))
+                .collect();
+        }
+
+        fn metadata(&self, i: usize) -> Vec<(String, String)> {
+            let mut entries = vec![("Tile".to_string(), i.to_string())];
+            if self.metadata_read.get() {
+                entries.push(("Size".to_string(), "1 KB".to_string()));
+            }
+            entries
+        }
+
+        fn group(&self, i: usize) -> Option<String> {
+            self.groups.get(i).map(|group| group.to_string())
+        }
+    }
+
+    fn assert_pixel(image: &RgbaImage, x: u32, y: u32, expected: [u8; 3]) {
+        let pixel = image.get_pixel(x, y);
+        let close = (0..3).all(|c| (pixel[c] as i32 - expected[c] as i32).abs() <= 1);
+        assert!(
+            close,
+            "pixel at {}, {} is {:?}, expected {:?}",
+            x, y, pixel, expected
+        );
+    }
+
+    #[test]
+    fn snapshot() {
+        let red = [255, 0, 0];
+        let green = [0, 255, 0];
+        let mut tiles = Tiles::new(&[Some(red), Some(green), None]);
+        let mut grid: Grid<SoftwareGraphics> = Grid::new(Box::new(&mut tiles), 20, 20);
+        // Two 20 pixel tiles fit on a row 100 pixels wide, 5 pixels apart and
+        // 20 from the edges, which leaves 7 pixels on the left to center them.
+        // The third one wraps to the next row.
+        let image = grid.snapshot(100, 90).unwrap();
+        assert_eq!(image.dimensions(), (100, 90));
+        let background = [26, 51, 77];
+        let placeholder = [51, 77, 102];
+        let highlight = [255, 255, 255];
+        // Tiles at 27 and 52 on the first row, which starts at 20
+        assert_pixel(&image, 0, 0, background);
+        assert_pixel(&image, 23, 30, background);
+        assert_pixel(&image, 37, 30, red);
+        assert_pixel(&image, 49, 30, background);
+        assert_pixel(&image, 52, 20, green);
+        assert_pixel(&image, 71, 39, green);
+        assert_pixel(&image, 73, 30, background);
+        // The tile that has not loaded yet, on the second row at 45
+        assert_pixel(&image, 28, 46, placeholder);
+        assert_pixel(&image, 46, 64, placeholder);
+        assert_pixel(&image, 28, 66, background);
+        // The selected tile is outlined on either side of its edge
+        assert_pixel(&image, 26, 30, highlight);
+        assert_pixel(&image, 28, 30, highlight);
+        assert_pixel(&image, 47, 30, highlight);
+        assert_pixel(&image, 37, 19, highlight);
+    }
+
+    #[test]
+    fn gamepad_moves_and_activates() {
+        use gilrs::{Axis, Button};
+        let mut tiles = Tiles::new(&[None, None, None, None]);
+        {
+            let mut grid: Grid<SoftwareGraphics> = Grid::new(Box::new(&mut tiles), 20, 20);
+            // Two tiles on each row
+            grid.resize(100.0, 90.0);
+            grid.gamepad_event(GamepadEvent::ButtonPressed(Button::DPadRight));
+            assert_eq!(grid.selected(), 1);
+            // The stick moves once when pushed, and again once it has been
+            // let go
+            grid.gamepad_event(GamepadEvent::AxisChanged(Axis::LeftStickY, -0.8));
+            grid.gamepad_event(GamepadEvent::AxisChanged(Axis::LeftStickY, -1.0));
+            assert_eq!(grid.selected(), 3);
+            grid.gamepad_event(GamepadEvent::AxisChanged(Axis::LeftStickY, 0.0));
+            grid.gamepad_event(GamepadEvent::AxisChanged(Axis::LeftStickY, 0.8));
+            assert_eq!(grid.selected(), 1);
+            grid.gamepad_event(GamepadEvent::ButtonReleased(Button::DPadLeft));
+            assert_eq!(grid.selected(), 1);
+            // A shows the tile fullscreen first, then acts on it
+            grid.gamepad_event(GamepadEvent::ButtonPressed(Button::South));
+            assert!(grid.draw_tile);
+            grid.gamepad_event(GamepadEvent::ButtonPressed(Button::South));
+        }
+        assert_eq!(tiles.acted, [1]);
+    }
+
+    #[test]
+    fn animating_only_while_something_moves() {
+        let red = [255, 0, 0];
+        let mut tiles = Tiles::new(&[Some(red), Some(red), None]);
+        let mut grid: Grid<SoftwareGraphics> = Grid::new(Box::new(&mut tiles), 20, 20);
+        // Only the first row is visible
+        grid.resize(100.0, 40.0);
+        assert!(!grid.animating());
+        grid.scroll_by(100.0);
+        assert!(grid.animating());
+        // The loading spinner on the second row is in view once scrolled
+        grid.scroll_pos = grid.scroll_target;
+        assert!(grid.animating());
+    }
+
+    #[test]
+    fn info_follows_the_metadata() {
+        let red = [255, 0, 0];
+        let mut tiles = Tiles::new(&[Some(red), Some(red)]);
+        let read = tiles.metadata_read.clone();
+        let mut grid: Grid<SoftwareGraphics> = Grid::new(Box::new(&mut tiles), 20, 20);
+        let entries = |grid: &Grid<SoftwareGraphics>| grid.info.as_ref().unwrap().entries.clone();
+        grid.show_info = true;
+        grid.snapshot(400, 90).unwrap();
+        assert_eq!(entries(&grid).len(), 1);
+        // Details read in the background show up once they are in
+        read.set(true);
+        grid.snapshot(400, 90).unwrap();
+        assert_eq!(entries(&grid).len(), 2);
+        assert!(!grid.info.as_ref().unwrap().rows.is_empty());
+        grid.select(1);
+        grid.snapshot(500, 90).unwrap();
+        assert_eq!(entries(&grid)[0], ("Tile".to_string(), "1".to_string()));
+        grid.show_info = false;
+        grid.snapshot(500, 90).unwrap();
+        assert!(grid.info.is_none());
+    }
+
+    #[test]
+    fn searches_keep_what_they_hide_selected() {
+        let mut tiles = Tiles::new(&[None; 12]);
+        {
+            let mut grid: Grid<SoftwareGraphics> = Grid::new(Box::new(&mut tiles), 20, 20);
+            grid.selection.toggle(1);
+            grid.selection.toggle(2);
+            // Typing a search, and cancelling it
+            grid.open_prompt();
+            grid.text_event("1");
+            assert_eq!(grid.tile_handler.tiles(), &[1, 10, 11]);
+            assert_eq!(grid.selection(), [1]);
+            grid.close_prompt(false);
+            assert_eq!(grid.selection(), [1, 2]);
+            // Only what is shown is confirmed
+            grid.set_filter(Some(Search::new("1", SearchMode::Substring).unwrap()));
+            grid.snapshot(100, 100).unwrap();
+            grid.confirm_selection();
+            // Or the current tile, when none of the selected ones are shown
+            grid.set_filter(Some(Search::new("3", SearchMode::Substring).unwrap()));
+            grid.confirm_selection();
+            grid.set_filter(None);
+            assert_eq!(grid.selection(), [1, 2]);
+        }
+        assert_eq!(tiles.acted, [1, 3]);
+    }
+
+    #[test]
+    fn groups_that_are_interleaved() {
+        let mut tiles = Tiles::new(&[None; 6]);
+        tiles.groups = vec!["a", "b", "a", "c", "b", "c"];
+        let mut grid: Grid<SoftwareGraphics> = Grid::new(Box::new(&mut tiles), 20, 20);
+        grid.next_group();
+        assert_eq!(grid.selected(), 1);
+        grid.next_group();
+        assert_eq!(grid.selected(), 3);
+        grid.next_group();
+        assert_eq!(grid.selected(), 3);
+        // Back to the start of the group, then to the group before it
+        grid.select(4);
+        grid.previous_group();
+        assert_eq!(grid.selected(), 1);
+        grid.previous_group();
+        assert_eq!(grid.selected(), 0);
+        grid.previous_group();
+        assert_eq!(grid.selected(), 0);
+        // Tiles of a group that appeared before still move to the next group
+        grid.select(2);
+        grid.next_group();
+        assert_eq!(grid.selected(), 1);
     }
 }
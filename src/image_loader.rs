@@ -1,13 +1,37 @@
+use crate::animation::{self, Frame};
+use crate::dir_walker::DirWalker;
 use crate::grid::GridResult;
-use opengl_graphics::{Texture, TextureSettings};
+use crate::orientation::{self, Orientation};
+use crate::render::ImageTexture;
+use crate::sort::{self, SortBy, SortInfo};
+use crate::thumbnail_cache::ThumbnailCache;
+use anyhow::anyhow;
+use image::{ImageError, RgbaImage};
 use regex::Regex;
-use std::path::PathBuf;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 pub struct ImageLoader {
-    must_not_match: Vec<String>,
-    must_match: Vec<String>,
+    must_not_match: Vec<Regex>,
+    must_match: Vec<Regex>,
     max_count: Option<usize>,
-    //images: Receiver<image::ImageBuffer>,
+    thumbnail_size: Option<(u32, u32)>,
+    cache: Option<Arc<ThumbnailCache>>,
+    walker: DirWalker,
+    sort: Option<SortBy>,
+}
+
+impl Default for ImageLoader {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ImageLoader {
@@ -16,103 +40,863 @@ impl ImageLoader {
             must_not_match: Vec::new(),
             must_match: Vec::new(),
             max_count: None,
+            thumbnail_size: None,
+            cache: None,
+            walker: DirWalker::new(),
+            sort: None,
         }
     }
 
-    pub fn filter(&mut self, filter: &str) {
-        self.must_not_match.push(filter.to_owned());
+    /// Leaves out files whose path matches the regular expression.
+    pub fn filter(&mut self, filter: &str) -> GridResult<()> {
+        self.must_not_match.push(pattern(filter)?);
+        Ok(())
     }
 
-    pub fn only(&mut self, only: &str) {
-        self.must_match.push(only.to_owned());
+    /// Leaves out files whose path does not match the regular expression.
+    pub fn only(&mut self, only: &str) -> GridResult<()> {
+        self.must_match.push(pattern(only)?);
+        Ok(())
     }
 
     pub fn max(&mut self, max: usize) {
         self.max_count = Some(max);
     }
 
-    pub fn load_all(&self, path: PathBuf) -> GridResult<(Vec<String>, Vec<Texture>)> {
-        let files = path
-            .read_dir()?
-            .filter(Result::is_ok)
-            .map(|f| f.unwrap().path())
-            .collect();
-        self.load_files(files)
+    /// Downscales images to fit within the size before they are handed out so
+    /// that only tile sized textures need to be uploaded.
+    pub fn thumbnail_size(&mut self, width: u32, height: u32) {
+        self.thumbnail_size = Some((width, height));
+    }
+
+    /// Reads and writes thumbnails through the cache instead of decoding every
+    /// image from scratch. Only used when a thumbnail size is set.
+    pub fn cache(&mut self, cache: ThumbnailCache) {
+        self.cache = Some(Arc::new(cache));
+    }
+
+    /// Controls how `load_all`, `stream_all` and `list` find files in a
+    /// directory. Only the directory's immediate children are used by default.
+    pub fn walker(&mut self, walker: DirWalker) {
+        self.walker = walker;
+    }
+
+    /// Orders the files before they are loaded, so `max` keeps the first
+    /// files in this order. Files are kept in directory order by default.
+    pub fn sort(&mut self, sort: SortBy) {
+        self.sort = Some(sort);
+    }
+
+    /// Lists the files under the directory, along with the subdirectories
+    /// that could not be read.
+    pub fn list(&self, path: &Path) -> GridResult<(Vec<PathBuf>, LoadReport)> {
+        let (files, failures) = self.walker.walk(path)?;
+        Ok((files, LoadReport { failures }))
+    }
+
+    pub fn load_all<T: ImageTexture>(
+        &self,
+        path: PathBuf,
+    ) -> GridResult<(Vec<String>, Vec<T>, LoadReport)> {
+        let (files, mut listed) = self.list(&path)?;
+        let (files, images, report) = self.load_files(files)?;
+        listed.failures.extend(report.failures);
+        Ok((files, images, listed))
     }
 
-    pub fn load_files(&self, files: Vec<PathBuf>) -> GridResult<(Vec<String>, Vec<Texture>)> {
+    /// Decodes all of the files before returning. Decoding still happens on
+    /// the worker threads; only texture creation happens on the caller's
+    /// thread. Files that cannot be loaded are left out, and listed in the
+    /// report along with why.
+    pub fn load_files<T: ImageTexture>(
+        &self,
+        files: Vec<PathBuf>,
+    ) -> GridResult<(Vec<String>, Vec<T>, LoadReport)> {
+        let mut stream = self.stream_files(files);
+        let mut results: Vec<LoadedImage> = stream.by_ref().collect();
+        stream.receive_sorted();
+        results.sort_by_key(|loaded| stream.rank(loaded.index));
         let mut loaded_files = Vec::new();
         let mut images = Vec::new();
-        let mut count = 0;
-        let must_not_match: Vec<Regex> = self
-            .must_not_match
-            .iter()
-            .map(|f| Regex::new(f).expect(format!("Regex error for 'filter': {}", f).as_str()))
-            .collect();
-        let must_match: Vec<Regex> = self
-            .must_match
-            .iter()
-            .map(|f| Regex::new(f).expect(format!("Regex error for 'only': {}", f).as_str()))
-            .collect();
-        'fileloop: for file in files {
-            // Is there a way to do this more concisely?
+        let mut report = LoadReport::default();
+        for loaded in results {
+            report.failures.extend(loaded.cache_error);
             if let Some(max) = self.max_count {
-                if count >= max {
+                if images.len() >= max {
                     break;
                 }
             }
-            //println!("{:?}", &file);
-            // refactor to resize(ctx, image, max_x, max_y)
-            if file.is_dir() {
-                continue;
+            // The file names are handed out as strings
+            let path = loaded.path;
+            let image = loaded.image.and_then(|image| match path.to_str() {
+                Some(file) => Ok((file.to_owned(), image)),
+                None => Err(LoadError::NonUtf8Path { path: path.clone() }),
+            });
+            match image {
+                Ok((file, (image, _frames))) => {
+                    loaded_files.push(file);
+                    images.push(T::from_rgba(&image));
+                }
+                Err(err) => report.failures.push(err),
             }
-            let filestr = file
-                .to_str()
-                .expect("Unable to convert image filename to str");
-            for regex in &must_match {
-                if !regex.is_match(&filestr) {
-                    continue 'fileloop;
+        }
+        Ok((loaded_files, images, report))
+    }
+
+    /// Streams the files under the directory. The subdirectories that could
+    /// not be read start off the stream's report.
+    pub fn stream_all(&self, path: PathBuf) -> GridResult<ImageStream> {
+        let (files, report) = self.list(&path)?;
+        let mut stream = self.stream_files(files);
+        stream.report = report;
+        Ok(stream)
+    }
+
+    /// Starts decoding the files that pass the filters on a pool of worker
+    /// threads and returns immediately. Decoded images arrive through the
+    /// returned stream in whatever order they finish. With a `sort`, the
+    /// workers sort the files first, and decode them in sorted order.
+    pub fn stream_files(&self, files: Vec<PathBuf>) -> ImageStream {
+        let files = self.candidates(files);
+        spawn_workers(
+            files,
+            self.thumbnail_size,
+            self.cache.clone(),
+            self.max_count,
+            None,
+            self.sort,
+        )
+    }
+
+    /// Decodes the files at their original size in the background, without
+    /// applying any of the filters. The frames of animated files are decoded
+    /// as well, as many as fit in `max_frame_bytes`.
+    pub fn stream_full_size(files: Vec<PathBuf>, max_frame_bytes: usize) -> ImageStream {
+        spawn_workers(files, None, None, None, Some(max_frame_bytes), None)
+    }
+
+    /// Applies the 'filter' and 'only' patterns and drops directories.
+    fn candidates(&self, files: Vec<PathBuf>) -> Vec<PathBuf> {
+        files
+            .into_iter()
+            .filter(|file| {
+                if file.is_dir() {
+                    return false;
                 }
+                // Paths that are not valid UTF-8 are matched with replacement
+                // characters in their place
+                let filestr = file.to_string_lossy();
+                self.must_match.iter().all(|regex| regex.is_match(&filestr))
+                    && !self
+                        .must_not_match
+                        .iter()
+                        .any(|regex| regex.is_match(&filestr))
+            })
+            .collect()
+    }
+}
+
+fn pattern(pattern: &str) -> GridResult<Regex> {
+    Regex::new(pattern).map_err(|source| {
+        LoadError::InvalidPattern {
+            pattern: pattern.to_string(),
+            source,
+        }
+        .into()
+    })
+}
+
+/// Why files could not be listed or loaded. Each error names the path or
+/// pattern it is about. The underlying errors are shared, so that the same
+/// error can be handed out and kept in a report.
+#[derive(Debug, Clone)]
+pub enum LoadError {
+    /// A 'filter' or 'only' pattern is not a valid regular expression.
+    InvalidPattern {
+        pattern: String,
+        source: regex::Error,
+    },
+    /// The file or directory could not be read.
+    Io {
+        path: PathBuf,
+        source: Arc<io::Error>,
+    },
+    /// The file is an image, but it is damaged or uses a feature that cannot
+    /// be decoded.
+    Decode {
+        path: PathBuf,
+        source: Arc<anyhow::Error>,
+    },
+    /// The file is not in an image format that can be read.
+    UnsupportedFormat { path: PathBuf, message: String },
+    /// The path cannot be handed out as a string.
+    NonUtf8Path { path: PathBuf },
+    /// The image loaded, but its thumbnail could not be written to the cache.
+    Cache {
+        path: PathBuf,
+        source: Arc<anyhow::Error>,
+    },
+}
+
+impl LoadError {
+    /// The file or directory the error is about, which is None for patterns.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            LoadError::InvalidPattern { .. } => None,
+            LoadError::Io { path, .. }
+            | LoadError::Decode { path, .. }
+            | LoadError::UnsupportedFormat { path, .. }
+            | LoadError::NonUtf8Path { path }
+            | LoadError::Cache { path, .. } => Some(path),
+        }
+    }
+
+    /// What went wrong, without the path.
+    pub fn message(&self) -> String {
+        match self {
+            LoadError::InvalidPattern { pattern, .. } => format!("Invalid pattern '{}'", pattern),
+            LoadError::Io { source, .. } => source.to_string(),
+            LoadError::Decode { source, .. } => source.to_string(),
+            LoadError::UnsupportedFormat { message, .. } => message.clone(),
+            LoadError::NonUtf8Path { .. } => "path is not valid UTF-8".to_string(),
+            LoadError::Cache { source, .. } => format!("unable to cache thumbnail: {}", source),
+        }
+    }
+
+    /// Whether the file does not exist, as opposed to being unreadable.
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            LoadError::Io { source, .. } => source.kind() == io::ErrorKind::NotFound,
+            _ => false,
+        }
+    }
+
+    pub(crate) fn io(path: &Path, source: io::Error) -> LoadError {
+        LoadError::Io {
+            path: path.to_path_buf(),
+            source: Arc::new(source),
+        }
+    }
+
+    fn decode(path: &Path, source: anyhow::Error) -> LoadError {
+        LoadError::Decode {
+            path: path.to_path_buf(),
+            source: Arc::new(source),
+        }
+    }
+
+    fn image(path: &Path, err: ImageError) -> LoadError {
+        match err {
+            ImageError::UnsupportedError(message) => LoadError::UnsupportedFormat {
+                path: path.to_path_buf(),
+                message,
+            },
+            // The file is decoded from memory, so running out of data means
+            // that the file ends too early
+            ImageError::NotEnoughData => LoadError::decode(path, anyhow!("file is truncated")),
+            ImageError::IoError(ref source) if source.kind() == io::ErrorKind::UnexpectedEof => {
+                LoadError::decode(path, anyhow!("file is truncated"))
+            }
+            err => LoadError::decode(path, err.into()),
+        }
+    }
+}
+
+/// Prefixed with the path, for errors about a file or directory.
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.path() {
+            Some(path) => write!(f, "{}: {}", path.display(), self.message()),
+            None => f.write_str(&self.message()),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::InvalidPattern { source, .. } => Some(source),
+            LoadError::Io { source, .. } => Some(source.as_ref()),
+            LoadError::Decode { source, .. } | LoadError::Cache { source, .. } => {
+                Some((**source).as_ref())
             }
-            for regex in &must_not_match {
-                //println!("{}, {:?}", &filestr, regex);
-                if regex.is_match(&filestr) {
-                    continue 'fileloop;
+            LoadError::UnsupportedFormat { .. } | LoadError::NonUtf8Path { .. } => None,
+        }
+    }
+}
+
+/// The files and directories that could not be read, along with why, and
+/// the thumbnails that could not be cached.
+#[derive(Debug, Default, Clone)]
+pub struct LoadReport {
+    pub failures: Vec<LoadError>,
+}
+
+impl LoadReport {
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// One failure per line.
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for failure in &self.failures {
+            writeln!(f, "{}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct LoadedImage {
+    /// Position of the file in `ImageStream::files`.
+    pub index: usize,
+    pub path: PathBuf,
+    /// The image along with its frames when it is animated and they were
+    /// asked for, or no frames otherwise.
+    pub image: Result<(RgbaImage, Vec<Frame>), LoadError>,
+    /// Set when the image loaded but its thumbnail could not be cached.
+    pub cache_error: Option<LoadError>,
+    /// The turn saved in the image's sidecar, on top of its EXIF orientation
+    /// which the image already has. See `orientation::saved_orientation`.
+    pub orientation: Orientation,
+}
+
+/// The order the files of a stream are shown in, along with what it was
+/// worked out from.
+#[derive(Clone)]
+pub struct SortedFiles {
+    pub sort: SortBy,
+    /// Indexes into `ImageStream::files`, in sorted order.
+    pub order: Vec<usize>,
+    /// The info of each file, by its index.
+    pub infos: Vec<SortInfo>,
+}
+
+pub struct ImageStream {
+    files: Vec<PathBuf>,
+    receiver: Receiver<LoadedImage>,
+    sorting: Option<Receiver<SortedFiles>>,
+    sorted: Option<SortedFiles>,
+    // Where each file is in the sorted order, by its index
+    positions: Option<Vec<usize>>,
+    // The positions that have finished loading, and the results that cannot
+    // be returned yet because files before them are still loading and may
+    // take up the remaining places
+    succeeded: BTreeSet<usize>,
+    failed: BTreeSet<usize>,
+    held: Vec<LoadedImage>,
+    max_count: Option<usize>,
+    done: bool,
+    report: LoadReport,
+}
+
+impl ImageStream {
+    /// All of the files that will be attempted, in the order they were
+    /// listed. `sorted` has the order they are shown in when they are sorted.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The sorted order, once the workers have worked it out. None while they
+    /// are still reading what the files are sorted by, or if they are not
+    /// sorted. Images only arrive once the order is known.
+    pub fn sorted(&self) -> Option<&SortedFiles> {
+        self.sorted.as_ref()
+    }
+
+    /// True until the sorted order has arrived, for sorted streams.
+    pub fn is_sorting(&self) -> bool {
+        self.sorting.is_some()
+    }
+
+    /// Returns the images that finished since the last call without blocking.
+    /// With a `max`, only the first `max` files that load are returned, along
+    /// with the failures before the last of them, the same as `load_files`.
+    /// Results are held back until that is certain, which is as soon as there
+    /// are not enough files before them left to fill the places.
+    pub fn poll(&mut self) -> Vec<LoadedImage> {
+        // The order is sent before any image, so it is always received first
+        self.receive_sorted();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(loaded) => self.accept(loaded),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.done = true;
+                    break;
                 }
             }
-            let image = self.load(&file);
-            match image {
-                Ok(i) => {
-                    count += 1;
-                    loaded_files.push(file.to_str().unwrap().to_owned());
-                    images.push(i);
+        }
+        let max = match self.max_count {
+            Some(max) => max,
+            None => {
+                let results = std::mem::take(&mut self.held);
+                results.iter().for_each(|loaded| self.record(loaded));
+                return results;
+            }
+        };
+        let mut results = Vec::new();
+        for loaded in std::mem::take(&mut self.held) {
+            let position = self.rank(loaded.index);
+            // Every file before this one that has not failed may load, so at
+            // most this many of them take up places
+            let possible = position - self.failed.range(..position).count();
+            if possible < max {
+                self.record(&loaded);
+                results.push(loaded);
+            } else if self.succeeded.range(..position).count() < max && !self.done {
+                self.held.push(loaded);
+            }
+        }
+        results
+    }
+
+    /// True once every worker has finished and all results have been polled.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Everything that went wrong in the results handed out so far, for the
+    /// caller to show once it is done.
+    pub fn report(&self) -> &LoadReport {
+        &self.report
+    }
+
+    fn record(&mut self, loaded: &LoadedImage) {
+        if let Err(err) = &loaded.image {
+            self.report.failures.push(err.clone());
+        }
+    }
+
+    fn accept(&mut self, loaded: LoadedImage) {
+        self.report.failures.extend(loaded.cache_error.clone());
+        let position = self.rank(loaded.index);
+        if loaded.image.is_ok() {
+            self.succeeded.insert(position);
+        } else {
+            self.failed.insert(position);
+        }
+        self.held.push(loaded);
+    }
+
+    fn receive_sorted(&mut self) {
+        let sorted = match &self.sorting {
+            Some(sorting) => match sorting.try_recv() {
+                Ok(sorted) => sorted,
+                Err(TryRecvError::Empty) => return,
+                // Nothing gets loaded if the sorting thread died
+                Err(TryRecvError::Disconnected) => {
+                    self.sorting = None;
+                    return;
+                }
+            },
+            None => return,
+        };
+        let mut positions = vec![0; sorted.order.len()];
+        for (position, index) in sorted.order.iter().enumerate() {
+            positions[*index] = position;
+        }
+        self.positions = Some(positions);
+        self.sorted = Some(sorted);
+        self.sorting = None;
+    }
+
+    // Where the file is in the order it is shown in
+    fn rank(&self, index: usize) -> usize {
+        self.positions
+            .as_ref()
+            .map_or(index, |positions| positions[index])
+    }
+}
+
+/// Blocks until the next image is decoded.
+impl Iterator for ImageStream {
+    type Item = LoadedImage;
+
+    fn next(&mut self) -> Option<LoadedImage> {
+        match self.receiver.recv() {
+            Ok(loaded) => {
+                self.report.failures.extend(loaded.cache_error.clone());
+                self.record(&loaded);
+                Some(loaded)
+            }
+            Err(_) => {
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+fn spawn_workers(
+    files: Vec<PathBuf>,
+    thumbnail_size: Option<(u32, u32)>,
+    cache: Option<Arc<ThumbnailCache>>,
+    max_count: Option<usize>,
+    frames: Option<usize>,
+    sort: Option<SortBy>,
+) -> ImageStream {
+    let (sender, receiver) = channel();
+    let queue: Vec<(usize, PathBuf)> = files.clone().into_iter().enumerate().collect();
+    let decode = move |queue| {
+        decode_files(queue, sender, thumbnail_size, cache, max_count, frames);
+    };
+    let sorting = match sort {
+        Some(sort) => {
+            let (sorted_sender, sorting) = channel();
+            let files = files.clone();
+            thread::spawn(move || {
+                let infos = sort::read_infos(&files, sort.key, Vec::new());
+                let order = sort.order(&infos);
+                let queue = order.iter().map(|i| (*i, files[*i].clone())).collect();
+                let sorted = SortedFiles { sort, order, infos };
+                if sorted_sender.send(sorted).is_ok() {
+                    decode(queue);
+                }
+            });
+            Some(sorting)
+        }
+        None => {
+            decode(queue);
+            None
+        }
+    };
+    ImageStream {
+        files,
+        receiver,
+        sorting,
+        sorted: None,
+        positions: None,
+        succeeded: BTreeSet::new(),
+        failed: BTreeSet::new(),
+        held: Vec::new(),
+        max_count,
+        done: false,
+        report: LoadReport::default(),
+    }
+}
+
+// Decodes the files in the order they are queued, on as many threads as
+// there are cores
+fn decode_files(
+    queue: Vec<(usize, PathBuf)>,
+    sender: Sender<LoadedImage>,
+    thumbnail_size: Option<(u32, u32)>,
+    cache: Option<Arc<ThumbnailCache>>,
+    max_count: Option<usize>,
+    frames: Option<usize>,
+) {
+    let workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(queue.len().max(1));
+    let queue = Arc::new(Mutex::new(queue.into_iter()));
+    let loaded = Arc::new(AtomicUsize::new(0));
+    for _ in 0..workers {
+        let sender = sender.clone();
+        let queue = queue.clone();
+        let loaded = loaded.clone();
+        let cache = cache.clone();
+        thread::spawn(move || loop {
+            // Stop picking up new files once enough have been decoded
+            if let Some(max) = max_count {
+                if loaded.load(Ordering::SeqCst) >= max {
+                    break;
                 }
-                Err(err) => eprintln!("{}: {}", file.display(), err),
             }
+            let next = queue.lock().ok().and_then(|mut queue| queue.next());
+            let (index, path) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            let mut cache_error = None;
+            let image = load(
+                &path,
+                thumbnail_size,
+                cache.as_deref(),
+                frames,
+                &mut cache_error,
+            );
+            let mut saved = Orientation::default();
+            if image.is_ok() {
+                loaded.fetch_add(1, Ordering::SeqCst);
+                // This may read the whole file again, which is better done
+                // here than where the image is shown
+                saved = orientation::saved_orientation(&path);
+            }
+            let loaded_image = LoadedImage {
+                index,
+                path,
+                image,
+                cache_error,
+                orientation: saved,
+            };
+            if sender.send(loaded_image).is_err() {
+                break;
+            }
+        });
+    }
+}
+
+/// Lists the immediate children of a directory.
+#[deprecated(note = "use `DirWalker::walk`, which can also descend into subdirectories")]
+pub fn list_dir(path: &Path) -> GridResult<Vec<PathBuf>> {
+    // Entries that cannot be read are left out, as they were before errors
+    // were passed on
+    DirWalker::new().walk(path).map(|(files, _)| files)
+}
+
+fn load(
+    file: &Path,
+    thumbnail_size: Option<(u32, u32)>,
+    cache: Option<&ThumbnailCache>,
+    frames: Option<usize>,
+    cache_error: &mut Option<LoadError>,
+) -> Result<(RgbaImage, Vec<Frame>), LoadError> {
+    if let (Some((width, height)), Some(cache)) = (thumbnail_size, cache) {
+        if let Some(img) = cache.get(file, width, height) {
+            return Ok((thumbnail(img, width, height), Vec::new()));
+        }
+    }
+    let contents = std::fs::read(file).map_err(|err| LoadError::io(file, err))?;
+    let decode = |source| LoadError::decode(file, source);
+    // Photos are turned upright before anything else, so that cached
+    // thumbnails are upright as well
+    let orientation = orientation::exif_orientation(&contents);
+    if let Some(max_bytes) = frames {
+        if let Some(frames) = animation::decode_frames(&contents, max_bytes).map_err(decode)? {
+            let frames: Vec<Frame> = frames
+                .into_iter()
+                .map(|frame| Frame {
+                    image: orientation.apply(frame.image),
+                    delay: frame.delay,
+                })
+                .collect();
+            return Ok((frames[0].image.clone(), frames));
+        }
+    }
+    let img = match image::load_from_memory(&contents) {
+        Ok(image::DynamicImage::ImageRgba8(img)) => img,
+        Ok(x) => x.to_rgba(),
+        // The image crate cannot read animated WebP files, so they are shown
+        // by their first frame
+        Err(err) => match animation::decode_first_frame(&contents) {
+            Ok(Some(img)) => img,
+            _ => return Err(LoadError::image(file, err)),
+        },
+    };
+    let img = orientation.apply(img);
+    // Resize to reduce GPU memory consumption
+    match thumbnail_size {
+        Some((width, height)) => {
+            if let Some(cache) = cache {
+                // A thumbnail that cannot be cached is still displayed
+                if let Err(err) = cache.put(file, width, height, &img) {
+                    *cache_error = Some(LoadError::Cache {
+                        path: file.to_path_buf(),
+                        source: Arc::new(err),
+                    });
+                }
+            }
+            Ok((thumbnail(img, width, height), Vec::new()))
         }
-        Ok((loaded_files, images))
+        None => Ok((img, Vec::new())),
+    }
+}
+
+/// Scales the image down to fit within the given size, keeping its aspect
+/// ratio. Images that already fit are returned unchanged.
+pub fn thumbnail(img: RgbaImage, width: u32, height: u32) -> RgbaImage {
+    if img.width() <= width && img.height() <= height {
+        return img;
     }
+    let scale = f64::min(
+        width as f64 / img.width() as f64,
+        height as f64 / img.height() as f64,
+    );
+    image::imageops::thumbnail(
+        &img,
+        ((img.width() as f64 * scale).round() as u32).max(1),
+        ((img.height() as f64 * scale).round() as u32).max(1),
+    )
+}
 
-    fn load(&self, file: &PathBuf) -> GridResult<Texture> {
-        let contents = std::fs::read(file).expect("Unable to read file");
-        let img = image::load_from_memory(&contents)?;
-        let img = match img {
-            image::DynamicImage::ImageRgba8(img) => img,
-            x => x.to_rgba(),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(count: usize, max: usize) -> (ImageStream, Sender<LoadedImage>) {
+        let (sender, receiver) = channel();
+        let stream = ImageStream {
+            files: (0..count).map(|i| PathBuf::from(i.to_string())).collect(),
+            receiver,
+            sorting: None,
+            sorted: None,
+            positions: None,
+            succeeded: BTreeSet::new(),
+            failed: BTreeSet::new(),
+            held: Vec::new(),
+            max_count: Some(max),
+            done: false,
+            report: LoadReport::default(),
         };
-        // TODO: Uncomment once full size display is working
-        // Resize to reduce GPU memory consumption
-        // let scale = f32::min(
-        //     200.0 as f32 / img.width() as f32,
-        //     200.0 as f32 / img.height() as f32,
-        // );
-        // let img = image::imageops::resize(
-        //     &img,
-        //     (img.width() as f32 * scale) as u32,
-        //     (img.height() as f32 * scale) as u32,
-        //     image::imageops::FilterType::Gaussian,
-        // );
-
-        Ok(Texture::from_image(&img, &TextureSettings::new()))
+        (stream, sender)
+    }
+
+    fn send(sender: &Sender<LoadedImage>, index: usize, ok: bool) {
+        let path = PathBuf::from(index.to_string());
+        let image = if ok {
+            Ok((RgbaImage::new(1, 1), Vec::new()))
+        } else {
+            Err(LoadError::UnsupportedFormat {
+                path: path.clone(),
+                message: String::new(),
+            })
+        };
+        sender
+            .send(LoadedImage {
+                index,
+                path,
+                image,
+                cache_error: None,
+                orientation: Orientation::default(),
+            })
+            .unwrap();
+    }
+
+    fn indexes(results: Vec<LoadedImage>) -> Vec<usize> {
+        let mut indexes: Vec<usize> = results.into_iter().map(|loaded| loaded.index).collect();
+        indexes.sort();
+        indexes
+    }
+
+    #[test]
+    fn max_keeps_the_first_files() {
+        let (mut stream, sender) = stream(6, 2);
+        // 3 finishes first, but 0 and 1 may still take both places
+        send(&sender, 3, true);
+        send(&sender, 1, true);
+        assert_eq!(indexes(stream.poll()), vec![1]);
+        // 0 failed, so 3 is the second file that loaded after all
+        send(&sender, 0, false);
+        send(&sender, 2, false);
+        assert_eq!(indexes(stream.poll()), vec![0, 2, 3]);
+        send(&sender, 4, true);
+        drop(sender);
+        assert!(stream.poll().is_empty());
+        assert!(stream.is_done());
+    }
+
+    #[test]
+    fn max_drops_later_files_that_finish_first() {
+        let (mut stream, sender) = stream(4, 1);
+        send(&sender, 2, true);
+        send(&sender, 3, false);
+        assert!(stream.poll().is_empty());
+        send(&sender, 0, true);
+        send(&sender, 1, false);
+        drop(sender);
+        assert_eq!(indexes(stream.poll()), vec![0]);
+    }
+
+    #[test]
+    fn report_has_the_failures_handed_out() {
+        let (mut stream, sender) = stream(4, 2);
+        send(&sender, 0, false);
+        send(&sender, 3, false);
+        send(&sender, 1, true);
+        send(&sender, 2, true);
+        drop(sender);
+        assert_eq!(indexes(stream.poll()), vec![0, 1, 2]);
+        let failures: Vec<_> = stream.report().failures.iter().map(|f| f.path()).collect();
+        assert_eq!(failures, vec![Some(Path::new("0"))]);
+    }
+
+    #[test]
+    fn report_has_thumbnails_that_were_not_cached() {
+        let (mut stream, sender) = stream(1, 1);
+        let path = PathBuf::from("0");
+        sender
+            .send(LoadedImage {
+                index: 0,
+                path: path.clone(),
+                image: Ok((RgbaImage::new(1, 1), Vec::new())),
+                cache_error: Some(LoadError::Cache {
+                    path,
+                    source: Arc::new(anyhow!("disk full")),
+                }),
+                orientation: Orientation::default(),
+            })
+            .unwrap();
+        assert_eq!(indexes(stream.poll()), vec![0]);
+        assert_eq!(
+            stream.report().to_string(),
+            "0: unable to cache thumbnail: disk full\n"
+        );
+    }
+
+    #[test]
+    fn truncated_files() {
+        let path = Path::new("a.png");
+        let eof = io::Error::new(io::ErrorKind::UnexpectedEof, "eof");
+        for err in [ImageError::NotEnoughData, ImageError::IoError(eof)] {
+            assert_eq!(LoadError::image(path, err).message(), "file is truncated");
+        }
+        let other = io::Error::other("other");
+        assert_ne!(
+            LoadError::image(path, ImageError::IoError(other)).message(),
+            "file is truncated"
+        );
+    }
+
+    #[test]
+    fn animated_webp_thumbnails() {
+        let path = std::env::temp_dir().join(format!(
+            "image_grid_test_animated_{}.webp",
+            std::process::id()
+        ));
+        std::fs::write(&path, crate::animation::tests::animated_webp()).unwrap();
+        let loaded = load(&path, Some((8, 8)), None, None, &mut None);
+        std::fs::remove_file(&path).unwrap();
+        let (image, frames) = loaded.unwrap();
+        assert_eq!(image.dimensions(), (1, 1));
+        assert!(frames.is_empty());
+    }
+
+    #[test]
+    fn max_follows_the_sorted_order() {
+        let (mut stream, sender) = stream(3, 1);
+        let (sorted_sender, sorting) = channel();
+        stream.sorting = Some(sorting);
+        sorted_sender
+            .send(SortedFiles {
+                sort: SortBy::new(crate::sort::SortKey::Name),
+                order: vec![2, 0, 1],
+                infos: Vec::new(),
+            })
+            .unwrap();
+        send(&sender, 0, true);
+        assert!(stream.poll().is_empty());
+        assert!(!stream.is_sorting());
+        send(&sender, 2, true);
+        assert_eq!(indexes(stream.poll()), vec![2]);
+    }
+
+    #[test]
+    fn streams_sort_before_loading() {
+        let mut loader = ImageLoader::new();
+        loader.sort(SortBy::new(crate::sort::SortKey::Name));
+        let files = ["b", "a", "c"]
+            .iter()
+            .map(|name| std::env::temp_dir().join(format!("image_grid_missing_{}", name)))
+            .collect();
+        let mut stream = loader.stream_files(files);
+        while !stream.is_done() {
+            stream.poll();
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(stream.sorted().unwrap().order, [1, 0, 2]);
+        assert_eq!(stream.report().failures.len(), 3);
     }
 }
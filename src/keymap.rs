@@ -0,0 +1,710 @@
+use crate::grid::GridResult;
+use anyhow::anyhow;
+use piston::input::keyboard::{Key, ModifierKey};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// Maps keys to named actions. A binding is a single key with modifiers, such
+// as "Ctrl+A" or "PageDown", or a sequence of characters typed one after the
+// other, such as "gg". Digits typed before a binding are a count, so "5j"
+// performs the action bound to "j" five times.
+//
+// Keymap files have one action per line, followed by the bindings that
+// replace its defaults, separated by spaces:
+//
+//     # vim style movement
+//     move-down = j Down
+//     first = gg Home
+//     last = G End
+//
+// Leaving out the bindings unbinds the action.
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    PageUp,
+    PageDown,
+    First,
+    Last,
+    NextGroup,
+    PreviousGroup,
+    // The same as the moves above, but also selecting the tiles in between
+    ExtendUp,
+    ExtendDown,
+    ExtendLeft,
+    ExtendRight,
+    ExtendPageUp,
+    ExtendPageDown,
+    ExtendFirst,
+    ExtendLast,
+    /// Shows the tile fullscreen, or acts on it when it already is.
+    Activate,
+    ToggleFullscreen,
+    // Zooming applies to the fullscreen tile
+    ZoomIn,
+    ZoomOut,
+    ZoomFit,
+    ZoomFitWidth,
+    /// Shows one image pixel per screen pixel.
+    ZoomActual,
+    // Turning applies to the selected tiles, or the current one
+    RotateClockwise,
+    RotateCounterclockwise,
+    Rotate180,
+    FlipHorizontal,
+    FlipVertical,
+    /// Starts or stops a slideshow.
+    Slideshow,
+    PauseSlideshow,
+    /// Pauses or resumes animated tiles.
+    PauseAnimation,
+    // Stepping through the frames of an animation pauses it
+    NextFrame,
+    PreviousFrame,
+    /// Opens a prompt that narrows the grid to the tiles matching what is
+    /// typed.
+    Search,
+    // Jump between the tiles matching the last search
+    NextMatch,
+    PreviousMatch,
+    /// Shows every tile again after a search.
+    ClearFilter,
+    /// Shows or hides the list of key bindings.
+    Help,
+    /// Shows or hides the metadata of the current tile.
+    Info,
+    /// Leaves fullscreen, or closes the grid.
+    Back,
+    Quit,
+    ToggleSelect,
+    SelectAll,
+    SelectNone,
+    InvertSelection,
+    ConfirmSelection,
+    /// An action registered by the tile handler.
+    Handler(String),
+}
+
+impl Action {
+    pub const BUILTIN: [Action; 48] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::PageUp,
+        Action::PageDown,
+        Action::First,
+        Action::Last,
+        Action::NextGroup,
+        Action::PreviousGroup,
+        Action::ExtendUp,
+        Action::ExtendDown,
+        Action::ExtendLeft,
+        Action::ExtendRight,
+        Action::ExtendPageUp,
+        Action::ExtendPageDown,
+        Action::ExtendFirst,
+        Action::ExtendLast,
+        Action::Activate,
+        Action::ToggleFullscreen,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::ZoomFit,
+        Action::ZoomFitWidth,
+        Action::ZoomActual,
+        Action::RotateClockwise,
+        Action::RotateCounterclockwise,
+        Action::Rotate180,
+        Action::FlipHorizontal,
+        Action::FlipVertical,
+        Action::Slideshow,
+        Action::PauseSlideshow,
+        Action::PauseAnimation,
+        Action::NextFrame,
+        Action::PreviousFrame,
+        Action::Search,
+        Action::NextMatch,
+        Action::PreviousMatch,
+        Action::ClearFilter,
+        Action::Help,
+        Action::Info,
+        Action::Back,
+        Action::Quit,
+        Action::ToggleSelect,
+        Action::SelectAll,
+        Action::SelectNone,
+        Action::InvertSelection,
+        Action::ConfirmSelection,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            Action::MoveUp => "move-up",
+            Action::MoveDown => "move-down",
+            Action::MoveLeft => "move-left",
+            Action::MoveRight => "move-right",
+            Action::PageUp => "page-up",
+            Action::PageDown => "page-down",
+            Action::First => "first",
+            Action::Last => "last",
+            Action::NextGroup => "next-group",
+            Action::PreviousGroup => "previous-group",
+            Action::ExtendUp => "extend-up",
+            Action::ExtendDown => "extend-down",
+            Action::ExtendLeft => "extend-left",
+            Action::ExtendRight => "extend-right",
+            Action::ExtendPageUp => "extend-page-up",
+            Action::ExtendPageDown => "extend-page-down",
+            Action::ExtendFirst => "extend-first",
+            Action::ExtendLast => "extend-last",
+            Action::Activate => "activate",
+            Action::ToggleFullscreen => "toggle-fullscreen",
+            Action::ZoomIn => "zoom-in",
+            Action::ZoomOut => "zoom-out",
+            Action::ZoomFit => "zoom-fit",
+            Action::ZoomFitWidth => "zoom-fit-width",
+            Action::ZoomActual => "zoom-actual",
+            Action::RotateClockwise => "rotate-clockwise",
+            Action::RotateCounterclockwise => "rotate-counterclockwise",
+            Action::Rotate180 => "rotate-180",
+            Action::FlipHorizontal => "flip-horizontal",
+            Action::FlipVertical => "flip-vertical",
+            Action::Slideshow => "slideshow",
+            Action::PauseSlideshow => "pause-slideshow",
+            Action::PauseAnimation => "pause-animation",
+            Action::NextFrame => "next-frame",
+            Action::PreviousFrame => "previous-frame",
+            Action::Search => "search",
+            Action::NextMatch => "next-match",
+            Action::PreviousMatch => "previous-match",
+            Action::ClearFilter => "clear-filter",
+            Action::Help => "help",
+            Action::Info => "info",
+            Action::Back => "back",
+            Action::Quit => "quit",
+            Action::ToggleSelect => "toggle-select",
+            Action::SelectAll => "select-all",
+            Action::SelectNone => "select-none",
+            Action::InvertSelection => "invert-selection",
+            Action::ConfirmSelection => "confirm-selection",
+            Action::Handler(name) => name,
+        }
+    }
+
+    /// Looks up a built in action, or one of `handler_actions`, the names of
+    /// the actions the handler registers. None if it is neither.
+    pub fn from_name(name: &str, handler_actions: &[&str]) -> Option<Action> {
+        Action::BUILTIN
+            .iter()
+            .find(|action| action.name() == name)
+            .cloned()
+            .or_else(|| {
+                handler_actions
+                    .contains(&name)
+                    .then(|| Action::Handler(name.to_string()))
+            })
+    }
+
+    fn default_bindings(&self) -> &'static [&'static str] {
+        match self {
+            Action::MoveUp => &["Up"],
+            Action::MoveDown => &["Down"],
+            Action::MoveLeft => &["Left"],
+            Action::MoveRight => &["Right"],
+            Action::PageUp => &["PageUp"],
+            Action::PageDown => &["PageDown"],
+            Action::First => &["Home"],
+            Action::Last => &["End"],
+            Action::NextGroup => &["]"],
+            Action::PreviousGroup => &["["],
+            Action::ExtendUp => &["Shift+Up"],
+            Action::ExtendDown => &["Shift+Down"],
+            Action::ExtendLeft => &["Shift+Left"],
+            Action::ExtendRight => &["Shift+Right"],
+            Action::ExtendPageUp => &["Shift+PageUp"],
+            Action::ExtendPageDown => &["Shift+PageDown"],
+            Action::ExtendFirst => &["Shift+Home"],
+            Action::ExtendLast => &["Shift+End"],
+            Action::Activate => &["Return"],
+            Action::ToggleFullscreen => &["f"],
+            // "+" is Shift+= on most keyboards
+            Action::ZoomIn => &["=", "Shift+="],
+            Action::ZoomOut => &["-"],
+            Action::ZoomFit => &["w"],
+            Action::ZoomFitWidth => &["e"],
+            Action::ZoomActual => &["a"],
+            Action::RotateClockwise => &["r"],
+            Action::RotateCounterclockwise => &["R"],
+            Action::Rotate180 => &["Ctrl+R"],
+            Action::FlipHorizontal => &["h"],
+            Action::FlipVertical => &["v"],
+            Action::Slideshow => &["F5"],
+            Action::PauseSlideshow => &["p"],
+            Action::PauseAnimation => &["k"],
+            Action::NextFrame => &["."],
+            Action::PreviousFrame => &[","],
+            // "?" on most keyboards
+            Action::Search => &["/"],
+            Action::NextMatch => &["n"],
+            Action::PreviousMatch => &["N"],
+            Action::ClearFilter => &["Ctrl+/"],
+            Action::Help => &["F1", "Shift+/"],
+            Action::Info => &["i"],
+            Action::Back => &["Escape"],
+            Action::Quit => &["Ctrl+Q"],
+            Action::ToggleSelect => &["Space"],
+            Action::SelectAll => &["Ctrl+A"],
+            Action::SelectNone => &["Ctrl+Shift+A"],
+            Action::InvertSelection => &["Ctrl+I"],
+            Action::ConfirmSelection => &["Ctrl+Return"],
+            Action::Handler(_) => &[],
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A key together with the modifiers held down while pressing it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Chord {
+    pub key: Key,
+    pub modifiers: ModifierKey,
+}
+
+impl Chord {
+    pub fn new(key: Key, modifiers: ModifierKey) -> Chord {
+        Chord { key, modifiers }
+    }
+}
+
+/// Written the way `parse_binding` reads it: "g", "G", "Ctrl+A" or "PageUp".
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = key_char(self.key).filter(|c| *c != '+');
+        if let Some(c) = c {
+            if self.modifiers == ModifierKey::NO_MODIFIER {
+                return write!(f, "{}", c);
+            }
+            if self.modifiers == ModifierKey::SHIFT && c.is_ascii_lowercase() {
+                return write!(f, "{}", c.to_ascii_uppercase());
+            }
+        }
+        for (modifier, name) in MODIFIERS.iter() {
+            if self.modifiers.contains(*modifier) {
+                write!(f, "{}+", name)?;
+            }
+        }
+        match c {
+            Some(c) => write!(f, "{}", c.to_ascii_uppercase()),
+            // The other keys are named after their variants
+            None => write!(f, "{:?}", self.key),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Binding {
+    pub keys: Vec<Chord>,
+    pub action: Action,
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Sequences can only be made of single characters
+        for chord in &self.keys {
+            write!(f, "{}", chord)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    bindings: Vec<Binding>,
+    // Actions whose bindings were set explicitly, which handler defaults must
+    // not override.
+    configured: HashSet<Action>,
+    // The keys typed so far of a sequence, and the count typed before it
+    pending: Vec<Chord>,
+    count: Option<usize>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Keymap {
+    /// The default bindings of the grid's own actions.
+    pub fn new() -> Keymap {
+        let mut keymap = Keymap {
+            bindings: Vec::new(),
+            configured: HashSet::new(),
+            pending: Vec::new(),
+            count: None,
+        };
+        for action in Action::BUILTIN.iter() {
+            for binding in action.default_bindings() {
+                keymap.add(parse_binding(binding).unwrap(), action.clone());
+            }
+        }
+        keymap
+    }
+
+    /// The default bindings, changed by the lines of a keymap file. Actions
+    /// must be built in or among `handler_actions`, so that a misspelled name
+    /// does not take keys away from the action it was meant for.
+    pub fn parse(config: &str, handler_actions: &[&str]) -> GridResult<Keymap> {
+        let mut keymap = Keymap::new();
+        for (n, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let bindings = parts
+                .next()
+                .ok_or_else(|| anyhow!("line {}: expected 'action = keys'", n + 1))?;
+            if name.is_empty() {
+                return Err(anyhow!("line {}: missing action name", n + 1));
+            }
+            let keys = bindings
+                .split_whitespace()
+                .map(parse_binding)
+                .collect::<GridResult<Vec<_>>>()
+                .map_err(|err| anyhow!("line {}: {}", n + 1, err))?;
+            let action = Action::from_name(name, handler_actions)
+                .ok_or_else(|| anyhow!("line {}: unknown action '{}'", n + 1, name))?;
+            keymap.bind(action, keys);
+        }
+        Ok(keymap)
+    }
+
+    pub fn load(path: &Path, handler_actions: &[&str]) -> GridResult<Keymap> {
+        let config = fs::read_to_string(path)?;
+        Keymap::parse(&config, handler_actions)
+            .map_err(|err| anyhow!("{}: {}", path.display(), err))
+    }
+
+    /// Replaces the bindings of the action. The keys are taken away from any
+    /// other action they were bound to.
+    pub fn bind(&mut self, action: Action, keys: Vec<Vec<Chord>>) {
+        self.bindings.retain(|binding| binding.action != action);
+        for keys in keys {
+            self.add(keys, action.clone());
+        }
+        self.configured.insert(action);
+    }
+
+    /// Adds the default bindings of an action registered by the handler,
+    /// unless the action was configured or the keys are already taken.
+    pub fn register(&mut self, action: Action, bindings: &[&str]) -> GridResult<()> {
+        if self.configured.contains(&action) {
+            return Ok(());
+        }
+        for binding in bindings {
+            let keys = parse_binding(binding)?;
+            if !self.bindings.iter().any(|b| b.keys == keys) {
+                self.bindings.push(Binding {
+                    keys,
+                    action: action.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    /// The bindings of a single action.
+    pub fn bindings_for<'a>(&'a self, action: &'a Action) -> impl Iterator<Item = &'a Binding> {
+        self.bindings
+            .iter()
+            .filter(move |binding| binding.action == *action)
+    }
+
+    /// Feeds a key press to the keymap. Returns the action and how many times
+    /// to perform it once a binding is complete.
+    pub fn key(&mut self, chord: Chord) -> Option<(Action, usize)> {
+        // Escape abandons a partly typed sequence or count
+        if chord.key == Key::Escape && (!self.pending.is_empty() || self.count.is_some()) {
+            self.reset();
+            return None;
+        }
+        self.pending.push(chord);
+        if let Some(binding) = self.bindings.iter().find(|b| b.keys == self.pending) {
+            let action = binding.action.clone();
+            let count = self.count.unwrap_or(1);
+            self.reset();
+            return Some((action, count));
+        }
+        if self
+            .bindings
+            .iter()
+            .any(|b| b.keys.starts_with(&self.pending))
+        {
+            return None;
+        }
+        self.pending.pop();
+        if self.pending.is_empty() {
+            if let Some(digit) = count_digit(chord, self.count) {
+                self.count = Some(self.count.unwrap_or(0).saturating_mul(10) + digit);
+                return None;
+            }
+        }
+        self.reset();
+        None
+    }
+
+    fn add(&mut self, keys: Vec<Chord>, action: Action) {
+        self.bindings.retain(|binding| binding.keys != keys);
+        self.bindings.push(Binding { keys, action });
+    }
+
+    fn reset(&mut self) {
+        self.pending.clear();
+        self.count = None;
+    }
+}
+
+// A digit pressed without modifiers continues the count. Like in vim, a count
+// cannot start with 0.
+fn count_digit(chord: Chord, count: Option<usize>) -> Option<usize> {
+    if chord.modifiers != ModifierKey::NO_MODIFIER {
+        return None;
+    }
+    let digit = key_char(chord.key)?.to_digit(10)? as usize;
+    if digit == 0 && count.is_none() {
+        return None;
+    }
+    Some(digit)
+}
+
+/// Whether the key only changes what other keys do, like Shift. Pressing one
+/// while typing a count or a sequence, such as the Shift in "5G", must not
+/// abandon it.
+pub fn is_modifier(key: Key) -> bool {
+    matches!(
+        key,
+        Key::LShift
+            | Key::RShift
+            | Key::LCtrl
+            | Key::RCtrl
+            | Key::LAlt
+            | Key::RAlt
+            | Key::LGui
+            | Key::RGui
+            | Key::CapsLock
+            | Key::Mode
+    )
+}
+
+const MODIFIERS: [(ModifierKey, &str); 4] = [
+    (ModifierKey::CTRL, "Ctrl"),
+    (ModifierKey::ALT, "Alt"),
+    (ModifierKey::GUI, "Super"),
+    (ModifierKey::SHIFT, "Shift"),
+];
+
+const NAMED_KEYS: [(&str, Key); 17] = [
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("PageUp", Key::PageUp),
+    ("PageDown", Key::PageDown),
+    ("Home", Key::Home),
+    ("End", Key::End),
+    ("Return", Key::Return),
+    ("Enter", Key::Return),
+    ("Escape", Key::Escape),
+    ("Space", Key::Space),
+    ("Tab", Key::Tab),
+    ("Backspace", Key::Backspace),
+    ("Delete", Key::Delete),
+    ("Insert", Key::Insert),
+    ("Plus", Key::Plus),
+];
+
+// The character printed on the key, for letters, digits and punctuation.
+fn key_char(key: Key) -> Option<char> {
+    let code = key as u32;
+    if code > 0x20 && code < 0x7f {
+        std::char::from_u32(code)
+    } else {
+        None
+    }
+}
+
+fn parse_key(name: &str) -> GridResult<Key> {
+    if let Some((_, key)) = NAMED_KEYS
+        .iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+    {
+        return Ok(*key);
+    }
+    if let Some(n) = name
+        .strip_prefix('F')
+        .and_then(|n| n.parse::<u32>().ok())
+        .filter(|n| (1..=12).contains(n))
+    {
+        return Ok(Key::from(Key::F1 as u32 + n - 1));
+    }
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_graphic() => match Key::from(c.to_ascii_lowercase() as u32) {
+            Key::Unknown => Err(anyhow!("Unknown key '{}'", name)),
+            key => Ok(key),
+        },
+        _ => Err(anyhow!("Unknown key '{}'", name)),
+    }
+}
+
+// A single character stands for the key that types it, so an upper case
+// letter is the letter with Shift held down.
+fn char_chord(c: char) -> GridResult<Chord> {
+    let modifiers = if c.is_ascii_uppercase() {
+        ModifierKey::SHIFT
+    } else {
+        ModifierKey::NO_MODIFIER
+    };
+    Ok(Chord::new(parse_key(&c.to_string())?, modifiers))
+}
+
+/// Parses "Ctrl+Shift+A", "PageDown", "G" or "gg" into the keys to press.
+pub fn parse_binding(binding: &str) -> GridResult<Vec<Chord>> {
+    if binding.len() > 1 && binding.contains('+') {
+        let mut parts: Vec<&str> = binding.split('+').collect();
+        let key = parts.pop().unwrap();
+        let mut modifiers = ModifierKey::NO_MODIFIER;
+        for part in parts {
+            let (modifier, _) = MODIFIERS
+                .iter()
+                .find(|(_, name)| name.eq_ignore_ascii_case(part))
+                .ok_or_else(|| anyhow!("Unknown modifier '{}' in '{}'", part, binding))?;
+            modifiers |= *modifier;
+        }
+        // "Ctrl+a" and "Ctrl+A" are the same key
+        return Ok(vec![Chord::new(parse_key(key)?, modifiers)]);
+    }
+    if let Ok(key) = parse_key(binding) {
+        if binding.chars().count() > 1 {
+            return Ok(vec![Chord::new(key, ModifierKey::NO_MODIFIER)]);
+        }
+    }
+    binding.chars().map(char_chord).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(key: Key) -> Chord {
+        Chord::new(key, ModifierKey::NO_MODIFIER)
+    }
+
+    fn press(keymap: &mut Keymap, keys: &[Chord]) -> Option<(Action, usize)> {
+        let (last, keys) = keys.split_last().unwrap();
+        for key in keys {
+            assert_eq!(keymap.key(*key), None);
+        }
+        keymap.key(*last)
+    }
+
+    #[test]
+    fn parse_binding_keys() {
+        assert_eq!(parse_binding("g").unwrap(), [chord(Key::G)]);
+        assert_eq!(
+            parse_binding("G").unwrap(),
+            [Chord::new(Key::G, ModifierKey::SHIFT)]
+        );
+        assert_eq!(parse_binding("gg").unwrap(), [chord(Key::G), chord(Key::G)]);
+        assert_eq!(parse_binding("PageDown").unwrap(), [chord(Key::PageDown)]);
+        assert_eq!(parse_binding("F5").unwrap(), [chord(Key::F5)]);
+        assert_eq!(parse_binding("+").unwrap(), [chord(Key::Plus)]);
+        assert_eq!(
+            parse_binding("Ctrl+Shift+a").unwrap(),
+            [Chord::new(Key::A, ModifierKey::CTRL | ModifierKey::SHIFT)]
+        );
+        assert!(parse_binding("Hyper+A").is_err());
+        assert!(parse_binding("Ctrl+Nothing").is_err());
+    }
+
+    #[test]
+    fn chords_print_as_parsed() {
+        for binding in &["g", "G", "Ctrl+A", "PageUp", "Ctrl+Shift+Z"] {
+            let keys = parse_binding(binding).unwrap();
+            assert_eq!(keys[0].to_string(), *binding);
+        }
+    }
+
+    #[test]
+    fn sequences() {
+        let mut keymap = Keymap::parse("first = gg\nlast = G", &[]).unwrap();
+        assert_eq!(
+            press(&mut keymap, &[chord(Key::G), chord(Key::G)]),
+            Some((Action::First, 1))
+        );
+        // A key that does not continue the sequence starts over
+        assert_eq!(press(&mut keymap, &[chord(Key::G), chord(Key::X)]), None);
+        assert_eq!(
+            press(&mut keymap, &[chord(Key::G), chord(Key::G)]),
+            Some((Action::First, 1))
+        );
+    }
+
+    #[test]
+    fn counts() {
+        let mut keymap = Keymap::parse("move-down = j\nlast = G", &[]).unwrap();
+        assert_eq!(
+            press(
+                &mut keymap,
+                &[chord(Key::D1), chord(Key::D2), chord(Key::J)]
+            ),
+            Some((Action::MoveDown, 12))
+        );
+        assert_eq!(
+            press(
+                &mut keymap,
+                &[chord(Key::D5), Chord::new(Key::G, ModifierKey::SHIFT)]
+            ),
+            Some((Action::Last, 5))
+        );
+        // A count cannot start with 0, and is forgotten after Escape
+        assert_eq!(keymap.key(chord(Key::D0)), None);
+        assert_eq!(
+            press(
+                &mut keymap,
+                &[chord(Key::D3), chord(Key::Escape), chord(Key::J)]
+            ),
+            Some((Action::MoveDown, 1))
+        );
+        // The count is used once
+        assert_eq!(keymap.key(chord(Key::J)), Some((Action::MoveDown, 1)));
+    }
+
+    #[test]
+    fn unknown_actions() {
+        let err = Keymap::parse("first = gg\nmove-dwon = j", &[]).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: unknown action 'move-dwon'");
+        assert!(Keymap::parse("next-sort = s", &[]).is_err());
+        let mut keymap = Keymap::parse("next-sort = s", &["next-sort"]).unwrap();
+        assert_eq!(
+            keymap.key(chord(Key::S)),
+            Some((Action::Handler("next-sort".to_string()), 1))
+        );
+    }
+}
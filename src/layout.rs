@@ -0,0 +1,432 @@
+use std::cmp::{max, min};
+use std::ops::Range;
+
+const SCROLLBAR_WIDTH: usize = 8;
+const MIN_THUMB_HEIGHT: f64 = 20.0;
+
+/// [x, y, width, height] in content coordinates, i.e. before scrolling.
+pub type Rect = [f64; 4];
+
+// Pure layout calculations for the grid. Nothing in here touches a window or a
+// graphics backend so that positioning, hit-testing and scrolling can be
+// exercised without a GPU.
+//
+// margin: the total space between items the grid
+// border_margin: the space between the window edge and the outermost tiles
+// caption_height: space below each tile for its caption
+#[derive(Clone, Debug)]
+pub struct GridLayout {
+    tile_width: usize,
+    tile_height: usize,
+    margin: usize,
+    border_margin: usize,
+    caption_height: usize,
+    tile_count: usize,
+    width: f64,
+    height: f64,
+    tiles_per_row: usize,
+    margin_to_center: usize,
+}
+
+impl GridLayout {
+    pub fn new(tile_width: usize, tile_height: usize, tile_count: usize) -> GridLayout {
+        GridLayout {
+            tile_width,
+            tile_height,
+            margin: 5,
+            border_margin: 20,
+            caption_height: 0,
+            tile_count,
+            width: 0.0,
+            height: 0.0,
+            tiles_per_row: 1,
+            margin_to_center: 0,
+        }
+    }
+
+    pub fn with_margins(mut self, margin: usize, border_margin: usize) -> GridLayout {
+        self.margin = margin;
+        self.border_margin = border_margin;
+        self.relayout();
+        self
+    }
+
+    pub fn resize(&mut self, width: f64, height: f64) {
+        self.width = width;
+        self.height = height;
+        self.relayout();
+    }
+
+    pub fn set_caption_height(&mut self, caption_height: usize) {
+        self.caption_height = caption_height;
+    }
+
+    pub fn caption_height(&self) -> usize {
+        self.caption_height
+    }
+
+    pub fn set_tile_count(&mut self, tile_count: usize) {
+        self.tile_count = tile_count;
+    }
+
+    fn relayout(&mut self) {
+        let width = self.width.max(0.0) as usize;
+        let remaining_width = (width + self.margin).saturating_sub(self.border_margin * 2);
+        let tile_margin_width = self.tile_width + self.margin;
+        // TODO: tiles per row and margin to center do not handle case where
+        // tiles per row is greater than the number of tiles to display which
+        // leads to a not fully centered grid since all tiles can fit on a single
+        // row.
+        self.tiles_per_row = remaining_width / tile_margin_width;
+        self.margin_to_center = remaining_width % tile_margin_width / 2;
+        // minimum tiles per row is 1 regardless of window size
+        if self.tiles_per_row == 0 {
+            self.tiles_per_row = 1;
+            self.margin_to_center = 0;
+        }
+    }
+
+    pub fn tile_width(&self) -> usize {
+        self.tile_width
+    }
+
+    pub fn tile_height(&self) -> usize {
+        self.tile_height
+    }
+
+    pub fn tile_count(&self) -> usize {
+        self.tile_count
+    }
+
+    pub fn tiles_per_row(&self) -> usize {
+        self.tiles_per_row
+    }
+
+    pub fn width(&self) -> f64 {
+        self.width
+    }
+
+    pub fn height(&self) -> f64 {
+        self.height
+    }
+
+    /// Vertical distance between the top of one row and the top of the next.
+    pub fn row_height(&self) -> f64 {
+        (self.tile_height + self.caption_height + self.margin) as f64
+    }
+
+    pub fn rows(&self) -> usize {
+        self.tile_count.div_ceil(self.tiles_per_row)
+    }
+
+    pub fn row_of(&self, i: usize) -> usize {
+        i / self.tiles_per_row
+    }
+
+    /// Total height of all rows including the border above and below them.
+    pub fn content_height(&self) -> f64 {
+        if self.tile_count == 0 {
+            return (self.border_margin * 2) as f64;
+        }
+        (self.border_margin * 2) as f64 + self.rows() as f64 * self.row_height()
+            - self.margin as f64
+    }
+
+    pub fn max_scroll(&self) -> f64 {
+        (self.content_height() - self.height).max(0.0)
+    }
+
+    pub fn clamp_scroll(&self, scroll_pos: f64) -> f64 {
+        scroll_pos.max(0.0).min(self.max_scroll())
+    }
+
+    pub fn tile_rect(&self, i: usize) -> Rect {
+        let column = i % self.tiles_per_row;
+        let row = i / self.tiles_per_row;
+        let x = self.margin_to_center
+            + self.border_margin
+            + column * self.tile_width
+            + column * self.margin;
+        let y = self.border_margin as f64 + row as f64 * self.row_height();
+        [x as f64, y, self.tile_width as f64, self.tile_height as f64]
+    }
+
+    /// The tile together with its caption below it.
+    pub fn cell_rect(&self, i: usize) -> Rect {
+        let [x, y, w, h] = self.tile_rect(i);
+        [x, y, w, h + self.caption_height as f64]
+    }
+
+    /// Indexes of the tiles that are at least partially visible when the view
+    /// is scrolled to `scroll_pos`.
+    pub fn visible_range(&self, scroll_pos: f64) -> Range<usize> {
+        if self.tile_count == 0 {
+            return 0..0;
+        }
+        let top = scroll_pos - self.border_margin as f64;
+        let first_row = (top / self.row_height()).floor().max(0.0) as usize;
+        // The row the bottom edge of the window is in
+        let last_row = ((top + self.height) / self.row_height()).floor().max(0.0) as usize;
+        let start = min(first_row * self.tiles_per_row, self.tile_count);
+        let end = min((last_row + 1) * self.tiles_per_row, self.tile_count);
+        start..end
+    }
+
+    /// Finds the tile containing the point, which is given in content
+    /// coordinates (add the scroll position to window coordinates).
+    pub fn tile_at(&self, x: f64, y: f64) -> Option<usize> {
+        if self.tile_count == 0 {
+            return None;
+        }
+        let left = (self.margin_to_center + self.border_margin) as f64;
+        let top = self.border_margin as f64;
+        if x < left || y < top {
+            return None;
+        }
+        let column = ((x - left) / (self.tile_width + self.margin) as f64) as usize;
+        let row = ((y - top) / self.row_height()) as usize;
+        if column >= self.tiles_per_row {
+            return None;
+        }
+        let i = row * self.tiles_per_row + column;
+        if i >= self.tile_count {
+            return None;
+        }
+        let [tx, ty, tw, th] = self.cell_rect(i);
+        if x <= tx + tw && y <= ty + th {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the scroll position that brings tile `i` fully into view while
+    /// moving the view as little as possible.
+    pub fn scroll_to_show(&self, i: usize, scroll_pos: f64) -> f64 {
+        if self.tile_count == 0 {
+            return 0.0;
+        }
+        let [_x, y, _w, h] = self.cell_rect(i);
+        let mut scroll_pos = scroll_pos;
+        if y + h > scroll_pos + self.height {
+            scroll_pos = y + h + self.border_margin as f64 - self.height;
+        }
+        if y < scroll_pos {
+            scroll_pos = y - self.border_margin as f64;
+        }
+        self.clamp_scroll(scroll_pos)
+    }
+
+    /// Whether the content is taller than the window.
+    pub fn scrollable(&self) -> bool {
+        self.max_scroll() > 0.0
+    }
+
+    /// The scrollbar along the right edge of the window, in window
+    /// coordinates. It fits within the border so it never covers tiles.
+    pub fn scrollbar_track(&self) -> Rect {
+        let width = min(SCROLLBAR_WIDTH, self.border_margin) as f64;
+        [self.width - width, 0.0, width, self.height]
+    }
+
+    /// The part of the scrollbar that stands for the visible rows.
+    pub fn scrollbar_thumb(&self, scroll_pos: f64) -> Rect {
+        let [x, _y, w, h] = self.scrollbar_track();
+        let thumb_height = (h * h / self.content_height()).max(MIN_THUMB_HEIGHT).min(h);
+        let max_scroll = self.max_scroll();
+        let fraction = if max_scroll > 0.0 {
+            self.clamp_scroll(scroll_pos) / max_scroll
+        } else {
+            0.0
+        };
+        [x, fraction * (h - thumb_height), w, thumb_height]
+    }
+
+    /// The scroll position that puts the top of the thumb at `thumb_y`.
+    pub fn scroll_for_thumb(&self, thumb_y: f64) -> f64 {
+        let [_x, _y, _w, h] = self.scrollbar_track();
+        let [_x, _y, _w, thumb_height] = self.scrollbar_thumb(0.0);
+        if h <= thumb_height {
+            return 0.0;
+        }
+        self.clamp_scroll(thumb_y / (h - thumb_height) * self.max_scroll())
+    }
+
+    /// Returns the scroll position that puts tile `i` in the middle of the
+    /// view, as far as the content allows.
+    pub fn scroll_to_center(&self, i: usize) -> f64 {
+        if self.tile_count == 0 {
+            return 0.0;
+        }
+        let [_x, y, _w, h] = self.cell_rect(i);
+        self.clamp_scroll(y + h / 2.0 - self.height / 2.0)
+    }
+
+    pub fn up(&self, i: usize) -> usize {
+        i.saturating_sub(self.tiles_per_row)
+    }
+
+    pub fn down(&self, i: usize) -> usize {
+        min(self.last(), i + self.tiles_per_row)
+    }
+
+    /// Moves a page of whole rows up, or as far as possible.
+    pub fn page_up(&self, i: usize) -> usize {
+        i.saturating_sub(self.rows_per_page() * self.tiles_per_row)
+    }
+
+    pub fn page_down(&self, i: usize) -> usize {
+        min(self.last(), i + self.rows_per_page() * self.tiles_per_row)
+    }
+
+    /// The number of whole rows that fit in the window, at least one.
+    pub fn rows_per_page(&self) -> usize {
+        max(1, (self.height / self.row_height()) as usize)
+    }
+
+    pub fn left(&self, i: usize) -> usize {
+        i.saturating_sub(1)
+    }
+
+    pub fn right(&self, i: usize) -> usize {
+        min(self.last(), i + 1)
+    }
+
+    pub fn last(&self) -> usize {
+        self.tile_count.saturating_sub(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 100 pixel tiles, 10 pixels apart, 20 from the window edge. In a window
+    // 370 wide three tiles fit on a row with 5 pixels left over on each side.
+    fn layout(tile_count: usize, width: f64, height: f64) -> GridLayout {
+        let mut layout = GridLayout::new(100, 100, tile_count).with_margins(10, 20);
+        layout.resize(width, height);
+        layout
+    }
+
+    #[test]
+    fn tile_rect() {
+        let layout = layout(7, 370.0, 250.0);
+        assert_eq!(layout.tiles_per_row(), 3);
+        assert_eq!(layout.tile_rect(0), [25.0, 20.0, 100.0, 100.0]);
+        assert_eq!(layout.tile_rect(2), [245.0, 20.0, 100.0, 100.0]);
+        assert_eq!(layout.tile_rect(4), [135.0, 130.0, 100.0, 100.0]);
+        // The last row only has one tile, which starts at the left
+        assert_eq!(layout.tile_rect(6), [25.0, 240.0, 100.0, 100.0]);
+        assert_eq!(layout.rows(), 3);
+        assert_eq!(layout.content_height(), 360.0);
+    }
+
+    #[test]
+    fn tile_at() {
+        let layout = layout(7, 370.0, 250.0);
+        assert_eq!(layout.tile_at(25.0, 20.0), Some(0));
+        assert_eq!(layout.tile_at(124.0, 119.0), Some(0));
+        assert_eq!(layout.tile_at(140.0, 140.0), Some(4));
+        assert_eq!(layout.tile_at(30.0, 250.0), Some(6));
+        // In the border, between tiles, and where the last row ends
+        assert_eq!(layout.tile_at(10.0, 10.0), None);
+        assert_eq!(layout.tile_at(130.0, 50.0), None);
+        assert_eq!(layout.tile_at(50.0, 125.0), None);
+        assert_eq!(layout.tile_at(140.0, 250.0), None);
+        assert_eq!(layout.tile_at(360.0, 50.0), None);
+    }
+
+    #[test]
+    fn visible_range() {
+        let layout = layout(7, 370.0, 250.0);
+        assert_eq!(layout.visible_range(0.0), 0..7);
+        let layout = self::layout(30, 370.0, 250.0);
+        // Rows 0 to 2, the last of which is cut off by the bottom edge
+        assert_eq!(layout.visible_range(0.0), 0..9);
+        assert_eq!(layout.visible_range(300.0), 6..15);
+        assert_eq!(layout.visible_range(layout.max_scroll()), 21..30);
+    }
+
+    #[test]
+    fn scroll_to_show() {
+        let layout = layout(7, 370.0, 250.0);
+        assert_eq!(layout.max_scroll(), 110.0);
+        // Tiles that are already visible do not move the view
+        assert_eq!(layout.scroll_to_show(3, 0.0), 0.0);
+        assert_eq!(layout.scroll_to_show(6, 0.0), 110.0);
+        assert_eq!(layout.scroll_to_show(0, 110.0), 0.0);
+        assert_eq!(layout.scroll_to_show(4, 50.0), 50.0);
+    }
+
+    #[test]
+    fn clamp_scroll() {
+        let layout = layout(7, 370.0, 250.0);
+        assert_eq!(layout.clamp_scroll(-5.0), 0.0);
+        assert_eq!(layout.clamp_scroll(60.0), 60.0);
+        assert_eq!(layout.clamp_scroll(500.0), 110.0);
+        // Content that fits in the window does not scroll
+        let layout = self::layout(3, 370.0, 250.0);
+        assert_eq!(layout.clamp_scroll(50.0), 0.0);
+        assert!(!layout.scrollable());
+    }
+
+    #[test]
+    fn navigation() {
+        let layout = layout(7, 370.0, 250.0);
+        assert_eq!(layout.last(), 6);
+        assert_eq!(layout.up(4), 1);
+        assert_eq!(layout.up(1), 0);
+        assert_eq!(layout.down(1), 4);
+        // Moving down into the partial last row stops at the last tile
+        assert_eq!(layout.down(4), 6);
+        assert_eq!(layout.down(5), 6);
+        assert_eq!(layout.down(6), 6);
+        assert_eq!(layout.left(3), 2);
+        assert_eq!(layout.left(0), 0);
+        assert_eq!(layout.right(2), 3);
+        assert_eq!(layout.right(6), 6);
+    }
+
+    #[test]
+    fn no_tiles() {
+        let layout = layout(0, 370.0, 250.0);
+        assert_eq!(layout.rows(), 0);
+        assert_eq!(layout.last(), 0);
+        assert_eq!(layout.up(0), 0);
+        assert_eq!(layout.down(0), 0);
+        assert_eq!(layout.left(0), 0);
+        assert_eq!(layout.right(0), 0);
+        assert_eq!(layout.visible_range(0.0), 0..0);
+        assert_eq!(layout.tile_at(30.0, 30.0), None);
+        assert_eq!(layout.scroll_to_show(0, 0.0), 0.0);
+        assert_eq!(layout.clamp_scroll(10.0), 0.0);
+    }
+
+    #[test]
+    fn one_column() {
+        let layout = layout(3, 150.0, 250.0);
+        assert_eq!(layout.tiles_per_row(), 1);
+        assert_eq!(layout.tile_rect(2), [25.0, 240.0, 100.0, 100.0]);
+        assert_eq!(layout.tile_at(30.0, 140.0), Some(1));
+        assert_eq!(layout.down(0), 1);
+        assert_eq!(layout.up(2), 1);
+        assert_eq!(layout.right(0), 1);
+        assert_eq!(layout.visible_range(0.0), 0..3);
+        assert_eq!(layout.scroll_to_show(2, 0.0), 110.0);
+    }
+
+    #[test]
+    fn narrower_than_a_tile() {
+        // There is always at least one tile per row, even if it does not fit
+        let layout = layout(3, 50.0, 250.0);
+        assert_eq!(layout.tiles_per_row(), 1);
+        assert_eq!(layout.tile_rect(0), [20.0, 20.0, 100.0, 100.0]);
+        assert_eq!(layout.tile_rect(1), [20.0, 130.0, 100.0, 100.0]);
+        assert_eq!(layout.tile_at(110.0, 30.0), Some(0));
+        assert_eq!(layout.down(0), 1);
+        let layout = self::layout(3, 0.0, 0.0);
+        assert_eq!(layout.tiles_per_row(), 1);
+        assert_eq!(layout.visible_range(0.0), 0..1);
+    }
+}
@@ -1,2 +1,22 @@
+pub mod animation;
+pub mod dir_walker;
+pub mod exif;
+pub mod gamepad;
 pub mod grid;
 pub mod image_loader;
+pub mod keymap;
+pub mod layout;
+pub mod metadata;
+pub mod orientation;
+pub mod placeholder;
+pub mod png_text;
+pub mod render;
+pub mod search;
+pub mod selection;
+pub mod slideshow;
+pub mod software;
+pub mod sort;
+pub mod text;
+pub mod texture_cache;
+pub mod thumbnail_cache;
+pub mod zoom;
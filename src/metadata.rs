@@ -0,0 +1,263 @@
+use crate::exif;
+use crate::png_text;
+use image::{ColorType, ImageDecoder, ImageFormat};
+use std::io::Cursor;
+
+/// What is known about an image file besides its pixels, read from its
+/// header and embedded metadata. Anything that cannot be read is left out.
+pub struct Metadata {
+    pub format: Option<ImageFormat>,
+    pub dimensions: Option<(u32, u32)>,
+    /// The color type and bit depth the file is stored with, before it is
+    /// converted to 8 bit RGBA for display.
+    pub color_type: Option<ColorType>,
+    /// Whether an ICC color profile is embedded.
+    pub icc_profile: bool,
+    /// EXIF fields, as returned by `exif::fields`.
+    pub exif: Vec<(&'static str, String)>,
+    /// The keywords and text of PNG text chunks, such as the parameters
+    /// generated images were made with.
+    pub text: Vec<(String, String)>,
+}
+
+impl Metadata {
+    pub fn read(contents: &[u8]) -> Metadata {
+        let format = image::guess_format(contents).ok();
+        let (dimensions, color_type) = match format {
+            Some(format) => header(contents, format),
+            None => (None, None),
+        };
+        let text = match format {
+            Some(ImageFormat::PNG) => png_text::read_text(contents),
+            _ => Vec::new(),
+        };
+        Metadata {
+            format,
+            dimensions,
+            color_type,
+            icc_profile: format.is_some_and(|format| has_icc_profile(contents, format)),
+            exif: exif::fields(contents),
+            text,
+        }
+    }
+
+    /// Labels and values for people to read, in the order they are shown.
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+        if let Some(format) = self.format {
+            entries.push(("Format".to_string(), format!("{:?}", format)));
+        }
+        if let Some((width, height)) = self.dimensions {
+            entries.push(("Dimensions".to_string(), format!("{}×{}", width, height)));
+        }
+        if let Some(color_type) = self.color_type {
+            let (color, bits) = describe(color_type);
+            entries.push(("Color".to_string(), color.to_string()));
+            entries.push(("Bit depth".to_string(), bits.to_string()));
+        }
+        if self.format.is_some() {
+            let icc_profile = if self.icc_profile { "Yes" } else { "No" };
+            entries.push(("ICC profile".to_string(), icc_profile.to_string()));
+        }
+        for (label, value) in &self.exif {
+            entries.push((label.to_string(), value.clone()));
+        }
+        entries.extend(self.text.iter().cloned());
+        entries
+    }
+}
+
+// The dimensions and color type, from the header alone where the decoders
+// allow it.
+fn header(contents: &[u8], format: ImageFormat) -> (Option<(u32, u32)>, Option<ColorType>) {
+    fn decoded<'a, D: ImageDecoder<'a>>(
+        decoder: image::ImageResult<D>,
+    ) -> (Option<(u32, u32)>, Option<ColorType>) {
+        match decoder {
+            Ok(decoder) => {
+                let (width, height) = decoder.dimensions();
+                (
+                    Some((width as u32, height as u32)),
+                    Some(decoder.colortype()),
+                )
+            }
+            Err(_) => (None, None),
+        }
+    }
+    let reader = Cursor::new(contents);
+    match format {
+        // The PNG decoder reports the color type it converts to, so the header
+        // is read directly
+        ImageFormat::PNG => match png_text::chunks(contents).first() {
+            Some(chunk) if &chunk.kind == b"IHDR" && chunk.data.len() == 13 => {
+                let data = chunk.data;
+                let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+                let bits = data[8];
+                let color_type = match data[9] {
+                    0 => Some(ColorType::Gray(bits)),
+                    2 => Some(ColorType::RGB(bits)),
+                    3 => Some(ColorType::Palette(bits)),
+                    4 => Some(ColorType::GrayA(bits)),
+                    6 => Some(ColorType::RGBA(bits)),
+                    _ => None,
+                };
+                (Some((width, height)), color_type)
+            }
+            _ => (None, None),
+        },
+        ImageFormat::JPEG => decoded(image::jpeg::JPEGDecoder::new(reader)),
+        // GIF frames are always indexed, whatever the decoder converts them to
+        ImageFormat::GIF => (
+            decoded(image::gif::Decoder::new(reader)).0,
+            Some(ColorType::Palette(8)),
+        ),
+        ImageFormat::WEBP => decoded(image::webp::WebpDecoder::new(reader)),
+        ImageFormat::BMP => decoded(image::bmp::BMPDecoder::new(reader)),
+        ImageFormat::TIFF => decoded(image::tiff::TIFFDecoder::new(reader)),
+        _ => match image::io::Reader::with_format(reader, format).into_dimensions() {
+            Ok(dimensions) => (Some(dimensions), None),
+            Err(_) => (None, None),
+        },
+    }
+}
+
+fn has_icc_profile(contents: &[u8], format: ImageFormat) -> bool {
+    match format {
+        ImageFormat::PNG => png_text::chunks(contents)
+            .iter()
+            .any(|chunk| &chunk.kind == b"iCCP"),
+        // Large profiles are split over several segments, which all start the
+        // same way
+        ImageFormat::JPEG => exif::jpeg_segments(contents)
+            .into_iter()
+            .any(|(marker, data)| marker == 0xE2 && contents[data].starts_with(b"ICC_PROFILE\0")),
+        // The extended header has a flag for it
+        ImageFormat::WEBP => {
+            contents.get(12..16) == Some(&b"VP8X"[..])
+                && contents.get(20).is_some_and(|flags| flags & 0x20 != 0)
+        }
+        _ => false,
+    }
+}
+
+fn describe(color_type: ColorType) -> (&'static str, String) {
+    let (color, bits) = match color_type {
+        ColorType::Gray(bits) => ("Grayscale", bits),
+        ColorType::GrayA(bits) => ("Grayscale with alpha", bits),
+        ColorType::RGB(bits) | ColorType::BGR(bits) => ("RGB", bits),
+        ColorType::RGBA(bits) | ColorType::BGRA(bits) => ("RGB with alpha", bits),
+        ColorType::Palette(bits) => return ("Indexed", format!("{} bits per pixel", bits)),
+    };
+    (color, format!("{} bits per channel", bits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(color_type: u8, bits: u8, chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&3u32.to_be_bytes());
+        header.extend_from_slice(&2u32.to_be_bytes());
+        header.extend_from_slice(&[bits, color_type, 0, 0, 0]);
+        let mut png = png_text::SIGNATURE.to_vec();
+        png_text::write_chunk(&mut png, b"IHDR", &header);
+        for (kind, data) in chunks {
+            png_text::write_chunk(&mut png, kind, data);
+        }
+        png_text::write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    fn entry(label: &str, value: &str) -> (String, String) {
+        (label.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn png_header_and_text() {
+        let png = png(
+            6,
+            16,
+            &[(b"iCCP", b"sRGB\0\0"), (b"tEXt", b"parameters\0steps: 20")],
+        );
+        assert_eq!(
+            Metadata::read(&png).entries(),
+            vec![
+                entry("Format", "PNG"),
+                entry("Dimensions", "3×2"),
+                entry("Color", "RGB with alpha"),
+                entry("Bit depth", "16 bits per channel"),
+                entry("ICC profile", "Yes"),
+                entry("parameters", "steps: 20"),
+            ]
+        );
+    }
+
+    #[test]
+    fn png_color_types() {
+        let color = |color_type, bits| Metadata::read(&png(color_type, bits, &[])).color_type;
+        assert_eq!(color(0, 1), Some(ColorType::Gray(1)));
+        assert_eq!(color(2, 8), Some(ColorType::RGB(8)));
+        assert_eq!(color(3, 4), Some(ColorType::Palette(4)));
+        assert_eq!(color(4, 8), Some(ColorType::GrayA(8)));
+        assert_eq!(color(6, 8), Some(ColorType::RGBA(8)));
+        assert_eq!(color(5, 8), None);
+        assert_eq!(
+            describe(ColorType::Palette(4)),
+            ("Indexed", "4 bits per pixel".to_string())
+        );
+        assert_eq!(
+            describe(ColorType::GrayA(8)),
+            ("Grayscale with alpha", "8 bits per channel".to_string())
+        );
+    }
+
+    #[test]
+    fn jpeg_header_and_profile() {
+        let mut jpeg = vec![0xFF, 0xD8];
+        let mut segment = |marker: u8, data: &[u8]| {
+            jpeg.extend_from_slice(&[0xFF, marker]);
+            jpeg.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+            jpeg.extend_from_slice(data);
+        };
+        segment(0xE2, b"ICC_PROFILE\0\x01\x01");
+        // 8 bits, 2 rows of 3 pixels, and 3 components
+        segment(
+            0xC0,
+            &[8, 0, 2, 0, 3, 3, 1, 0x11, 0, 2, 0x11, 0, 3, 0x11, 0],
+        );
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+        let metadata = Metadata::read(&jpeg);
+        assert_eq!(metadata.format, Some(ImageFormat::JPEG));
+        assert_eq!(metadata.dimensions, Some((3, 2)));
+        assert_eq!(metadata.color_type, Some(ColorType::RGB(8)));
+        assert!(metadata.icc_profile);
+        assert!(metadata.exif.is_empty());
+    }
+
+    #[test]
+    fn webp_profile_flag() {
+        let webp = |flags: u8| {
+            let mut webp = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0".to_vec();
+            webp.extend_from_slice(&[flags, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            webp
+        };
+        assert!(has_icc_profile(&webp(0x20), ImageFormat::WEBP));
+        assert!(!has_icc_profile(&webp(0x02), ImageFormat::WEBP));
+    }
+
+    #[test]
+    fn unknown_files() {
+        let metadata = Metadata::read(b"not an image");
+        assert_eq!(metadata.format, None);
+        assert!(metadata.entries().is_empty());
+        // A PNG without a header is still a PNG
+        let mut png = png_text::SIGNATURE.to_vec();
+        png_text::write_chunk(&mut png, b"IEND", &[]);
+        assert_eq!(
+            Metadata::read(&png).entries(),
+            vec![entry("Format", "PNG"), entry("ICC profile", "No")]
+        );
+    }
+}
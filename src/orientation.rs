@@ -0,0 +1,453 @@
+use crate::exif;
+use crate::grid::GridResult;
+use anyhow::anyhow;
+use graphics::math::{multiply, Matrix2d};
+use image::{imageops, RgbaImage};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// How an image is turned to be shown upright: flipped horizontally or not,
+/// and then rotated clockwise by a number of quarter turns. These are the
+/// eight orientations EXIF describes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Orientation {
+    flip: bool,
+    turns: u8,
+}
+
+impl Orientation {
+    /// Reads an EXIF orientation value.
+    pub fn from_exif(value: u16) -> Option<Orientation> {
+        let (flip, turns) = match value {
+            1 => (false, 0),
+            2 => (true, 0),
+            3 => (false, 2),
+            4 => (true, 2),
+            5 => (true, 3),
+            6 => (false, 1),
+            7 => (true, 1),
+            8 => (false, 3),
+            _ => return None,
+        };
+        Some(Orientation { flip, turns })
+    }
+
+    pub fn exif(self) -> u16 {
+        match (self.flip, self.turns) {
+            (false, 0) => 1,
+            (true, 0) => 2,
+            (false, 2) => 3,
+            (true, 2) => 4,
+            (true, 3) => 5,
+            (false, 1) => 6,
+            (true, 1) => 7,
+            _ => 8,
+        }
+    }
+
+    pub fn rotate_clockwise(self) -> Orientation {
+        self.rotate(1)
+    }
+
+    pub fn rotate_counterclockwise(self) -> Orientation {
+        self.rotate(3)
+    }
+
+    pub fn rotate_180(self) -> Orientation {
+        self.rotate(2)
+    }
+
+    /// Mirrors the image as it is currently shown, left to right.
+    pub fn flip_horizontal(self) -> Orientation {
+        // Flipping after a rotation is the same as flipping first and then
+        // rotating the other way
+        Orientation {
+            flip: !self.flip,
+            turns: (4 - self.turns) % 4,
+        }
+    }
+
+    /// Mirrors the image as it is currently shown, top to bottom.
+    pub fn flip_vertical(self) -> Orientation {
+        self.flip_horizontal().rotate_180()
+    }
+
+    /// This orientation followed by `other`.
+    pub fn then(self, other: Orientation) -> Orientation {
+        let flipped = if other.flip {
+            self.flip_horizontal()
+        } else {
+            self
+        };
+        flipped.rotate(other.turns)
+    }
+
+    /// The orientation that undoes this one.
+    pub fn inverse(self) -> Orientation {
+        if self.flip {
+            // Every flipped orientation is a mirror image, which undoes itself
+            self
+        } else {
+            Orientation {
+                flip: false,
+                turns: (4 - self.turns) % 4,
+            }
+        }
+    }
+
+    /// Whether width and height trade places.
+    pub fn swaps_dimensions(self) -> bool {
+        self.turns % 2 == 1
+    }
+
+    /// The size of an image of the given size once it is turned.
+    pub fn size<T>(self, width: T, height: T) -> (T, T) {
+        if self.swaps_dimensions() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Maps the pixels of a `width` by `height` image to where they are shown,
+    /// with the turned image's top left corner at the origin.
+    pub fn matrix(self, width: f64, height: f64) -> Matrix2d {
+        let mut matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        if self.flip {
+            matrix = [[-1.0, 0.0, width], [0.0, 1.0, 0.0]];
+        }
+        let (mut width, mut height) = (width, height);
+        for _ in 0..self.turns {
+            matrix = multiply([[0.0, -1.0, height], [1.0, 0.0, 0.0]], matrix);
+            std::mem::swap(&mut width, &mut height);
+        }
+        matrix
+    }
+
+    /// Turns the pixels of the image.
+    pub fn apply(self, image: RgbaImage) -> RgbaImage {
+        let image = if self.flip {
+            imageops::flip_horizontal(&image)
+        } else {
+            image
+        };
+        match self.turns {
+            1 => imageops::rotate90(&image),
+            2 => imageops::rotate180(&image),
+            3 => imageops::rotate270(&image),
+            _ => image,
+        }
+    }
+
+    fn rotate(self, turns: u8) -> Orientation {
+        Orientation {
+            flip: self.flip,
+            turns: (self.turns + turns) % 4,
+        }
+    }
+}
+
+/// The orientation of a JPEG, PNG or WebP file according to its EXIF data.
+pub fn exif_orientation(contents: &[u8]) -> Orientation {
+    exif::orientation(contents)
+        .and_then(|tag| Orientation::from_exif(tag.value))
+        .unwrap_or_default()
+}
+
+/// Where rotations are saved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveTarget {
+    /// The EXIF orientation in the file itself, for files that have one.
+    /// Other files get a sidecar.
+    File,
+    /// An XMP sidecar file next to the image, which other photo managers read
+    /// too.
+    Sidecar,
+}
+
+impl FromStr for SaveTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> GridResult<SaveTarget> {
+        match s {
+            "file" => Ok(SaveTarget::File),
+            "sidecar" => Ok(SaveTarget::Sidecar),
+            _ => Err(anyhow!("Unknown place to save rotations '{}'", s)),
+        }
+    }
+}
+
+// The sidecar only needs to hold the orientation, in the tiff namespace the
+// way XMP maps EXIF tags.
+const SIDECAR: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:tiff="http://ns.adobe.com/tiff/1.0/" tiff:Orientation="1"/>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+const PROPERTY: &str = "tiff:Orientation";
+
+/// The sidecar of "photo.jpg" is "photo.jpg.xmp", so that files which only
+/// differ in their extension do not share one.
+pub fn sidecar_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".xmp");
+    PathBuf::from(path)
+}
+
+/// How the image has to be turned in addition to its EXIF orientation, which
+/// `ImageLoader` applies, for the orientation in its sidecar. Images without a
+/// sidecar are left as they are.
+pub fn saved_orientation(file: &Path) -> Orientation {
+    let sidecar = match fs::read_to_string(sidecar_path(file)) {
+        Ok(sidecar) => sidecar,
+        Err(_) => return Orientation::default(),
+    };
+    let orientation = match sidecar_orientation(&sidecar) {
+        Some(orientation) => orientation,
+        None => return Orientation::default(),
+    };
+    let exif = fs::read(file)
+        .map(|contents| exif_orientation(&contents))
+        .unwrap_or_default();
+    exif.inverse().then(orientation)
+}
+
+/// Saves that the image is now turned by `to` instead of `from`, which are
+/// relative to how it was loaded, like `saved_orientation`. Only the change is
+/// applied to what is saved, so it does not matter whether the file itself
+/// changed since it was loaded. A sidecar is used when there already is one,
+/// or when the file has no orientation tag to change.
+pub fn save_orientation(
+    file: &Path,
+    from: Orientation,
+    to: Orientation,
+    target: SaveTarget,
+) -> GridResult<()> {
+    let mut contents = fs::read(file)?;
+    let tag = exif::orientation(&contents);
+    let sidecar = sidecar_path(file);
+    let xmp = fs::read_to_string(&sidecar).ok();
+    let saved = xmp
+        .as_deref()
+        .and_then(sidecar_orientation)
+        .or_else(|| tag.and_then(|tag| Orientation::from_exif(tag.value)))
+        .unwrap_or_default();
+    let orientation = saved.then(from.inverse()).then(to);
+    match (tag, xmp) {
+        (Some(tag), None) if target == SaveTarget::File => {
+            exif::set_orientation(&mut contents, &tag, orientation.exif());
+            write_atomically(file, &contents)?;
+        }
+        (_, Some(xmp)) => {
+            let xmp = set_sidecar_orientation(&xmp, orientation)
+                .ok_or_else(|| anyhow!("{}: no place for the orientation", sidecar.display()))?;
+            write_atomically(&sidecar, xmp.as_bytes())?;
+        }
+        (_, None) => write_atomically(
+            &sidecar,
+            set_sidecar_orientation(SIDECAR, orientation)
+                .unwrap()
+                .as_bytes(),
+        )?,
+    }
+    Ok(())
+}
+
+// Writes to a temporary file in the same directory and renames it over the
+// file, so that a crash or a full disk never leaves a photo half written. The
+// file keeps its permissions.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    let result = (|| {
+        let mut temp = fs::File::create(&temp_path)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            temp.set_permissions(metadata.permissions())?;
+        }
+        temp.write_all(contents)?;
+        temp.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+// XMP properties are either attributes, tiff:Orientation="6", or elements,
+// <tiff:Orientation>6</tiff:Orientation>. Either way the value is the digit
+// after the property name.
+fn sidecar_orientation(xmp: &str) -> Option<Orientation> {
+    let digit = orientation_digit(xmp)?;
+    Orientation::from_exif((xmp.as_bytes()[digit] - b'0') as u16)
+}
+
+fn set_sidecar_orientation(xmp: &str, orientation: Orientation) -> Option<String> {
+    let value = orientation.exif().to_string();
+    if let Some(digit) = orientation_digit(xmp) {
+        return Some(format!("{}{}{}", &xmp[..digit], value, &xmp[digit + 1..]));
+    }
+    // Sidecars written by other programs may not mention the orientation yet
+    let description = xmp.find("<rdf:Description")? + "<rdf:Description".len();
+    let namespace = if xmp.contains("xmlns:tiff=") {
+        ""
+    } else {
+        r#" xmlns:tiff="http://ns.adobe.com/tiff/1.0/""#
+    };
+    Some(format!(
+        r#"{}{} {}="{}"{}"#,
+        &xmp[..description],
+        namespace,
+        PROPERTY,
+        value,
+        &xmp[description..]
+    ))
+}
+
+fn orientation_digit(xmp: &str) -> Option<usize> {
+    let start = xmp.find(PROPERTY)? + PROPERTY.len();
+    let digit = start + xmp[start..].find(|c: char| c.is_ascii_digit() || c == '/')?;
+    match xmp.as_bytes()[digit] {
+        b'1'..=b'8' => Some(digit),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graphics::math::transform_pos;
+    use image::Rgba;
+
+    fn all() -> Vec<Orientation> {
+        (1..=8).filter_map(Orientation::from_exif).collect()
+    }
+
+    // Every pixel is different, so that any turn or flip shows
+    fn image() -> RgbaImage {
+        RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8, y as u8, 0, 255]))
+    }
+
+    // Images cannot be compared themselves
+    fn pixels(image: RgbaImage) -> (u32, u32, Vec<u8>) {
+        (image.width(), image.height(), image.into_raw())
+    }
+
+    fn shown(orientation: Orientation) -> (u32, u32, Vec<u8>) {
+        pixels(orientation.apply(image()))
+    }
+
+    #[test]
+    fn exif_values() {
+        assert_eq!(all().len(), 8);
+        for value in 1..=8 {
+            assert_eq!(Orientation::from_exif(value).unwrap().exif(), value);
+        }
+        assert_eq!(Orientation::from_exif(0), None);
+        assert_eq!(Orientation::from_exif(9), None);
+        assert_eq!(Orientation::default().exif(), 1);
+    }
+
+    #[test]
+    fn then_applies_one_after_the_other() {
+        for first in all() {
+            for second in all() {
+                assert_eq!(
+                    shown(first.then(second)),
+                    pixels(second.apply(first.apply(image()))),
+                    "{:?} then {:?}",
+                    first,
+                    second
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes() {
+        for orientation in all() {
+            let inverse = orientation.inverse();
+            assert_eq!(orientation.then(inverse), Orientation::default());
+            assert_eq!(inverse.then(orientation), Orientation::default());
+            assert_eq!(
+                pixels(inverse.apply(orientation.apply(image()))),
+                pixels(image())
+            );
+        }
+    }
+
+    #[test]
+    fn turns_and_flips_act_on_the_image_as_shown() {
+        for orientation in all() {
+            let image = orientation.apply(image());
+            assert_eq!(
+                shown(orientation.rotate_clockwise()),
+                pixels(imageops::rotate90(&image))
+            );
+            assert_eq!(
+                shown(orientation.rotate_counterclockwise()),
+                pixels(imageops::rotate270(&image))
+            );
+            assert_eq!(
+                shown(orientation.rotate_180()),
+                pixels(imageops::rotate180(&image))
+            );
+            assert_eq!(
+                shown(orientation.flip_horizontal()),
+                pixels(imageops::flip_horizontal(&image))
+            );
+            assert_eq!(
+                shown(orientation.flip_vertical()),
+                pixels(imageops::flip_vertical(&image))
+            );
+            assert_eq!(orientation.size(3, 2), image.dimensions());
+        }
+    }
+
+    #[test]
+    fn matrix_puts_pixels_where_apply_does() {
+        let image = image();
+        for orientation in all() {
+            let shown = orientation.apply(image.clone());
+            let matrix = orientation.matrix(3.0, 2.0);
+            for (x, y, pixel) in image.enumerate_pixels() {
+                let [sx, sy] = transform_pos(matrix, [x as f64 + 0.5, y as f64 + 0.5]);
+                assert_eq!(
+                    shown.get_pixel(sx.floor() as u32, sy.floor() as u32),
+                    pixel,
+                    "{:?} at {}, {}",
+                    orientation,
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn write_atomically_replaces_the_file() {
+        let dir = std::env::temp_dir().join(format!("image_grid_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("photo.jpg.xmp");
+        write_atomically(&path, b"first").unwrap();
+        write_atomically(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        // Nothing is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sidecar_orientation_round_trips() {
+        let turned = Orientation::default().rotate_clockwise();
+        let xmp = set_sidecar_orientation(SIDECAR, turned).unwrap();
+        assert_eq!(sidecar_orientation(&xmp), Some(turned));
+        // Sidecars of other programs without the property
+        let other = "<x:xmpmeta><rdf:RDF><rdf:Description rdf:about=\"\"/></rdf:RDF></x:xmpmeta>";
+        let xmp = set_sidecar_orientation(other, turned).unwrap();
+        assert_eq!(sidecar_orientation(&xmp), Some(turned));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::grid::{Color, GridResult};
+use crate::render::ImageTexture;
+use crate::text::TextRenderer;
+use graphics::math::Matrix2d;
+use graphics::types::FontSize;
+use graphics::{CircleArc, DrawState, Graphics, Line, Rectangle, Transformed};
+use std::f64::consts::PI;
+
+const FONT_SIZE: FontSize = 11;
+
+// Turns of the loading spinner per second
+const SPIN_SPEED: f64 = 0.8;
+
+// The outline of a landscape in a picture frame, as fractions of the icon's
+// size, and the crack through it
+const MOUNTAINS: [[f64; 2]; 5] = [
+    [0.1, 0.85],
+    [0.35, 0.45],
+    [0.55, 0.7],
+    [0.7, 0.55],
+    [0.9, 0.85],
+];
+const CRACK: [[f64; 2]; 4] = [[0.58, 0.0], [0.44, 0.35], [0.62, 0.6], [0.48, 1.0]];
+
+/// What a tile without an image shows instead, which is marked on top of its
+/// placeholder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placeholder {
+    /// The image has not been decoded yet.
+    Loading,
+    /// The file could not be read or decoded.
+    Broken,
+    /// The file does not exist.
+    Missing,
+}
+
+impl Placeholder {
+    /// Draws the marker in the middle of a `width` by `height` tile whose top
+    /// left corner is at the origin of `transform`. Spinners turn with `time`,
+    /// in seconds.
+    pub fn draw<G>(
+        self,
+        text: &mut TextRenderer<G::Texture>,
+        color: Color,
+        time: f64,
+        [width, height]: [f64; 2],
+        transform: Matrix2d,
+        g: &mut G,
+    ) -> GridResult<()>
+    where
+        G: Graphics,
+        G::Texture: ImageTexture,
+    {
+        let size = (width.min(height) * 0.3).clamp(8.0, 64.0);
+        let thickness = (size / 16.0).max(1.5);
+        let [x, y] = [(width - size) / 2.0, (height - size) / 2.0];
+        let state = DrawState::default();
+        match self {
+            Placeholder::Loading => {
+                let start = (time * SPIN_SPEED).fract() * 2.0 * PI;
+                CircleArc::new(color, thickness / 2.0, start, start + 1.5 * PI).draw(
+                    [x, y, size, size],
+                    &state,
+                    transform,
+                    g,
+                );
+            }
+            Placeholder::Broken => {
+                // A frame in landscape format
+                let frame = [x, y + size * 0.1, size, size * 0.8];
+                let point =
+                    |[px, py]: [f64; 2]| [frame[0] + px * frame[2], frame[1] + py * frame[3]];
+                Rectangle::new_border(color, thickness / 2.0).draw(frame, &state, transform, g);
+                let line = Line::new_round(color, thickness / 2.0);
+                for points in [&MOUNTAINS[..], &CRACK[..]] {
+                    for pair in points.windows(2) {
+                        line.draw_from_to(point(pair[0]), point(pair[1]), &state, transform, g);
+                    }
+                }
+            }
+            Placeholder::Missing => {
+                let label = text.elide("Missing", FONT_SIZE, width);
+                let [label_width, label_height] =
+                    [text.width(&label, FONT_SIZE), text.line_height(FONT_SIZE)];
+                let [x, y] = [(width - label_width) / 2.0, (height - label_height) / 2.0];
+                text.draw(
+                    &label,
+                    color,
+                    FONT_SIZE,
+                    transform.trans(x.round(), y.round()),
+                    g,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
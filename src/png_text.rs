@@ -0,0 +1,179 @@
+// Minimal access to the chunks of PNG files, which the image crate does not
+// expose. Used to store and validate thumbnail metadata, to take animated PNGs
+// apart and to show the text stored in PNG files.
+
+pub const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+// Compressed text can expand a thousandfold, so a small file could otherwise
+// take up any amount of memory
+const MAX_TEXT: usize = 1024 * 1024;
+
+pub struct Chunk<'a> {
+    pub kind: [u8; 4],
+    pub data: &'a [u8],
+    /// Offset of the chunk's length field in the file.
+    pub offset: usize,
+}
+
+/// Iterates over the chunks of a PNG file. Stops at the first truncated or
+/// malformed chunk, so a prefix of a file can be passed in.
+pub fn chunks(png: &[u8]) -> Vec<Chunk<'_>> {
+    let mut chunks = Vec::new();
+    if png.len() < SIGNATURE.len() || png[..SIGNATURE.len()] != SIGNATURE {
+        return chunks;
+    }
+    let mut offset = SIGNATURE.len();
+    while offset + 8 <= png.len() {
+        let length = u32::from_be_bytes([
+            png[offset],
+            png[offset + 1],
+            png[offset + 2],
+            png[offset + 3],
+        ]) as usize;
+        let start = offset + 8;
+        let end = match start.checked_add(length) {
+            Some(end) if end + 4 <= png.len() => end,
+            _ => break,
+        };
+        let kind = [
+            png[offset + 4],
+            png[offset + 5],
+            png[offset + 6],
+            png[offset + 7],
+        ];
+        chunks.push(Chunk {
+            kind,
+            data: &png[start..end],
+            offset,
+        });
+        if &kind == b"IEND" {
+            break;
+        }
+        offset = end + 4;
+    }
+    chunks
+}
+
+/// Returns the keyword and text of every tEXt, zTXt and iTXt chunk, in the
+/// order they appear. Chunks that cannot be decompressed, or that decompress
+/// to more than a megabyte, are left out.
+pub fn read_text(png: &[u8]) -> Vec<(String, String)> {
+    chunks(png)
+        .iter()
+        .filter_map(|chunk| {
+            let separator = chunk.data.iter().position(|b| *b == 0)?;
+            let keyword = latin1(&chunk.data[..separator]);
+            let rest = &chunk.data[separator + 1..];
+            let text = match &chunk.kind {
+                b"tEXt" => latin1(rest),
+                // The text is preceded by the compression method
+                b"zTXt" => latin1(&inflate_text(rest.get(1..)?, MAX_TEXT)?),
+                // The text is preceded by whether it is compressed, the
+                // compression method, a language tag and a translated keyword
+                b"iTXt" => {
+                    let (compressed, rest) = (*rest.first()? != 0, rest.get(2..)?);
+                    let language = rest.iter().position(|b| *b == 0)?;
+                    let rest = &rest[language + 1..];
+                    let translated = rest.iter().position(|b| *b == 0)?;
+                    let text = &rest[translated + 1..];
+                    if compressed {
+                        let text = inflate_text(text, MAX_TEXT)?;
+                        String::from_utf8_lossy(&text).into_owned()
+                    } else {
+                        String::from_utf8_lossy(text).into_owned()
+                    }
+                }
+                _ => return None,
+            };
+            Some((keyword, text))
+        })
+        .collect()
+}
+
+fn inflate_text(mut data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    let mut stream = inflate::InflateStream::from_zlib();
+    let mut text = Vec::new();
+    loop {
+        let (used, output) = stream.update(data).ok()?;
+        if output.is_empty() {
+            return Some(text);
+        }
+        if text.len() + output.len() > max_len {
+            return None;
+        }
+        text.extend_from_slice(output);
+        data = &data[used..];
+    }
+}
+
+/// Inserts tEXt chunks directly after the IHDR chunk so that they can be read
+/// from the beginning of the file.
+pub fn insert_text(png: &[u8], text: &[(&str, &str)]) -> Vec<u8> {
+    let after_header = match chunks(png).first() {
+        Some(header) if &header.kind == b"IHDR" => header.offset + 12 + header.data.len(),
+        _ => return png.to_vec(),
+    };
+    let mut out = Vec::with_capacity(png.len());
+    out.extend_from_slice(&png[..after_header]);
+    for (keyword, value) in text {
+        let mut data = keyword.as_bytes().to_vec();
+        data.push(0);
+        data.extend_from_slice(value.as_bytes());
+        write_chunk(&mut out, b"tEXt", &data);
+    }
+    out.extend_from_slice(&png[after_header..]);
+    out
+}
+
+pub fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "image_grid " 40 times, compressed with zlib
+    const COMPRESSED: [u8; 24] = [
+        120, 218, 203, 204, 77, 76, 79, 141, 79, 47, 202, 76, 81, 200, 28, 101, 14, 29, 38, 0, 115,
+        97, 166, 65,
+    ];
+
+    #[test]
+    fn inflate_is_capped() {
+        let text = inflate_text(&COMPRESSED, 440).unwrap();
+        assert_eq!(text, "image_grid ".repeat(40).as_bytes());
+        assert_eq!(inflate_text(&COMPRESSED, 439), None);
+    }
+
+    #[test]
+    fn compressed_text_chunks() {
+        let mut png = SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &[0; 13]);
+        let mut ztxt = b"Comment\0\0".to_vec();
+        ztxt.extend_from_slice(&COMPRESSED);
+        write_chunk(&mut png, b"zTXt", &ztxt);
+        let mut itxt = b"Title\0\x01\0en\0\0".to_vec();
+        itxt.extend_from_slice(&COMPRESSED);
+        write_chunk(&mut png, b"iTXt", &itxt);
+        write_chunk(&mut png, b"IEND", &[]);
+        let text = "image_grid ".repeat(40);
+        assert_eq!(
+            read_text(&png),
+            [
+                ("Comment".to_string(), text.clone()),
+                ("Title".to_string(), text)
+            ]
+        );
+    }
+}
@@ -0,0 +1,20 @@
+use graphics::ImageSize;
+use image::RgbaImage;
+use opengl_graphics::{Filter, Texture, TextureSettings};
+use texture::{CreateTexture, UpdateTexture};
+
+/// Textures that can be created from decoded image data. Each graphics backend
+/// the grid draws with provides an implementation for its texture type. The
+/// texture traits let the glyph cache render text into them.
+pub trait ImageTexture:
+    ImageSize + Sized + CreateTexture<(), Error = String> + UpdateTexture<()>
+{
+    fn from_rgba(img: &RgbaImage) -> Self;
+}
+
+impl ImageTexture for Texture {
+    fn from_rgba(img: &RgbaImage) -> Self {
+        // Magnified pixels stay sharp so they can be told apart when zoomed in
+        Texture::from_image(img, &TextureSettings::new().mag(Filter::Nearest))
+    }
+}
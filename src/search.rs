@@ -0,0 +1,206 @@
+use crate::grid::GridResult;
+use regex::Regex;
+use std::fmt;
+
+/// How a search query is matched against paths.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Paths containing the query. Case is ignored unless the query has upper
+    /// case letters.
+    Substring,
+    /// Shell style wildcards: `*` and `?` within a path component, `**`
+    /// across components and `[abc]` for sets. The pattern has to match the
+    /// end of the path from the start of a component, so `*.png` matches file
+    /// names and `cats/*.png` the directory as well. Case is ignored as for
+    /// substrings.
+    Glob,
+    /// A regular expression found anywhere in the path.
+    Regex,
+}
+
+impl SearchMode {
+    /// The mode after this one, for switching between them while typing.
+    pub fn next(self) -> SearchMode {
+        match self {
+            SearchMode::Substring => SearchMode::Glob,
+            SearchMode::Glob => SearchMode::Regex,
+            SearchMode::Regex => SearchMode::Substring,
+        }
+    }
+}
+
+impl fmt::Display for SearchMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SearchMode::Substring => "substring",
+            SearchMode::Glob => "glob",
+            SearchMode::Regex => "regex",
+        })
+    }
+}
+
+/// A query, compiled for matching paths.
+#[derive(Clone, Debug)]
+pub struct Search {
+    query: String,
+    mode: SearchMode,
+    regex: Regex,
+}
+
+impl Search {
+    /// Fails for regular expressions that do not compile.
+    pub fn new(query: &str, mode: SearchMode) -> GridResult<Search> {
+        // Smart case, as in editors
+        let case = if query.chars().any(char::is_uppercase) {
+            ""
+        } else {
+            "(?i)"
+        };
+        let pattern = match mode {
+            SearchMode::Substring => format!("{}{}", case, regex::escape(query)),
+            SearchMode::Glob => format!("{}(^|/){}$", case, glob_to_regex(query)),
+            SearchMode::Regex => query.to_string(),
+        };
+        Ok(Search {
+            query: query.to_string(),
+            mode,
+            regex: Regex::new(&pattern)?,
+        })
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn mode(&self) -> SearchMode {
+        self.mode
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.regex.is_match(path)
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                let rest: String = chars.clone().collect();
+                let set = match rest.find(']') {
+                    Some(end) if end > 0 => &rest[..end],
+                    // Not a set after all
+                    _ => {
+                        regex.push_str(r"\[");
+                        continue;
+                    }
+                };
+                for _ in 0..=set.chars().count() {
+                    chars.next();
+                }
+                let set = set.replace('\\', r"\\").replace('[', r"\[");
+                match set.strip_prefix('!') {
+                    Some(negated) => regex.push_str(&format!("[^{}]", negated)),
+                    None => regex.push_str(&format!("[{}]", set)),
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(query: &str, mode: SearchMode, path: &str) -> bool {
+        Search::new(query, mode).unwrap().matches(path)
+    }
+
+    fn glob(query: &str, path: &str) -> bool {
+        matches(query, SearchMode::Glob, path)
+    }
+
+    #[test]
+    fn stars() {
+        assert!(glob("*.png", "cat.png"));
+        assert!(glob("*.png", "photos/cats/cat.png"));
+        assert!(!glob("*.png", "cat.png.bak"));
+        assert!(!glob("*.png", "catpng"));
+        // A single star stays within a component
+        assert!(glob("cats/*.png", "photos/cats/cat.png"));
+        assert!(!glob("cats/*.png", "photos/cats/old/cat.png"));
+        // Two cross them
+        assert!(glob("cats/**.png", "photos/cats/old/cat.png"));
+        assert!(glob("photos/**/cat.png", "/home/photos/cats/old/cat.png"));
+        assert!(glob("**", "anything/at/all"));
+    }
+
+    #[test]
+    fn globs_match_from_the_start_of_a_component() {
+        assert!(glob("cat.png", "cat.png"));
+        assert!(glob("cat.png", "/photos/cat.png"));
+        assert!(!glob("cat.png", "/photos/bobcat.png"));
+        assert!(!glob("cats/*.png", "/photos/bobcats/cat.png"));
+    }
+
+    #[test]
+    fn question_marks() {
+        assert!(glob("?.png", "photos/a.png"));
+        assert!(!glob("?.png", "photos/ab.png"));
+        assert!(!glob("a?b.png", "a/b.png"));
+    }
+
+    #[test]
+    fn sets() {
+        assert!(glob("[ab].png", "a.png"));
+        assert!(glob("[ab].png", "b.png"));
+        assert!(!glob("[ab].png", "c.png"));
+        assert!(glob("[a-c].png", "b.png"));
+        assert!(glob("[!ab].png", "c.png"));
+        assert!(!glob("[!ab].png", "a.png"));
+        assert!(glob("[[].png", "[.png"));
+        assert!(glob(r"[\].png", r"\.png"));
+    }
+
+    #[test]
+    fn brackets_that_are_not_sets() {
+        // Unterminated, or empty
+        assert!(glob("[ab.png", "[ab.png"));
+        assert!(!glob("[ab.png", "a.png"));
+        assert!(glob("[].png", "[].png"));
+        // Other special characters are taken literally
+        assert!(glob("a+(b).png", "a+(b).png"));
+        assert!(glob("^$.png", "^$.png"));
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(glob("*.png", "CAT.PNG"));
+        assert!(!glob("*.PNG", "cat.png"));
+        assert!(glob("*.PNG", "cat.PNG"));
+        assert!(matches("cat", SearchMode::Substring, "Photos/CAT.png"));
+        assert!(!matches("Cat", SearchMode::Substring, "photos/cat.png"));
+        assert!(matches("a.b", SearchMode::Substring, "a.b.png"));
+        assert!(!matches("a.b", SearchMode::Substring, "axb.png"));
+        // Regular expressions are taken as they are
+        assert!(!matches("cat", SearchMode::Regex, "CAT.png"));
+        assert!(matches("(?i)cat", SearchMode::Regex, "CAT.png"));
+        assert!(matches("c.t", SearchMode::Regex, "cut.png"));
+    }
+
+    #[test]
+    fn invalid_regex() {
+        assert!(Search::new("(", SearchMode::Regex).is_err());
+        assert!(Search::new("(", SearchMode::Substring).is_ok());
+        assert!(Search::new("(", SearchMode::Glob).is_ok());
+    }
+}
@@ -0,0 +1,222 @@
+use std::cmp::{max, min};
+use std::collections::BTreeSet;
+
+// The set of selected tiles. Tiles are identified by the values returned from
+// `TileHandler::tiles`, not by their position, so the selection survives the
+// handler reordering its tiles. Positions are only used for ranges.
+#[derive(Clone, Debug, Default)]
+pub struct Selection {
+    selected: BTreeSet<usize>,
+    // The tile that ranges are extended from
+    anchor: Option<usize>,
+    // What was selected when the anchor was set. Extending a range replaces
+    // the previous range but keeps these.
+    base: BTreeSet<usize>,
+}
+
+impl Selection {
+    pub fn new() -> Selection {
+        Selection::default()
+    }
+
+    pub fn contains(&self, tile: usize) -> bool {
+        self.selected.contains(&tile)
+    }
+
+    pub fn len(&self) -> usize {
+        self.selected.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.selected.is_empty()
+    }
+
+    /// The selected tiles in the order they appear in `tiles`.
+    pub fn in_order(&self, tiles: &[usize]) -> Vec<usize> {
+        tiles
+            .iter()
+            .copied()
+            .filter(|tile| self.contains(*tile))
+            .collect()
+    }
+
+    pub fn toggle(&mut self, tile: usize) {
+        if !self.selected.remove(&tile) {
+            self.selected.insert(tile);
+        }
+        self.set_anchor(tile);
+    }
+
+    /// Selects the tiles between the anchor and position `to`. If there is no
+    /// anchor yet, the range starts at position `from`.
+    pub fn extend(&mut self, tiles: &[usize], from: usize, to: usize) {
+        if tiles.is_empty() {
+            return;
+        }
+        let anchor = self
+            .anchor
+            .and_then(|anchor| tiles.iter().position(|tile| *tile == anchor));
+        let anchor = match anchor {
+            Some(anchor) => anchor,
+            None => {
+                self.set_anchor(tiles[from]);
+                from
+            }
+        };
+        let to = min(to, tiles.len() - 1);
+        self.selected = self.base.clone();
+        self.selected
+            .extend(tiles[min(anchor, to)..=max(anchor, to)].iter().copied());
+    }
+
+    /// The next range will start wherever the cursor is at that point.
+    pub fn reset_anchor(&mut self) {
+        self.anchor = None;
+    }
+
+    /// Selects every tile in `tiles`, in addition to any others that are
+    /// selected.
+    pub fn select_all(&mut self, tiles: &[usize]) {
+        self.selected.extend(tiles.iter().copied());
+        self.reset_anchor();
+    }
+
+    pub fn clear(&mut self) {
+        self.selected.clear();
+        self.reset_anchor();
+    }
+
+    /// Selects the tiles in `tiles` that are not selected, and deselects the
+    /// ones that are. Other tiles are left as they are.
+    pub fn invert(&mut self, tiles: &[usize]) {
+        for tile in tiles {
+            if !self.selected.remove(tile) {
+                self.selected.insert(*tile);
+            }
+        }
+        self.reset_anchor();
+    }
+
+    /// Forgets tiles that are no longer in `tiles`.
+    pub fn retain(&mut self, tiles: &[usize]) {
+        let present: BTreeSet<usize> = tiles.iter().copied().collect();
+        self.selected.retain(|tile| present.contains(tile));
+        self.base.retain(|tile| present.contains(tile));
+    }
+
+    fn set_anchor(&mut self, tile: usize) {
+        self.anchor = Some(tile);
+        self.base = self.selected.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tiles identified by values that differ from their positions
+    const TILES: [usize; 6] = [10, 11, 12, 13, 14, 15];
+
+    fn selected(selection: &Selection) -> Vec<usize> {
+        selection.in_order(&TILES)
+    }
+
+    #[test]
+    fn toggle() {
+        let mut selection = Selection::new();
+        selection.toggle(12);
+        selection.toggle(10);
+        assert_eq!(selected(&selection), [10, 12]);
+        assert_eq!(selection.len(), 2);
+        selection.toggle(12);
+        assert_eq!(selected(&selection), [10]);
+        selection.toggle(10);
+        assert!(selection.is_empty());
+    }
+
+    #[test]
+    fn extend_from_the_cursor_without_an_anchor() {
+        let mut selection = Selection::new();
+        selection.extend(&TILES, 1, 3);
+        assert_eq!(selected(&selection), [11, 12, 13]);
+        // Extending again replaces the range instead of adding to it
+        selection.extend(&TILES, 3, 0);
+        assert_eq!(selected(&selection), [10, 11]);
+        // Positions past the end stop at the last tile
+        selection.extend(&TILES, 0, 10);
+        assert_eq!(selected(&selection), [11, 12, 13, 14, 15]);
+    }
+
+    #[test]
+    fn extend_keeps_what_was_selected_before_the_anchor() {
+        let mut selection = Selection::new();
+        selection.toggle(10);
+        selection.toggle(13);
+        // 13 is the anchor
+        selection.extend(&TILES, 0, 5);
+        assert_eq!(selected(&selection), [10, 13, 14, 15]);
+        selection.extend(&TILES, 0, 2);
+        assert_eq!(selected(&selection), [10, 12, 13]);
+        // The anchor follows its tile when the tiles are reordered
+        let reordered = [13, 12, 11, 10, 14, 15];
+        selection.extend(&reordered, 0, 2);
+        assert_eq!(selected(&selection), [10, 11, 12, 13]);
+    }
+
+    #[test]
+    fn reset_anchor_starts_a_new_range() {
+        let mut selection = Selection::new();
+        selection.extend(&TILES, 0, 1);
+        selection.reset_anchor();
+        selection.extend(&TILES, 4, 5);
+        assert_eq!(selected(&selection), [10, 11, 14, 15]);
+    }
+
+    #[test]
+    fn select_all_none_and_invert() {
+        let mut selection = Selection::new();
+        selection.select_all(&TILES[..3]);
+        assert_eq!(selected(&selection), [10, 11, 12]);
+        selection.invert(&TILES);
+        assert_eq!(selected(&selection), [13, 14, 15]);
+        selection.clear();
+        assert!(selection.is_empty());
+        selection.invert(&TILES);
+        assert_eq!(selected(&selection), TILES);
+        // Nothing is left to extend from
+        selection.extend(&TILES, 2, 2);
+        assert_eq!(selected(&selection), TILES);
+    }
+
+    #[test]
+    fn retain() {
+        let mut selection = Selection::new();
+        selection.toggle(10);
+        selection.toggle(12);
+        selection.extend(&TILES, 0, 4);
+        assert_eq!(selected(&selection), [10, 12, 13, 14]);
+        selection.retain(&[10, 11, 13, 14]);
+        assert_eq!(selected(&selection), [10, 13, 14]);
+        // The range is still extended from 12 once it is back
+        selection.extend(&[10, 11, 12, 13, 14], 0, 3);
+        assert_eq!(selected(&selection), [10, 12, 13]);
+    }
+
+    #[test]
+    fn select_all_and_invert_leave_other_tiles() {
+        let mut selection = Selection::new();
+        selection.toggle(10);
+        selection.toggle(14);
+        selection.select_all(&[11, 12]);
+        assert_eq!(selected(&selection), [10, 11, 12, 14]);
+        selection.invert(&[12, 13, 14]);
+        assert_eq!(selected(&selection), [10, 11, 13]);
+    }
+
+    #[test]
+    fn extending_nothing() {
+        let mut selection = Selection::new();
+        selection.extend(&[], 0, 0);
+        assert!(selection.is_empty());
+    }
+}
@@ -0,0 +1,263 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug)]
+pub struct SlideshowSettings {
+    /// Seconds each tile is shown for.
+    pub interval: f64,
+    pub shuffle: bool,
+    /// Start over after the last tile instead of stopping there.
+    pub repeat: bool,
+    /// Seconds spent cross-fading from one tile to the next, or 0 to switch
+    /// at once.
+    pub fade: f64,
+}
+
+impl Default for SlideshowSettings {
+    fn default() -> Self {
+        SlideshowSettings {
+            interval: 5.0,
+            shuffle: false,
+            repeat: false,
+            fade: 0.0,
+        }
+    }
+}
+
+// Keeps time for a slideshow and decides which tile comes next. Tiles are
+// referred to by their position in the grid, and the order they are shown in
+// is a permutation of the positions, which is shuffled when asked to.
+#[derive(Clone, Debug)]
+pub struct Slideshow {
+    settings: SlideshowSettings,
+    order: Vec<usize>,
+    // Index into order of the tile being shown
+    current: usize,
+    elapsed: f64,
+    paused: bool,
+    rng: u64,
+    // The tile being faded out, and the time since the fade started
+    fading: Option<(usize, f64)>,
+}
+
+impl Slideshow {
+    /// Starts at the tile at position `start` out of `count`.
+    pub fn new(settings: SlideshowSettings, start: usize, count: usize) -> Slideshow {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        let mut slideshow = Slideshow {
+            settings,
+            order: Vec::new(),
+            current: 0,
+            elapsed: 0.0,
+            paused: false,
+            // xorshift gets stuck on 0
+            rng: seed | 1,
+            fading: None,
+        };
+        slideshow.reorder(start, count);
+        slideshow
+    }
+
+    pub fn settings(&self) -> &SlideshowSettings {
+        &self.settings
+    }
+
+    /// The position of the tile being shown.
+    pub fn position(&self) -> usize {
+        self.order.get(self.current).copied().unwrap_or(0)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// True once the last tile has been shown for its interval and the
+    /// slideshow does not repeat.
+    pub fn is_finished(&self) -> bool {
+        !self.settings.repeat
+            && self.current + 1 >= self.order.len()
+            && self.elapsed >= self.settings.interval
+    }
+
+    /// Advances the clock by `dt` seconds. Returns the position of the next
+    /// tile when it is time to move on. `count` is the number of tiles in the
+    /// grid, which may change while the slideshow is running.
+    pub fn update(&mut self, dt: f64, count: usize) -> Option<usize> {
+        if count != self.order.len() {
+            self.resize(count);
+        }
+        if let Some((tile, time)) = self.fading {
+            let time = time + dt;
+            self.fading = if time < self.settings.fade {
+                Some((tile, time))
+            } else {
+                None
+            };
+        }
+        if self.paused || count == 0 {
+            return None;
+        }
+        self.elapsed += dt;
+        if self.elapsed < self.settings.interval || self.is_finished() {
+            return None;
+        }
+        self.step(1)
+    }
+
+    /// Moves forwards or backwards by `steps` tiles in slideshow order and
+    /// restarts the clock. Returns the position of the new tile.
+    pub fn step(&mut self, steps: isize) -> Option<usize> {
+        let len = self.order.len() as isize;
+        if len == 0 {
+            return None;
+        }
+        let previous = self.position();
+        let mut next = self.current as isize + steps;
+        if next >= len && self.settings.repeat && self.settings.shuffle {
+            // A new order for every round, which does not start with the tile
+            // that ended the last one
+            self.shuffle_from(0);
+            if self.order.len() > 1 && self.order[0] == previous {
+                self.order.swap(0, 1);
+            }
+            next = 0;
+        } else if self.settings.repeat {
+            next = next.rem_euclid(len);
+        } else {
+            next = next.clamp(0, len - 1);
+        }
+        self.current = next as usize;
+        self.elapsed = 0.0;
+        if self.position() != previous && self.settings.fade > 0.0 {
+            self.fading = Some((previous, 0.0));
+        }
+        Some(self.position())
+    }
+
+    /// The position of the tile that comes after the current one, if it is
+    /// known yet. A shuffled slideshow that repeats only decides the order of
+    /// the next round once it gets there.
+    pub fn next_position(&self) -> Option<usize> {
+        match self.order.get(self.current + 1) {
+            Some(position) => Some(*position),
+            None if self.settings.repeat && !self.settings.shuffle => self.order.first().copied(),
+            None => None,
+        }
+    }
+
+    /// The tile being faded out, if any, and how opaque the new tile is.
+    pub fn fading(&self) -> Option<(usize, f32)> {
+        self.fading
+            .map(|(tile, time)| (tile, (time / self.settings.fade) as f32))
+    }
+
+    // Builds the order for `count` tiles, continuing from position `start`.
+    fn reorder(&mut self, start: usize, count: usize) {
+        self.order = (0..count).collect();
+        if count == 0 {
+            self.current = 0;
+            return;
+        }
+        let start = start.min(count - 1);
+        if self.settings.shuffle {
+            self.order.swap(0, start);
+            self.shuffle_from(1);
+            self.current = 0;
+        } else {
+            self.current = start;
+        }
+    }
+
+    // Tiles that are added, for example while they are still being loaded, go
+    // among the ones that have not been shown yet. The order of the rest is
+    // kept.
+    fn resize(&mut self, count: usize) {
+        let len = self.order.len();
+        if count > len {
+            self.order.extend(len..count);
+            if self.settings.shuffle {
+                self.shuffle_from((self.current + 1).min(len));
+            }
+            return;
+        }
+        let position = self.position();
+        self.order.retain(|p| *p < count);
+        self.current = match self.order.iter().position(|p| *p == position) {
+            Some(current) => current,
+            None => self.current.min(count.saturating_sub(1)),
+        };
+    }
+
+    // Fisher-Yates shuffle of the order from index `from` on
+    fn shuffle_from(&mut self, from: usize) {
+        for i in (from + 1..self.order.len()).rev() {
+            let j = from + (self.next_random() % (i - from + 1) as u64) as usize;
+            self.order.swap(i, j);
+        }
+    }
+
+    // xorshift64, which is plenty for shuffling
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(shuffle: bool, repeat: bool) -> SlideshowSettings {
+        SlideshowSettings {
+            interval: 1.0,
+            shuffle,
+            repeat,
+            fade: 0.0,
+        }
+    }
+
+    #[test]
+    fn next_position() {
+        let mut slideshow = Slideshow::new(settings(false, false), 1, 3);
+        assert_eq!(slideshow.next_position(), Some(2));
+        assert_eq!(slideshow.update(1.0, 3), Some(2));
+        assert_eq!(slideshow.next_position(), None);
+        let slideshow = Slideshow::new(settings(false, true), 2, 3);
+        assert_eq!(slideshow.next_position(), Some(0));
+    }
+
+    #[test]
+    fn added_tiles_keep_the_order_shown_so_far() {
+        let mut slideshow = Slideshow::new(settings(true, false), 2, 4);
+        let mut shown = vec![slideshow.position()];
+        for _ in 0..2 {
+            shown.push(slideshow.update(1.0, 4).unwrap());
+        }
+        // More tiles finish loading
+        slideshow.update(0.0, 10);
+        assert_eq!(slideshow.position(), shown[2]);
+        assert_eq!(&slideshow.order[..3], &shown[..]);
+        while let Some(position) = slideshow.update(1.0, 10) {
+            shown.push(position);
+        }
+        shown.sort_unstable();
+        assert_eq!(shown, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn removed_tiles_leave_the_order() {
+        let mut slideshow = Slideshow::new(settings(false, false), 1, 5);
+        slideshow.update(0.0, 3);
+        assert_eq!(slideshow.position(), 1);
+        assert_eq!(slideshow.order, [0, 1, 2]);
+        slideshow.update(0.0, 1);
+        assert_eq!(slideshow.position(), 0);
+    }
+}
@@ -0,0 +1,303 @@
+use crate::render::ImageTexture;
+use graphics::draw_state::Blend;
+use graphics::{Context, DrawState, Graphics, ImageSize};
+use image::{GenericImage, Rgba, RgbaImage};
+use texture::{CreateTexture, Format, TextureOp, TextureSettings, UpdateTexture};
+
+// A CPU implementation of the piston graphics backend. It rasterizes the same
+// triangle lists that the OpenGL backend receives into an in-memory image, so
+// anything drawn through `Graphics` (the grid, tiles, outlines, overlays) can
+// be rendered without a window or a GPU.
+
+pub struct SoftwareTexture {
+    image: RgbaImage,
+}
+
+impl SoftwareTexture {
+    pub fn new(image: RgbaImage) -> SoftwareTexture {
+        SoftwareTexture { image }
+    }
+
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+}
+
+impl ImageSize for SoftwareTexture {
+    fn get_size(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+}
+
+impl TextureOp<()> for SoftwareTexture {
+    type Error = String;
+}
+
+impl CreateTexture<()> for SoftwareTexture {
+    fn create<S: Into<[u32; 2]>>(
+        _factory: &mut (),
+        _format: Format,
+        memory: &[u8],
+        size: S,
+        _settings: &TextureSettings,
+    ) -> Result<Self, String> {
+        let [width, height] = size.into();
+        RgbaImage::from_raw(width, height, memory.to_vec())
+            .map(SoftwareTexture::new)
+            .ok_or_else(|| "Texture data does not match its size".to_string())
+    }
+}
+
+impl UpdateTexture<()> for SoftwareTexture {
+    fn update<O, S>(
+        &mut self,
+        _factory: &mut (),
+        _format: Format,
+        memory: &[u8],
+        offset: O,
+        size: S,
+    ) -> Result<(), String>
+    where
+        O: Into<[u32; 2]>,
+        S: Into<[u32; 2]>,
+    {
+        let [x, y] = offset.into();
+        let [width, height] = size.into();
+        let update = RgbaImage::from_raw(width, height, memory.to_vec())
+            .ok_or_else(|| "Texture data does not match its size".to_string())?;
+        if self.image.copy_from(&update, x, y) {
+            Ok(())
+        } else {
+            Err("Texture update is out of bounds".to_string())
+        }
+    }
+}
+
+impl ImageTexture for SoftwareTexture {
+    fn from_rgba(img: &RgbaImage) -> Self {
+        SoftwareTexture::new(img.clone())
+    }
+}
+
+pub struct SoftwareGraphics {
+    buffer: RgbaImage,
+}
+
+impl SoftwareGraphics {
+    pub fn new(width: u32, height: u32) -> SoftwareGraphics {
+        SoftwareGraphics {
+            buffer: RgbaImage::new(width, height),
+        }
+    }
+
+    /// Calls `f` with a context whose coordinates are pixels of the buffer,
+    /// mirroring `GlGraphics::draw`.
+    pub fn draw<F, U>(&mut self, f: F) -> U
+    where
+        F: FnOnce(Context, &mut Self) -> U,
+    {
+        let (width, height) = self.buffer.dimensions();
+        let c = Context::new_abs(width as f64, height as f64);
+        f(c, self)
+    }
+
+    pub fn image(&self) -> &RgbaImage {
+        &self.buffer
+    }
+
+    pub fn into_image(self) -> RgbaImage {
+        self.buffer
+    }
+
+    // Converts normalized device coordinates to pixel coordinates.
+    fn to_pixels(&self, v: [f32; 2]) -> [f32; 2] {
+        let (width, height) = self.buffer.dimensions();
+        [
+            (v[0] + 1.0) * width as f32 / 2.0,
+            (1.0 - v[1]) * height as f32 / 2.0,
+        ]
+    }
+
+    fn clip_rect(&self, draw_state: &DrawState) -> [u32; 4] {
+        let (width, height) = self.buffer.dimensions();
+        match draw_state.scissor {
+            Some([x, y, w, h]) => [
+                x.min(width),
+                y.min(height),
+                x.saturating_add(w).min(width),
+                y.saturating_add(h).min(height),
+            ],
+            None => [0, 0, width, height],
+        }
+    }
+
+    fn fill_triangle(
+        &mut self,
+        draw_state: &DrawState,
+        color: &[f32; 4],
+        vertices: [[f32; 2]; 3],
+        texture: Option<(&RgbaImage, [[f32; 2]; 3])>,
+    ) {
+        let mut v = [
+            self.to_pixels(vertices[0]),
+            self.to_pixels(vertices[1]),
+            self.to_pixels(vertices[2]),
+        ];
+        let mut uv = texture.map(|(_, uv)| uv);
+        let mut area = edge(v[0], v[1], v[2]);
+        if area == 0.0 {
+            return;
+        }
+        // Normalize the winding so that the inside of the triangle is positive.
+        if area < 0.0 {
+            v.swap(1, 2);
+            if let Some(uv) = uv.as_mut() {
+                uv.swap(1, 2);
+            }
+            area = -area;
+        }
+
+        let [clip_x0, clip_y0, clip_x1, clip_y1] = self.clip_rect(draw_state);
+        let xs = v.iter().map(|p| p[0]);
+        let ys = v.iter().map(|p| p[1]);
+        let min_x = xs.clone().fold(f32::MAX, f32::min).floor().max(0.0) as u32;
+        let max_x = xs.fold(f32::MIN, f32::max).ceil().max(0.0) as u32;
+        let min_y = ys.clone().fold(f32::MAX, f32::min).floor().max(0.0) as u32;
+        let max_y = ys.fold(f32::MIN, f32::max).ceil().max(0.0) as u32;
+        let (x0, y0) = (min_x.max(clip_x0), min_y.max(clip_y0));
+        let (x1, y1) = (max_x.min(clip_x1), max_y.min(clip_y1));
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let p = [x as f32 + 0.5, y as f32 + 0.5];
+                let w0 = edge(v[1], v[2], p);
+                let w1 = edge(v[2], v[0], p);
+                let w2 = edge(v[0], v[1], p);
+                if !covers(w0, v[1], v[2]) || !covers(w1, v[2], v[0]) || !covers(w2, v[0], v[1]) {
+                    continue;
+                }
+                let mut src = *color;
+                if let (Some((image, _)), Some(uv)) = (texture, uv) {
+                    let u = (w0 * uv[0][0] + w1 * uv[1][0] + w2 * uv[2][0]) / area;
+                    let t = (w0 * uv[0][1] + w1 * uv[1][1] + w2 * uv[2][1]) / area;
+                    let texel = sample(image, u, t);
+                    for c in 0..4 {
+                        src[c] *= texel[c];
+                    }
+                }
+                self.blend_pixel(x, y, src, draw_state.blend);
+            }
+        }
+    }
+
+    fn blend_pixel(&mut self, x: u32, y: u32, src: [f32; 4], blend: Option<Blend>) {
+        let dst = self.buffer.get_pixel_mut(x, y);
+        if blend.is_none() {
+            *dst = to_rgba(src);
+            return;
+        }
+        let dst_color = from_rgba(*dst);
+        let alpha = src[3];
+        let out_alpha = alpha + dst_color[3] * (1.0 - alpha);
+        let mut out = [0.0, 0.0, 0.0, out_alpha];
+        for c in 0..3 {
+            out[c] = src[c] * alpha + dst_color[c] * (1.0 - alpha);
+        }
+        *dst = to_rgba(out);
+    }
+}
+
+impl Graphics for SoftwareGraphics {
+    type Texture = SoftwareTexture;
+
+    fn clear_color(&mut self, color: [f32; 4]) {
+        let color = to_rgba(color);
+        for pixel in self.buffer.pixels_mut() {
+            *pixel = color;
+        }
+    }
+
+    fn clear_stencil(&mut self, _value: u8) {}
+
+    fn tri_list<F>(&mut self, draw_state: &DrawState, color: &[f32; 4], mut f: F)
+    where
+        F: FnMut(&mut dyn FnMut(&[[f32; 2]])),
+    {
+        f(&mut |vertices: &[[f32; 2]]| {
+            for tri in vertices.chunks_exact(3) {
+                self.fill_triangle(draw_state, color, [tri[0], tri[1], tri[2]], None);
+            }
+        });
+    }
+
+    fn tri_list_uv<F>(
+        &mut self,
+        draw_state: &DrawState,
+        color: &[f32; 4],
+        texture: &SoftwareTexture,
+        mut f: F,
+    ) where
+        F: FnMut(&mut dyn FnMut(&[[f32; 2]], &[[f32; 2]])),
+    {
+        f(&mut |vertices: &[[f32; 2]], uvs: &[[f32; 2]]| {
+            for (tri, uv) in vertices.chunks_exact(3).zip(uvs.chunks_exact(3)) {
+                self.fill_triangle(
+                    draw_state,
+                    color,
+                    [tri[0], tri[1], tri[2]],
+                    Some((&texture.image, [uv[0], uv[1], uv[2]])),
+                );
+            }
+        });
+    }
+}
+
+// Evaluated with the endpoints in a fixed order, so that triangles sharing an
+// edge get exactly opposite values and no pixel on it falls between them due
+// to rounding.
+fn edge(a: [f32; 2], b: [f32; 2], p: [f32; 2]) -> f32 {
+    if (a[0], a[1]) > (b[0], b[1]) {
+        return -edge(b, a, p);
+    }
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+// Top-left fill rule: pixels exactly on an edge are only drawn for top and left
+// edges so that triangles sharing an edge do not blend it twice.
+fn covers(w: f32, a: [f32; 2], b: [f32; 2]) -> bool {
+    if w != 0.0 {
+        return w > 0.0;
+    }
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    (dy == 0.0 && dx > 0.0) || dy < 0.0
+}
+
+fn sample(image: &RgbaImage, u: f32, v: f32) -> [f32; 4] {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return [0.0; 4];
+    }
+    let x = ((u * width as f32) as i64).clamp(0, width as i64 - 1) as u32;
+    let y = ((v * height as f32) as i64).clamp(0, height as i64 - 1) as u32;
+    from_rgba(*image.get_pixel(x, y))
+}
+
+fn from_rgba(pixel: Rgba<u8>) -> [f32; 4] {
+    let [r, g, b, a] = pixel.0;
+    [
+        r as f32 / 255.0,
+        g as f32 / 255.0,
+        b as f32 / 255.0,
+        a as f32 / 255.0,
+    ]
+}
+
+fn to_rgba(color: [f32; 4]) -> Rgba<u8> {
+    let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    Rgba([
+        channel(color[0]),
+        channel(color[1]),
+        channel(color[2]),
+        channel(color[3]),
+    ])
+}
@@ -0,0 +1,325 @@
+use crate::grid::GridResult;
+use anyhow::anyhow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::SystemTime;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    /// Plain string order of the whole path.
+    Name,
+    /// Like `Name`, but runs of digits are compared by their numeric value so
+    /// that "img2" comes before "img10".
+    Natural,
+    Modified,
+    /// File size in bytes.
+    Size,
+    /// Pixel count.
+    Dimensions,
+    /// Width divided by height.
+    AspectRatio,
+}
+
+impl SortKey {
+    pub const ALL: [SortKey; 6] = [
+        SortKey::Name,
+        SortKey::Natural,
+        SortKey::Modified,
+        SortKey::Size,
+        SortKey::Dimensions,
+        SortKey::AspectRatio,
+    ];
+
+    /// The key after this one in `ALL`, wrapping around.
+    pub fn next(self) -> SortKey {
+        let i = SortKey::ALL.iter().position(|key| *key == self).unwrap();
+        SortKey::ALL[(i + 1) % SortKey::ALL.len()]
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Natural => "natural",
+            SortKey::Modified => "mtime",
+            SortKey::Size => "size",
+            SortKey::Dimensions => "dimensions",
+            SortKey::AspectRatio => "aspect",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SortBy {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl SortBy {
+    pub fn new(key: SortKey) -> SortBy {
+        SortBy {
+            key,
+            descending: false,
+        }
+    }
+
+    /// Files missing what the key compares come last in either order.
+    pub fn compare(&self, a: &SortInfo, b: &SortInfo) -> Ordering {
+        let directed = |ordering: Ordering| {
+            if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        };
+        let ordering = match self.key {
+            SortKey::Name => directed(a.name.cmp(&b.name)),
+            SortKey::Natural => directed(natural_cmp(&a.name, &b.name)),
+            SortKey::Modified => missing_last(a.modified, b.modified, |x, y| directed(x.cmp(&y))),
+            SortKey::Size => missing_last(a.size, b.size, |x, y| directed(x.cmp(&y))),
+            SortKey::Dimensions => {
+                missing_last(a.dimensions, b.dimensions, |(w1, h1), (w2, h2)| {
+                    directed((w1 as u64 * h1 as u64, w1).cmp(&(w2 as u64 * h2 as u64, w2)))
+                })
+            }
+            SortKey::AspectRatio => {
+                missing_last(a.dimensions, b.dimensions, |(w1, h1), (w2, h2)| {
+                    directed(
+                        (w1 as f64 / h1.max(1) as f64)
+                            .partial_cmp(&(w2 as f64 / h2.max(1) as f64))
+                            .unwrap_or(Ordering::Equal),
+                    )
+                })
+            }
+        };
+        // Fall back to the name so that the order is stable between runs
+        ordering.then_with(|| natural_cmp(&a.name, &b.name))
+    }
+
+    /// Sorts the files, reading whatever the sort key needs from disk.
+    pub fn sort_files(&self, files: Vec<PathBuf>) -> Vec<PathBuf> {
+        let infos = read_infos(&files, self.key, Vec::new());
+        self.order(&infos)
+            .into_iter()
+            .map(|i| files[i].clone())
+            .collect()
+    }
+
+    /// The indexes of `infos` in sorted order.
+    pub fn order(&self, infos: &[SortInfo]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..infos.len()).collect();
+        order.sort_by(|a, b| self.compare(&infos[*a], &infos[*b]));
+        order
+    }
+}
+
+/// Accepts the key optionally followed by the order, e.g. "mtime:desc".
+impl FromStr for SortBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> GridResult<SortBy> {
+        let mut parts = s.splitn(2, ':');
+        let key = parts.next().unwrap_or("");
+        let key = SortKey::ALL
+            .iter()
+            .find(|k| k.name() == key)
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown sort key '{}'", key))?;
+        let descending = match parts.next() {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(order) => return Err(anyhow!("Unknown sort order '{}'", order)),
+        };
+        Ok(SortBy { key, descending })
+    }
+}
+
+impl fmt::Display for SortBy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let order = if self.descending { "desc" } else { "asc" };
+        write!(f, "{}:{}", self.key.name(), order)
+    }
+}
+
+// What the sort keys compare. Fields that cannot be read are left empty and
+// sort after everything else.
+#[derive(Clone, Debug)]
+pub struct SortInfo {
+    pub name: String,
+    pub modified: Option<SystemTime>,
+    pub size: Option<u64>,
+    pub dimensions: Option<(u32, u32)>,
+}
+
+impl SortInfo {
+    /// Only reads the image header when `key` needs the dimensions.
+    pub fn new(file: &Path, key: SortKey) -> SortInfo {
+        let metadata = fs::metadata(file).ok();
+        let mut info = SortInfo {
+            name: file.to_string_lossy().into_owned(),
+            modified: metadata.as_ref().and_then(|m| m.modified().ok()),
+            size: metadata.map(|m| m.len()),
+            dimensions: None,
+        };
+        info.read_missing(file, key);
+        info
+    }
+
+    /// Reads what `key` compares if it was left out because the info was
+    /// created for another key, so the same info can be sorted again.
+    pub fn read_missing(&mut self, file: &Path, key: SortKey) {
+        if let SortKey::Dimensions | SortKey::AspectRatio = key {
+            if self.dimensions.is_none() {
+                self.dimensions = image::image_dimensions(file).ok();
+            }
+        }
+    }
+}
+
+/// Reads what `key` compares for each of the files on a pool of threads.
+/// `known` is either empty or holds infos read for the same files before,
+/// which only have what is missing read.
+pub fn read_infos(files: &[PathBuf], key: SortKey, known: Vec<SortInfo>) -> Vec<SortInfo> {
+    let mut infos: Vec<Option<SortInfo>> = known.into_iter().map(Some).collect();
+    infos.resize(files.len(), None);
+    let threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let chunk = files.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        for (files, infos) in files.chunks(chunk).zip(infos.chunks_mut(chunk)) {
+            scope.spawn(move || {
+                for (file, info) in files.iter().zip(infos) {
+                    match info {
+                        Some(info) => info.read_missing(file, key),
+                        None => *info = Some(SortInfo::new(file, key)),
+                    }
+                }
+            });
+        }
+    });
+    infos.into_iter().flatten().collect()
+}
+
+fn missing_last<T, F>(a: Option<T>, b: Option<T>, compare: F) -> Ordering
+where
+    F: Fn(T, T) -> Ordering,
+{
+    match (a, b) {
+        (Some(a), Some(b)) => compare(a, b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Compares strings so that embedded numbers are ordered by value. Text is
+/// compared ignoring case, falling back to plain string order for ties.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chunks = chunks(a);
+    let mut b_chunks = chunks(b);
+    loop {
+        let ordering = match (a_chunks.next(), b_chunks.next()) {
+            (Some(x), Some(y)) => compare_chunks(x, y),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (None, None) => return a.cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+fn compare_chunks(a: &str, b: &str) -> Ordering {
+    let is_number = |s: &str| s.bytes().all(|c| c.is_ascii_digit());
+    if is_number(a) && is_number(b) {
+        let a = a.trim_start_matches('0');
+        let b = b.trim_start_matches('0');
+        a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+    } else {
+        a.to_lowercase().cmp(&b.to_lowercase())
+    }
+}
+
+// Splits the string into alternating runs of digits and non-digits.
+fn chunks(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let digits = first.is_ascii_digit();
+        let end = rest
+            .char_indices()
+            .find(|(_, c)| c.is_ascii_digit() != digits)
+            .map_or(rest.len(), |(i, _)| i);
+        let (chunk, remaining) = rest.split_at(end);
+        rest = remaining;
+        Some(chunk)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str, size: Option<u64>) -> SortInfo {
+        SortInfo {
+            name: name.to_owned(),
+            modified: None,
+            size,
+            dimensions: None,
+        }
+    }
+
+    fn sorted(sort: &str, infos: &[SortInfo]) -> Vec<String> {
+        let sort: SortBy = sort.parse().unwrap();
+        let mut infos = infos.to_vec();
+        infos.sort_by(|a, b| sort.compare(a, b));
+        infos.into_iter().map(|info| info.name).collect()
+    }
+
+    #[test]
+    fn missing_values_sort_last_in_both_orders() {
+        let infos = [
+            info("a", None),
+            info("b", Some(2)),
+            info("c", Some(1)),
+            info("d", None),
+        ];
+        assert_eq!(sorted("size", &infos), ["c", "b", "a", "d"]);
+        assert_eq!(sorted("size:desc", &infos), ["b", "c", "a", "d"]);
+    }
+
+    #[test]
+    fn descending_reverses_names() {
+        let infos = [info("img2", None), info("img10", None), info("img1", None)];
+        assert_eq!(sorted("natural", &infos), ["img1", "img2", "img10"]);
+        assert_eq!(sorted("natural:desc", &infos), ["img10", "img2", "img1"]);
+    }
+
+    #[test]
+    fn ties_keep_name_order() {
+        let infos = [info("b", Some(1)), info("a", Some(1))];
+        assert_eq!(sorted("size:desc", &infos), ["a", "b"]);
+    }
+
+    #[test]
+    fn order_and_read_infos() {
+        let files: Vec<PathBuf> = ["img10", "img2", "img1"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let infos = read_infos(&files, SortKey::Natural, Vec::new());
+        let names: Vec<&str> = infos.iter().map(|info| info.name.as_str()).collect();
+        assert_eq!(names, ["img10", "img2", "img1"]);
+        assert_eq!(SortBy::new(SortKey::Natural).order(&infos), [2, 1, 0]);
+        // Infos that are already known are kept
+        let known = vec![info("a", Some(3)), info("b", Some(1)), info("c", Some(2))];
+        let infos = read_infos(&files, SortKey::Size, known);
+        assert_eq!(SortBy::new(SortKey::Size).order(&infos), [1, 2, 0]);
+        assert!(read_infos(&[], SortKey::Size, Vec::new()).is_empty());
+    }
+}
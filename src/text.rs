@@ -0,0 +1,146 @@
+use crate::grid::{Color, GridResult};
+use crate::render::ImageTexture;
+use anyhow::anyhow;
+use graphics::character::CharacterCache;
+use graphics::glyph_cache::rusttype::GlyphCache;
+use graphics::math::Matrix2d;
+use graphics::types::FontSize;
+use graphics::{DrawState, Graphics, Text, Transformed};
+use texture::TextureSettings;
+
+// Fira Sans, licensed under the SIL Open Font License (see
+// assets/FiraSans-LICENSE). It is embedded so that text works no matter where
+// the program is run from.
+const FONT: &[u8] = include_bytes!("../assets/FiraSans-Regular.ttf");
+
+// The glyph cache takes font sizes in points and renders them at this many
+// pixels per point.
+const PIXELS_PER_POINT: f64 = 1.333;
+
+const ELLIPSIS: &str = "…";
+
+// Draws single lines of text with the bundled font. Glyphs are rendered into
+// textures of the backend's texture type the first time they are used, so the
+// same code draws text with OpenGL and with the software renderer.
+pub struct TextRenderer<T> {
+    cache: GlyphCache<'static, (), T>,
+}
+
+impl<T: ImageTexture> Default for TextRenderer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: ImageTexture> TextRenderer<T> {
+    pub fn new() -> TextRenderer<T> {
+        let cache = GlyphCache::from_bytes(FONT, (), TextureSettings::new())
+            .expect("The bundled font is invalid");
+        TextRenderer { cache }
+    }
+
+    /// The height in pixels of a line of text, including some spacing.
+    pub fn line_height(&self, size: FontSize) -> f64 {
+        (size as f64 * PIXELS_PER_POINT * 1.4).ceil()
+    }
+
+    pub fn width(&mut self, text: &str, size: FontSize) -> f64 {
+        self.cache.width(size, text).unwrap_or(0.0)
+    }
+
+    /// Shortens the text with an ellipsis at the end until it fits within
+    /// `max_width` pixels.
+    pub fn elide(&mut self, text: &str, size: FontSize, max_width: f64) -> String {
+        if self.width(text, size) <= max_width {
+            return text.to_string();
+        }
+        let available = max_width - self.width(ELLIPSIS, size);
+        let mut width = 0.0;
+        let mut end = 0;
+        for (i, c) in text.char_indices() {
+            width += self.advance(c, size);
+            if width > available {
+                break;
+            }
+            end = i + c.len_utf8();
+        }
+        format!("{}{}", &text[..end], ELLIPSIS)
+    }
+
+    /// Breaks the text into lines that fit within `max_width` pixels, at
+    /// spaces where possible and within words that are too long by themselves.
+    /// Line breaks in the text are kept.
+    pub fn wrap(&mut self, text: &str, size: FontSize, max_width: f64) -> Vec<String> {
+        let mut lines = Vec::new();
+        for paragraph in text.lines() {
+            let mut line = String::new();
+            let mut width = 0.0;
+            // The last space on the line, and the width up to it
+            let mut space: Option<(usize, f64)> = None;
+            for c in paragraph.chars() {
+                let advance = self.advance(c, size);
+                if width + advance > max_width && !line.is_empty() {
+                    match space.take() {
+                        Some((at, before)) => {
+                            lines.push(line[..at].to_string());
+                            line = line[at + 1..].to_string();
+                            width -= before + self.advance(' ', size);
+                        }
+                        None => {
+                            lines.push(std::mem::take(&mut line));
+                            width = 0.0;
+                        }
+                    }
+                }
+                if c == ' ' {
+                    space = Some((line.len(), width));
+                }
+                line.push(c);
+                width += advance;
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Draws a line of text with the top left corner of the line at the origin
+    /// of `transform`.
+    pub fn draw<G>(
+        &mut self,
+        text: &str,
+        color: Color,
+        size: FontSize,
+        transform: Matrix2d,
+        g: &mut G,
+    ) -> GridResult<()>
+    where
+        G: Graphics<Texture = T>,
+    {
+        let baseline = self.baseline(size);
+        Text::new_color(color, size)
+            .round()
+            .draw(
+                text,
+                &mut self.cache,
+                &DrawState::default(),
+                transform.trans(0.0, baseline),
+                g,
+            )
+            .map_err(|err| anyhow!(err))
+    }
+
+    fn advance(&mut self, c: char, size: FontSize) -> f64 {
+        self.cache
+            .character(size, c)
+            .map_or(0.0, |character| character.advance_width())
+    }
+
+    // Places the baseline so that capital letters are centered in the line.
+    fn baseline(&mut self, size: FontSize) -> f64 {
+        let cap_height = self
+            .cache
+            .character(size, 'H')
+            .map_or(size as f64, |character| character.top());
+        ((self.line_height(size) + cap_height) / 2.0).round()
+    }
+}
@@ -0,0 +1,151 @@
+use graphics::ImageSize;
+use std::collections::{HashMap, VecDeque};
+
+// Keeps textures within a memory budget, evicting the least recently used
+// texture first. Sizes are estimated as four bytes per pixel, which is what
+// the RGBA textures created by `ImageTexture` take up. Memory that is kept
+// elsewhere, such as for thumbnails, can be reserved so that the budget
+// covers it too.
+pub struct TextureCache<T> {
+    budget: usize,
+    used: usize,
+    reserved: usize,
+    textures: HashMap<usize, T>,
+    // least recently used first
+    order: VecDeque<usize>,
+}
+
+impl<T: ImageSize> TextureCache<T> {
+    /// `budget` is the total size in bytes that the cached textures may use.
+    pub fn new(budget: usize) -> TextureCache<T> {
+        TextureCache {
+            budget,
+            used: 0,
+            reserved: 0,
+            textures: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// The size of the cached textures, not counting reserved memory.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn reserved(&self) -> usize {
+        self.reserved
+    }
+
+    /// Counts `bytes` against the budget, evicting textures to make room.
+    /// The most recently used texture is kept, so that the image on screen is
+    /// not dropped while thumbnails are still arriving.
+    pub fn reserve(&mut self, bytes: usize) {
+        self.reserved += bytes;
+        self.evict(1);
+    }
+
+    pub fn release(&mut self, bytes: usize) {
+        self.reserved = self.reserved.saturating_sub(bytes);
+    }
+
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+
+    pub fn contains(&self, key: usize) -> bool {
+        self.textures.contains_key(&key)
+    }
+
+    /// Looks up a texture without affecting the eviction order.
+    pub fn get(&self, key: usize) -> Option<&T> {
+        self.textures.get(&key)
+    }
+
+    /// Marks the texture as the most recently used one.
+    pub fn touch(&mut self, key: usize) {
+        if let Some(pos) = self.order.iter().position(|k| *k == key) {
+            self.order.remove(pos);
+            self.order.push_back(key);
+        }
+    }
+
+    /// Adds the texture and evicts older ones until the cache fits its budget.
+    /// The texture just inserted is never evicted, even if it alone exceeds
+    /// the budget.
+    pub fn insert(&mut self, key: usize, texture: T) {
+        self.remove(key);
+        self.used += texture_size(&texture);
+        self.textures.insert(key, texture);
+        self.order.push_back(key);
+        self.evict(1);
+    }
+
+    pub fn remove(&mut self, key: usize) -> Option<T> {
+        let texture = self.textures.remove(&key)?;
+        self.used -= texture_size(&texture);
+        self.order.retain(|k| *k != key);
+        Some(texture)
+    }
+
+    // Evicts the oldest textures until the cache fits, keeping at least `keep`
+    fn evict(&mut self, keep: usize) {
+        while self.used + self.reserved > self.budget && self.order.len() > keep {
+            let oldest = self.order[0];
+            self.remove(oldest);
+        }
+    }
+}
+
+pub fn texture_size<T: ImageSize>(texture: &T) -> usize {
+    let (width, height) = texture.get_size();
+    width as usize * height as usize * 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::software::SoftwareTexture;
+    use image::RgbaImage;
+
+    // 100 bytes
+    fn texture() -> SoftwareTexture {
+        SoftwareTexture::new(RgbaImage::new(5, 5))
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = TextureCache::new(250);
+        cache.insert(1, texture());
+        cache.insert(2, texture());
+        cache.touch(1);
+        cache.insert(3, texture());
+        assert!(cache.contains(1) && !cache.contains(2) && cache.contains(3));
+        assert_eq!(cache.used(), 200);
+    }
+
+    #[test]
+    fn reserved_memory_counts_against_the_budget() {
+        let mut cache = TextureCache::new(250);
+        cache.insert(1, texture());
+        cache.insert(2, texture());
+        cache.reserve(100);
+        assert!(!cache.contains(1) && cache.contains(2));
+        // The newest texture is kept even when it does not fit
+        cache.reserve(100);
+        assert!(cache.contains(2));
+        cache.insert(3, texture());
+        assert_eq!(cache.len(), 1);
+        assert!(cache.contains(3));
+        cache.release(200);
+        cache.insert(4, texture());
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -0,0 +1,391 @@
+use crate::grid::GridResult;
+use crate::image_loader::thumbnail;
+use crate::png_text;
+use image::png::PNGEncoder;
+use image::{ColorType, RgbaImage};
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// Thumbnails are stored following the freedesktop.org thumbnail spec, so
+// thumbnails written by file managers are reused and the other way around:
+//
+// $XDG_CACHE_HOME/thumbnails/<flavor>/<md5 of the file's URI>.png
+//
+// The flavor is the smallest of the spec's sizes that is at least as large as
+// the tile. Each thumbnail records the URI, modification time and size of the
+// original file, and is only used while those still match.
+
+// Thumbnails record which version of image_grid's thumbnails they are, as in
+// "image_grid 2". The version goes up whenever thumbnails are made
+// differently, so that older ones are made again: 2 turns photos upright.
+const SOFTWARE: &str = "image_grid";
+const VERSION: u32 = 2;
+
+fn software() -> String {
+    format!("{} {}", SOFTWARE, VERSION)
+}
+
+// Thumbnails of any version written by image_grid
+fn is_own(software: &str) -> bool {
+    software.split(' ').next() == Some(SOFTWARE)
+}
+
+const FLAVORS: [(&str, u32); 4] = [
+    ("normal", 128),
+    ("large", 256),
+    ("x-large", 512),
+    ("xx-large", 1024),
+];
+
+#[derive(Clone)]
+pub struct ThumbnailCache {
+    root: PathBuf,
+    max_size: u64,
+}
+
+impl ThumbnailCache {
+    /// `root` is the thumbnails directory that contains the flavor directories.
+    pub fn new(root: PathBuf) -> ThumbnailCache {
+        ThumbnailCache {
+            root,
+            max_size: 512 * 1024 * 1024,
+        }
+    }
+
+    /// `$XDG_CACHE_HOME/thumbnails`, falling back to `~/.cache/thumbnails`.
+    pub fn default_root() -> Option<PathBuf> {
+        let cache_home = std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(cache_home.join("thumbnails"))
+    }
+
+    /// Total size in bytes that thumbnails written by image_grid may use before
+    /// `prune` starts deleting the oldest ones.
+    pub fn max_size(&mut self, bytes: u64) {
+        self.max_size = bytes;
+    }
+
+    /// Returns the cached thumbnail for the file if it is still up to date. It
+    /// may be larger than `width` x `height`, but never smaller than needed.
+    pub fn get(&self, file: &Path, width: u32, height: u32) -> Option<RgbaImage> {
+        let (thumbnail_path, _size) = self.thumbnail_path(file, width, height)?;
+        let contents = fs::read(thumbnail_path).ok()?;
+        let source = Source::new(file).ok()?;
+        let text = png_text::read_text(&contents);
+        let value = |key: &str| {
+            text.iter()
+                .find(|(keyword, _)| keyword == key)
+                .map(|(_, value)| value.as_str())
+        };
+        if value("Thumb::URI") != Some(source.uri.as_str())
+            || value("Thumb::MTime") != Some(source.mtime.to_string().as_str())
+        {
+            return None;
+        }
+        // Thumbnails image_grid wrote before it made them the way it does now
+        if let Some(written_by) = value("Software") {
+            if is_own(written_by) && written_by != software() {
+                return None;
+            }
+        }
+        if let Some(size) = value("Thumb::Size") {
+            if size != source.size.to_string() {
+                return None;
+            }
+        }
+        Some(image::load_from_memory(&contents).ok()?.to_rgba())
+    }
+
+    /// Stores a thumbnail of `img`, the decoded contents of `file`, that can
+    /// later be returned by `get` for the same tile size.
+    pub fn put(&self, file: &Path, width: u32, height: u32, img: &RgbaImage) -> GridResult<()> {
+        let (thumbnail_path, size) = match self.thumbnail_path(file, width, height) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let source = Source::new(file)?;
+        let img = thumbnail(img.clone(), size, size);
+        let mut png = Vec::new();
+        PNGEncoder::new(&mut png).encode(&img, img.width(), img.height(), ColorType::RGBA(8))?;
+        let png = png_text::insert_text(
+            &png,
+            // Software comes first so that it is found quickly when pruning,
+            // however long the URI is
+            &[
+                ("Software", &software()),
+                ("Thumb::URI", &source.uri),
+                ("Thumb::MTime", &source.mtime.to_string()),
+                ("Thumb::Size", &source.size.to_string()),
+            ],
+        );
+
+        // Write to a temporary file and rename it so that other programs never
+        // see a partially written thumbnail.
+        let dir = thumbnail_path.parent().unwrap();
+        create_private_dir(dir)?;
+        let temp_path = dir.join(format!(
+            "{}.{}.tmp",
+            thumbnail_path.file_name().unwrap().to_string_lossy(),
+            std::process::id()
+        ));
+        create_private_file(&temp_path)?.write_all(&png)?;
+        fs::rename(&temp_path, &thumbnail_path)?;
+        Ok(())
+    }
+
+    /// Deletes the oldest thumbnails written by image_grid until they fit
+    /// within the maximum size. Returns the number of thumbnails deleted.
+    /// This reads the start of every thumbnail, so it is best done in the
+    /// background.
+    pub fn prune(&self) -> GridResult<usize> {
+        let mut entries = self.own_thumbnails();
+        let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+        entries.sort_by_key(|entry| entry.mtime);
+        let mut deleted = 0;
+        for entry in entries {
+            if total <= self.max_size {
+                break;
+            }
+            remove_thumbnail(&entry.path)?;
+            total -= entry.size;
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+
+    /// Deletes every thumbnail written by image_grid. Thumbnails created by
+    /// other programs are left alone. Returns the number of thumbnails deleted.
+    pub fn clear(&self) -> GridResult<usize> {
+        let entries = self.own_thumbnails();
+        for entry in &entries {
+            remove_thumbnail(&entry.path)?;
+        }
+        Ok(entries.len())
+    }
+
+    // Other programs may change the directories at the same time, so
+    // thumbnails that cannot be read are skipped rather than stopping the
+    // search.
+    fn own_thumbnails(&self) -> Vec<CacheEntry> {
+        let mut entries = Vec::new();
+        for (flavor, _size) in FLAVORS.iter() {
+            let dir = match self.root.join(flavor).read_dir() {
+                Ok(dir) => dir,
+                Err(_) => continue,
+            };
+            for entry in dir.filter_map(Result::ok) {
+                let path = entry.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("png") {
+                    continue;
+                }
+                let own = read_header(&path).is_ok_and(|header| {
+                    png_text::read_text(&header)
+                        .iter()
+                        .any(|(keyword, value)| keyword == "Software" && is_own(value))
+                });
+                if !own {
+                    continue;
+                }
+                let metadata = match fs::metadata(&path) {
+                    Ok(metadata) => metadata,
+                    Err(_) => continue,
+                };
+                entries.push(CacheEntry {
+                    size: metadata.len(),
+                    mtime: metadata.modified().unwrap_or(UNIX_EPOCH),
+                    path,
+                });
+            }
+        }
+        entries
+    }
+
+    fn thumbnail_path(&self, file: &Path, width: u32, height: u32) -> Option<(PathBuf, u32)> {
+        let needed = width.max(height);
+        let (flavor, size) = FLAVORS.iter().find(|(_, size)| *size >= needed)?;
+        let uri = file_uri(&fs::canonicalize(file).ok()?);
+        let name = format!("{:x}.png", md5::compute(uri.as_bytes()));
+        Some((self.root.join(flavor).join(name), *size))
+    }
+}
+
+// The signature and the chunks before the image data, which is where the text
+// chunks are, without reading the rest of the file.
+fn read_header(path: &Path) -> io::Result<Vec<u8>> {
+    // Larger chunks mean the file is not a thumbnail
+    const MAX_CHUNK: usize = 1024 * 1024;
+    let mut file = BufReader::new(fs::File::open(path)?);
+    let mut header = vec![0; 8];
+    file.read_exact(&mut header)?;
+    loop {
+        let mut chunk = [0; 8];
+        if file.read_exact(&mut chunk).is_err() || &chunk[4..] == b"IDAT" {
+            break;
+        }
+        let length = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize;
+        if length > MAX_CHUNK {
+            break;
+        }
+        header.extend_from_slice(&chunk);
+        // The data and the checksum
+        let start = header.len();
+        header.resize(start + length + 4, 0);
+        file.read_exact(&mut header[start..])?;
+    }
+    Ok(header)
+}
+
+// Thumbnails that are already gone, deleted by another program, count as
+// deleted.
+fn remove_thumbnail(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+struct CacheEntry {
+    path: PathBuf,
+    size: u64,
+    mtime: std::time::SystemTime,
+}
+
+// The properties of the original file that a thumbnail must match.
+struct Source {
+    uri: String,
+    mtime: u64,
+    size: u64,
+}
+
+impl Source {
+    fn new(file: &Path) -> GridResult<Source> {
+        let metadata = fs::metadata(file)?;
+        Ok(Source {
+            uri: file_uri(&fs::canonicalize(file)?),
+            mtime: metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs(),
+            size: metadata.len(),
+        })
+    }
+}
+
+/// Escapes the path the same way GLib's g_filename_to_uri does so that the
+/// hashes match the ones computed by other desktop programs.
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &b in path_bytes(path).iter() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => uri.push(b as char),
+            b'-' | b'_' | b'.' | b'~' | b'/' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*'
+            | b'+' | b',' | b';' | b'=' | b':' | b'@' => uri.push(b as char),
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+// The bytes of the path as the file system sees them, so that names which
+// are not valid UTF-8 are escaped as they are rather than replaced
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().into()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        std::borrow::Cow::Borrowed(path) => path.as_bytes().into(),
+        std::borrow::Cow::Owned(path) => path.into_bytes().into(),
+    }
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+#[cfg(unix)]
+fn create_private_file(path: &Path) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_file(path: &Path) -> std::io::Result<fs::File> {
+    fs::File::create(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_header_finds_text_after_long_chunks() {
+        let img = RgbaImage::new(4, 4);
+        let mut png = Vec::new();
+        PNGEncoder::new(&mut png)
+            .encode(&img, 4, 4, ColorType::RGBA(8))
+            .unwrap();
+        let uri = format!("file:///{}", "a".repeat(4000));
+        let png = png_text::insert_text(&png, &[("Thumb::URI", &uri), ("Software", SOFTWARE)]);
+        let path = std::env::temp_dir().join(format!("image_grid_test_{}.png", std::process::id()));
+        fs::write(&path, &png).unwrap();
+        let header = read_header(&path);
+        fs::remove_file(&path).unwrap();
+        let header = header.unwrap();
+        assert!(header.len() < png.len());
+        assert!(png_text::read_text(&header)
+            .iter()
+            .any(|(keyword, value)| keyword == "Software" && is_own(value)));
+    }
+
+    #[test]
+    fn remove_thumbnail_ignores_missing_files() {
+        let path = std::env::temp_dir().join("image_grid_test_missing.png");
+        assert!(remove_thumbnail(&path).is_ok());
+    }
+
+    #[test]
+    fn versions() {
+        assert!(is_own(&software()));
+        // Written before thumbnails had a version
+        assert!(is_own("image_grid"));
+        assert!(!is_own("image_grid_viewer 2"));
+        assert!(!is_own("GNOME::ThumbnailFactory"));
+    }
+
+    #[test]
+    fn file_uri_escapes() {
+        assert_eq!(
+            file_uri(Path::new("/home/me/a b/été#1.png")),
+            "file:///home/me/a%20b/%C3%A9t%C3%A9%231.png"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_uri_keeps_non_utf8_bytes() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let path = Path::new(OsStr::from_bytes(b"/tmp/\xe9t\xe9.png"));
+        assert_eq!(file_uri(path), "file:///tmp/%E9t%E9.png");
+    }
+}
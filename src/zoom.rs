@@ -0,0 +1,222 @@
+use crate::layout::Rect;
+
+const MIN_SCALE: f64 = 0.01;
+const MAX_SCALE: f64 = 64.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ZoomMode {
+    /// The whole image fits in the view.
+    Fit,
+    /// The image is as wide as the view and can be panned vertically.
+    FitWidth,
+    /// A fixed number of screen pixels per image pixel, 1.0 being actual size.
+    Scale(f64),
+}
+
+// Pure zoom and pan calculations for the fullscreen view, in the same spirit
+// as GridLayout. Sizes are [width, height] and positions are relative to the
+// top left corner of the view.
+//
+// The point of the image in the middle of the view is kept as a fraction of
+// the image size, so the same part stays in view when the window is resized
+// or when moving to another image of the same size.
+#[derive(Clone, Debug)]
+pub struct ZoomView {
+    mode: ZoomMode,
+    center: [f64; 2],
+}
+
+impl Default for ZoomView {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ZoomView {
+    pub fn new() -> ZoomView {
+        ZoomView {
+            mode: ZoomMode::Fit,
+            center: [0.5, 0.5],
+        }
+    }
+
+    pub fn mode(&self) -> ZoomMode {
+        self.mode
+    }
+
+    /// Switches to the mode centered on the image, or at its top when fitting
+    /// the width.
+    pub fn set_mode(&mut self, mode: ZoomMode) {
+        self.mode = mode;
+        self.center = match mode {
+            ZoomMode::FitWidth => [0.5, 0.0],
+            _ => [0.5, 0.5],
+        };
+    }
+
+    /// Screen pixels per image pixel.
+    pub fn scale(&self, image: [f64; 2], view: [f64; 2]) -> f64 {
+        if image[0] <= 0.0 || image[1] <= 0.0 {
+            return 1.0;
+        }
+        match self.mode {
+            ZoomMode::Fit => f64::min(view[0] / image[0], view[1] / image[1]),
+            ZoomMode::FitWidth => view[0] / image[0],
+            ZoomMode::Scale(scale) => scale,
+        }
+    }
+
+    /// Where the image is drawn. An image smaller than the view is centered
+    /// along that axis, a larger one always covers the view.
+    pub fn image_rect(&self, image: [f64; 2], view: [f64; 2]) -> Rect {
+        let scale = self.scale(image, view);
+        let size = [image[0] * scale, image[1] * scale];
+        let offset = |axis: usize| {
+            let offset = if size[axis] <= view[axis] {
+                (view[axis] - size[axis]) / 2.0
+            } else {
+                (view[axis] / 2.0 - self.center[axis] * size[axis])
+                    .clamp(view[axis] - size[axis], 0.0)
+            };
+            // Whole pixels keep image pixels aligned with the screen
+            offset.round()
+        };
+        [offset(0), offset(1), size[0], size[1]]
+    }
+
+    /// Moves the image by the given number of pixels, as far as it goes.
+    pub fn pan(&mut self, dx: f64, dy: f64, image: [f64; 2], view: [f64; 2]) {
+        let [x, y, w, h] = self.image_rect(image, view);
+        self.center_on([x + dx, y + dy], [w, h], view);
+        // Settle on what is actually shown so that panning past an edge does
+        // not have to be undone before the image moves again.
+        let [x, y, _w, _h] = self.image_rect(image, view);
+        self.center_on([x, y], [w, h], view);
+    }
+
+    /// Multiplies the scale by `factor`, keeping the image point under `point`
+    /// where it is. Passing through actual size stops there.
+    pub fn zoom_at(&mut self, factor: f64, point: [f64; 2], image: [f64; 2], view: [f64; 2]) {
+        let old_scale = self.scale(image, view);
+        let [x, y, _w, _h] = self.image_rect(image, view);
+        let mut scale = (old_scale * factor).clamp(MIN_SCALE, MAX_SCALE);
+        if (old_scale < 1.0 && scale > 1.0) || (old_scale > 1.0 && scale < 1.0) {
+            scale = 1.0;
+        }
+        // The image pixel under the point
+        let pixel = [(point[0] - x) / old_scale, (point[1] - y) / old_scale];
+        self.mode = ZoomMode::Scale(scale);
+        let size = [image[0] * scale, image[1] * scale];
+        self.center_on(
+            [point[0] - pixel[0] * scale, point[1] - pixel[1] * scale],
+            size,
+            view,
+        );
+        self.pan(0.0, 0.0, image, view);
+    }
+
+    // Sets the center for an image of the given on-screen size drawn at
+    // `origin`.
+    fn center_on(&mut self, origin: [f64; 2], size: [f64; 2], view: [f64; 2]) {
+        for axis in 0..2 {
+            if size[axis] > 0.0 {
+                self.center[axis] = (view[axis] / 2.0 - origin[axis]) / size[axis];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The image pixel drawn at `point`, to the nearest whole pixel as the
+    // image is placed on whole screen pixels
+    fn pixel_at(view: &ZoomView, point: [f64; 2], image: [f64; 2], size: [f64; 2]) -> [f64; 2] {
+        let [x, y, w, _h] = view.image_rect(image, size);
+        let scale = w / image[0];
+        [
+            ((point[0] - x) / scale).round(),
+            ((point[1] - y) / scale).round(),
+        ]
+    }
+
+    #[test]
+    fn image_rect() {
+        let mut zoom = ZoomView::new();
+        // Fit centers the image along the axis it does not fill
+        assert_eq!(
+            zoom.image_rect([200.0, 100.0], [100.0, 100.0]),
+            [0.0, 25.0, 100.0, 50.0]
+        );
+        zoom.set_mode(ZoomMode::Scale(1.0));
+        assert_eq!(
+            zoom.image_rect([400.0, 300.0], [100.0, 100.0]),
+            [-150.0, -100.0, 400.0, 300.0]
+        );
+        assert_eq!(
+            zoom.image_rect([50.0, 300.0], [100.0, 100.0]),
+            [25.0, -100.0, 50.0, 300.0]
+        );
+        // Fitting the width starts at the top
+        zoom.set_mode(ZoomMode::FitWidth);
+        assert_eq!(
+            zoom.image_rect([100.0, 400.0], [200.0, 200.0]),
+            [0.0, 0.0, 200.0, 800.0]
+        );
+        // An empty image is drawn at actual size
+        assert_eq!(zoom.scale([0.0, 10.0], [200.0, 200.0]), 1.0);
+    }
+
+    #[test]
+    fn pan_stops_at_the_edges() {
+        let image = [400.0, 300.0];
+        let view = [100.0, 100.0];
+        let mut zoom = ZoomView::new();
+        zoom.set_mode(ZoomMode::Scale(1.0));
+        zoom.pan(30.0, -20.0, image, view);
+        assert_eq!(zoom.image_rect(image, view), [-120.0, -120.0, 400.0, 300.0]);
+        zoom.pan(1000.0, -1000.0, image, view);
+        assert_eq!(zoom.image_rect(image, view), [0.0, -200.0, 400.0, 300.0]);
+        // Panning back moves the image right away
+        zoom.pan(-10.0, 10.0, image, view);
+        assert_eq!(zoom.image_rect(image, view), [-10.0, -190.0, 400.0, 300.0]);
+    }
+
+    #[test]
+    fn zoom_keeps_the_point_under_the_cursor() {
+        let image = [400.0, 400.0];
+        let view = [200.0, 200.0];
+        let point = [50.0, 50.0];
+        let mut zoom = ZoomView::new();
+        assert_eq!(pixel_at(&zoom, point, image, view), [100.0, 100.0]);
+        zoom.zoom_at(1.5, point, image, view);
+        assert_eq!(zoom.mode(), ZoomMode::Scale(0.75));
+        assert_eq!(zoom.image_rect(image, view), [-25.0, -25.0, 300.0, 300.0]);
+        assert_eq!(pixel_at(&zoom, point, image, view), [100.0, 100.0]);
+        zoom.zoom_at(1.2, point, image, view);
+        assert_eq!(pixel_at(&zoom, point, image, view), [100.0, 100.0]);
+        // Zooming out past the edge of the image keeps it covering the view
+        zoom.zoom_at(0.8, [200.0, 200.0], image, view);
+        assert_eq!(zoom.image_rect(image, view), [0.0, 0.0, 288.0, 288.0]);
+    }
+
+    #[test]
+    fn zoom_stops_at_actual_size() {
+        let image = [400.0, 400.0];
+        let view = [200.0, 200.0];
+        let point = [100.0, 100.0];
+        let mut zoom = ZoomView::new();
+        zoom.zoom_at(4.0, point, image, view);
+        assert_eq!(zoom.mode(), ZoomMode::Scale(1.0));
+        zoom.zoom_at(4.0, point, image, view);
+        assert_eq!(zoom.mode(), ZoomMode::Scale(4.0));
+        zoom.zoom_at(0.1, point, image, view);
+        assert_eq!(zoom.mode(), ZoomMode::Scale(1.0));
+        zoom.zoom_at(0.5, point, image, view);
+        assert_eq!(zoom.mode(), ZoomMode::Scale(0.5));
+        zoom.set_mode(ZoomMode::Scale(MAX_SCALE));
+        zoom.zoom_at(2.0, point, image, view);
+        assert_eq!(zoom.mode(), ZoomMode::Scale(MAX_SCALE));
+    }
+}